@@ -0,0 +1,83 @@
+//! Adaptive backoff for the background refresh
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// Tracks consecutive refresh failures and the interval they imply
+///
+/// Once `threshold` refreshes in a row have failed, the interval doubles with each
+/// further failure until it reaches `max`; the first success restores `base`.
+#[derive(Debug)]
+pub struct RefreshBackoff {
+    base: Duration,
+    max: Duration,
+    threshold: u32,
+    consecutive_failures: AtomicU32,
+}
+
+impl RefreshBackoff {
+    /// Create a backoff starting at the normal refresh interval
+    pub fn new(base: Duration, max: Duration, threshold: u32) -> Self {
+        Self {
+            base,
+            max: max.max(base),
+            threshold,
+            consecutive_failures: AtomicU32::new(0),
+        }
+    }
+
+    /// Record the result of a refresh and return the interval until the next one
+    pub fn record(&self, success: bool) -> Duration {
+        if success {
+            self.consecutive_failures.store(0, Ordering::Relaxed);
+        } else {
+            self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.interval()
+    }
+
+    /// Get the interval until the next refresh
+    pub fn interval(&self) -> Duration {
+        let failures = self.consecutive_failures();
+        if self.threshold == 0 || failures < self.threshold {
+            return self.base;
+        }
+        let doublings = (failures - self.threshold + 1).min(31);
+        self.base.saturating_mul(1 << doublings).min(self.max)
+    }
+
+    /// Get the number of refreshes that have failed in a row
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval_grows_after_threshold_and_resets_on_success() {
+        let backoff = RefreshBackoff::new(Duration::from_secs(300), Duration::from_secs(3600), 3);
+
+        assert_eq!(backoff.record(false), Duration::from_secs(300));
+        assert_eq!(backoff.record(false), Duration::from_secs(300));
+        assert_eq!(backoff.record(false), Duration::from_secs(600));
+        assert_eq!(backoff.record(false), Duration::from_secs(1200));
+        assert_eq!(backoff.record(false), Duration::from_secs(2400));
+        assert_eq!(backoff.record(false), Duration::from_secs(3600));
+        assert_eq!(backoff.record(false), Duration::from_secs(3600));
+        assert_eq!(backoff.consecutive_failures(), 7);
+
+        assert_eq!(backoff.record(true), Duration::from_secs(300));
+        assert_eq!(backoff.consecutive_failures(), 0);
+    }
+
+    #[test]
+    fn test_zero_threshold_disables_backoff() {
+        let backoff = RefreshBackoff::new(Duration::from_secs(300), Duration::from_secs(3600), 0);
+        for _ in 0..10 {
+            assert_eq!(backoff.record(false), Duration::from_secs(300));
+        }
+    }
+}
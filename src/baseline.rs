@@ -0,0 +1,146 @@
+//! Budget baseline comparison for the OpenStack Cost Dashboard
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::time::SystemTime;
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::models::ChartData;
+
+/// Actual versus planned cost for one service
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Variance {
+    pub service: String,
+    pub actual: f64,
+    pub planned: f64,
+    /// Actual minus planned
+    pub variance: f64,
+    /// Variance relative to the planned cost, `None` when nothing was planned
+    pub variance_percent: Option<f64>,
+}
+
+/// Compare chart data against planned per-service costs
+///
+/// Services present on only one side report 0 for the other.
+pub fn compute_variance(chart: &ChartData, planned: &HashMap<String, f64>) -> Vec<Variance> {
+    let actual: HashMap<&str, f64> = chart
+        .keys()
+        .iter()
+        .map(String::as_str)
+        .zip(chart.values.iter().copied())
+        .collect();
+    let services: BTreeSet<&str> = actual.keys().copied().chain(planned.keys().map(String::as_str)).collect();
+
+    services
+        .into_iter()
+        .map(|service| {
+            let actual = actual.get(service).copied().unwrap_or(0.0);
+            let planned = planned.get(service).copied().unwrap_or(0.0);
+            let variance = actual - planned;
+            Variance {
+                service: service.to_string(),
+                actual,
+                planned,
+                variance,
+                variance_percent: (planned != 0.0).then(|| variance / planned * 100.0),
+            }
+        })
+        .collect()
+}
+
+/// Planned costs loaded from a JSON `{ service: planned_cost }` file
+#[derive(Debug)]
+pub struct Baseline {
+    path: PathBuf,
+    state: RwLock<BaselineState>,
+}
+
+#[derive(Debug, Default)]
+struct BaselineState {
+    planned: HashMap<String, f64>,
+    modified: Option<SystemTime>,
+}
+
+impl Baseline {
+    /// Create a baseline backed by the given file; it is loaded on first use
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            state: RwLock::new(BaselineState::default()),
+        }
+    }
+
+    /// Get the planned costs, reloading the file if it changed since the last read
+    pub async fn planned(&self) -> HashMap<String, f64> {
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        {
+            let state = self.state.read().await;
+            if modified.is_none() || state.modified == modified {
+                return state.planned.clone();
+            }
+        }
+
+        let mut state = self.state.write().await;
+        let loaded = std::fs::read_to_string(&self.path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str::<HashMap<String, f64>>(&content).map_err(|e| e.to_string()));
+        match loaded {
+            Ok(planned) => {
+                info!("Loaded baseline for {} services from {}", planned.len(), self.path.display());
+                state.planned = planned;
+            }
+            Err(e) => warn!("Failed to load baseline file {}, keeping previous values: {}", self.path.display(), e),
+        }
+        state.modified = modified;
+        state.planned.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chart(rows: &[(&str, f64)]) -> ChartData {
+        let mut chart = ChartData::empty();
+        chart.labels = rows.iter().map(|(service, _)| service.to_string()).collect();
+        chart.values = rows.iter().map(|(_, cost)| *cost).collect();
+        chart
+    }
+
+    #[test]
+    fn test_variance_against_baseline() {
+        let planned = HashMap::from([("compute".to_string(), 100.0), ("network".to_string(), 10.0)]);
+        let variance = compute_variance(&chart(&[("compute", 120.0), ("storage", 5.0)]), &planned);
+
+        assert_eq!(variance.len(), 3);
+        assert_eq!(variance[0], Variance {
+            service: "compute".to_string(),
+            actual: 120.0,
+            planned: 100.0,
+            variance: 20.0,
+            variance_percent: Some(20.0),
+        });
+        assert_eq!(variance[1].service, "network");
+        assert_eq!(variance[1].actual, 0.0);
+        assert_eq!(variance[1].variance_percent, Some(-100.0));
+        assert_eq!(variance[2].service, "storage");
+        assert_eq!(variance[2].planned, 0.0);
+        assert_eq!(variance[2].variance_percent, None);
+    }
+
+    #[tokio::test]
+    async fn test_baseline_file_is_reloaded_on_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        std::fs::write(&path, r#"{"compute": 100.0}"#).unwrap();
+        let baseline = Baseline::new(&path);
+        assert_eq!(baseline.planned().await["compute"], 100.0);
+
+        std::fs::write(&path, r#"{"compute": 150.0}"#).unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert_eq!(baseline.planned().await["compute"], 150.0);
+    }
+}
@@ -0,0 +1,100 @@
+//! Spend burn-down over the fiscal month
+
+use chrono::{Datelike, Months, NaiveDate};
+use serde::Serialize;
+
+/// Spend of the current fiscal period and where it is heading
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Burndown {
+    /// First day of the period
+    pub period_start: NaiveDate,
+    /// Last day of the period
+    pub period_end: NaiveDate,
+    /// Whole days of the period before today
+    pub days_elapsed: i64,
+    /// Days of the period from today on
+    pub days_remaining: i64,
+    /// Spend over the elapsed days
+    pub current_spend: f64,
+    /// Current spend extrapolated over the whole period at the elapsed days' rate
+    pub projected_spend: f64,
+    /// Planned spend for the period, from `BASELINE_FILE`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<f64>,
+    /// Projected spend minus the budget: positive when heading over it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub projected_over_budget: Option<f64>,
+}
+
+/// First day of the fiscal period containing `today` and of the one after it
+///
+/// Periods begin on `start_day` of each month (1-28, so every month has it).
+pub fn fiscal_period(today: NaiveDate, start_day: u32) -> (NaiveDate, NaiveDate) {
+    let this_month = today.with_day(start_day).unwrap_or(today);
+    let start = if today.day() >= start_day {
+        this_month
+    } else {
+        this_month.checked_sub_months(Months::new(1)).unwrap_or(this_month)
+    };
+    let next = start.checked_add_months(Months::new(1)).unwrap_or(start);
+    (start, next)
+}
+
+/// Burn-down of the fiscal period containing `today`, given the spend of its elapsed days
+///
+/// On the first day of the period no day has elapsed, so nothing is extrapolated
+/// and the projection is the current spend.
+pub fn burndown(today: NaiveDate, start_day: u32, current_spend: f64, budget: Option<f64>) -> Burndown {
+    let (period_start, next_start) = fiscal_period(today, start_day);
+    let days_total = (next_start - period_start).num_days();
+    let days_elapsed = (today - period_start).num_days();
+    let projected_spend = if days_elapsed > 0 {
+        current_spend / days_elapsed as f64 * days_total as f64
+    } else {
+        current_spend
+    };
+    Burndown {
+        period_start,
+        period_end: next_start.pred_opt().unwrap_or(next_start),
+        days_elapsed,
+        days_remaining: days_total - days_elapsed,
+        current_spend,
+        projected_spend,
+        budget,
+        projected_over_budget: budget.map(|budget| projected_spend - budget),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_burndown_mid_period() {
+        let burndown = burndown(date("2025-03-20"), 15, 50.0, Some(300.0));
+        assert_eq!(burndown.period_start, date("2025-03-15"));
+        assert_eq!(burndown.period_end, date("2025-04-14"));
+        assert_eq!(burndown.days_elapsed, 5);
+        assert_eq!(burndown.days_remaining, 26);
+        assert_eq!(burndown.projected_spend, 310.0);
+        assert_eq!(burndown.projected_over_budget, Some(10.0));
+
+        // Before the start day, the period began last month
+        assert_eq!(fiscal_period(date("2025-03-10"), 15), (date("2025-02-15"), date("2025-03-15")));
+        assert_eq!(fiscal_period(date("2025-02-10"), 1), (date("2025-02-01"), date("2025-03-01")));
+    }
+
+    #[test]
+    fn test_first_day_of_period_is_not_extrapolated() {
+        let burndown = burndown(date("2025-03-15"), 15, 0.0, None);
+        assert_eq!(burndown.days_elapsed, 0);
+        assert_eq!(burndown.days_remaining, 31);
+        assert_eq!(burndown.projected_spend, 0.0);
+        assert!(burndown.projected_over_budget.is_none());
+        assert!(burndown.projected_spend.is_finite());
+    }
+}
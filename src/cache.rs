@@ -1,22 +1,51 @@
 //! Cache module for OpenStack data to reduce API requests
 
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
-use tracing::{info, debug};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, debug, warn};
 use sha2::{Sha256, Digest};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::models::RatingData;
+
+/// Version of the persisted entry layout; bump whenever `RatingData` changes shape
+pub const CACHE_SCHEMA_VERSION: u32 = 2;
+
+/// On-disk form of the cache written to `CACHE_FILE`
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedCache {
+    cache_schema_version: u32,
+    /// Entries are kept as raw JSON so a single outdated entry can be dropped on its own
+    entries: HashMap<String, serde_json::Value>,
+    /// Services that have incurred cost so far, for `ALERT_ON_NEW_SERVICE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seen_services: Option<BTreeSet<String>>,
+}
+
+/// On-disk form of a single cache entry
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedEntry {
+    cache_schema_version: u32,
+    data: RatingData,
+    /// Unix timestamp the entry was created at
+    created_at: u64,
+    ttl_seconds: u64,
+}
 
 /// Cache entry containing data and metadata
 #[derive(Debug, Clone)]
 pub struct CacheEntry {
-    pub data: HashMap<String, f64>,
+    pub data: RatingData,
     pub created_at: Instant,
     pub ttl: Duration,
 }
 
 impl CacheEntry {
     /// Create a new cache entry
-    pub fn new(data: HashMap<String, f64>, ttl: Duration) -> Self {
+    pub fn new(data: RatingData, ttl: Duration) -> Self {
         Self {
             data,
             created_at: Instant::now(),
@@ -42,6 +71,18 @@ pub struct OpenStackCache {
     cache: RwLock<HashMap<String, CacheEntry>>,
     /// Default TTL for cache entries
     default_ttl: Duration,
+    /// File the cache is persisted to and warmed up from, if any
+    file: Option<PathBuf>,
+    /// Only load the file, never write it (another instance owns it)
+    read_only: bool,
+    /// Services seen in the bill, `None` until the first refresh was observed
+    seen_services: RwLock<Option<BTreeSet<String>>>,
+    /// Lookups through `get` that found a valid entry
+    hits: AtomicU64,
+    /// Lookups through `get` that found no entry or an expired one
+    misses: AtomicU64,
+    /// Held from snapshot to rename so concurrent writes land in order
+    persist_lock: Mutex<()>,
 }
 
 impl OpenStackCache {
@@ -50,9 +91,137 @@ impl OpenStackCache {
         Self {
             cache: RwLock::new(HashMap::new()),
             default_ttl,
+            file: None,
+            read_only: false,
+            seen_services: RwLock::new(None),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            persist_lock: Mutex::new(()),
         }
     }
 
+    /// Persist the cache to `path` after every write
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Never write the cache file, only load it
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Warm the cache up from its file, returning how many entries were loaded
+    ///
+    /// Entries written with another schema version or that no longer match the
+    /// current layout are discarded, so a format change can't stop startup.
+    pub async fn load(&self) -> usize {
+        let Some(path) = &self.file else {
+            return 0;
+        };
+        let persisted = match tokio::fs::read_to_string(path).await {
+            Ok(content) => serde_json::from_str::<PersistedCache>(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return 0,
+            Err(e) => {
+                warn!("Failed to read cache file {}: {}", path.display(), e);
+                return 0;
+            }
+        };
+        let persisted = match persisted {
+            Ok(persisted) => persisted,
+            Err(e) => {
+                warn!("Ignoring unreadable cache file {}: {}", path.display(), e);
+                return 0;
+            }
+        };
+
+        if persisted.seen_services.is_some() {
+            *self.seen_services.write().await = persisted.seen_services;
+        }
+        let now = unix_now();
+        let total = persisted.entries.len();
+        let mut cache = self.cache.write().await;
+        for (key, value) in persisted.entries {
+            let entry = match serde_json::from_value::<PersistedEntry>(value) {
+                Ok(entry) if entry.cache_schema_version == CACHE_SCHEMA_VERSION => entry,
+                _ => continue,
+            };
+            let age = Duration::from_secs(now.saturating_sub(entry.created_at));
+            // Entries older than the monotonic clock are kept as expired for stale serving
+            let (created_at, ttl) = match Instant::now().checked_sub(age) {
+                Some(created_at) => (created_at, Duration::from_secs(entry.ttl_seconds)),
+                None => (Instant::now(), Duration::ZERO),
+            };
+            cache.insert(key, CacheEntry { data: entry.data, created_at, ttl });
+        }
+        let loaded = cache.len();
+        if loaded < total {
+            warn!("Discarded {} cache entries from {} with an outdated layout", total - loaded, path.display());
+        }
+        info!("Loaded {} cache entries from {}", loaded, path.display());
+        loaded
+    }
+
+    /// Write all entries to the cache file, if one is configured
+    async fn persist(&self) {
+        let Some(path) = self.file.as_ref().filter(|_| !self.read_only) else {
+            return;
+        };
+        // A snapshot taken after ours must not be overwritten by ours
+        let _guard = self.persist_lock.lock().await;
+        let now = unix_now();
+        let entries = {
+            let cache = self.cache.read().await;
+            cache
+                .iter()
+                .filter_map(|(key, entry)| {
+                    let persisted = PersistedEntry {
+                        cache_schema_version: CACHE_SCHEMA_VERSION,
+                        data: entry.data.clone(),
+                        created_at: now.saturating_sub(entry.created_at.elapsed().as_secs()),
+                        ttl_seconds: entry.ttl.as_secs(),
+                    };
+                    serde_json::to_value(persisted).ok().map(|value| (key.clone(), value))
+                })
+                .collect()
+        };
+        let persisted = PersistedCache {
+            cache_schema_version: CACHE_SCHEMA_VERSION,
+            entries,
+            seen_services: self.seen_services.read().await.clone(),
+        };
+        if let Err(e) = write_atomically(path, &persisted).await {
+            warn!("Failed to persist cache to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Record the services of a refresh and return those never seen before
+    ///
+    /// The first observation only initializes the set, so an empty history
+    /// doesn't report every existing service as new.
+    pub async fn observe_services<'a>(&self, services: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+        let (new_services, changed): (Vec<String>, bool) = {
+            let mut seen = self.seen_services.write().await;
+            match seen.as_mut() {
+                Some(seen) => {
+                    let new_services: Vec<String> =
+                        services.into_iter().filter(|service| seen.insert((*service).clone())).cloned().collect();
+                    let changed = !new_services.is_empty();
+                    (new_services, changed)
+                }
+                None => {
+                    *seen = Some(services.into_iter().cloned().collect());
+                    (Vec::new(), true)
+                }
+            }
+        };
+        if changed {
+            self.persist().await;
+        }
+        new_services
+    }
+
     /// Generate a cache key based on the command and parameters
     pub fn generate_key(&self, command: &str, params: &[String]) -> String {
         let mut hasher = Sha256::new();
@@ -64,35 +233,52 @@ impl OpenStackCache {
     }
 
     /// Get data from cache if available and valid
-    pub async fn get(&self, key: &str) -> Option<HashMap<String, f64>> {
+    pub async fn get(&self, key: &str) -> Option<RatingData> {
         let cache = self.cache.read().await;
         if let Some(entry) = cache.get(key) {
             if entry.is_valid() {
                 debug!("Cache hit for key: {}", key);
                 info!("Using cached data (TTL remaining: {:?})", entry.remaining_ttl());
+                self.hits.fetch_add(1, Ordering::Relaxed);
                 return Some(entry.data.clone());
             } else {
                 debug!("Cache entry expired for key: {}", key);
             }
         }
         debug!("Cache miss for key: {}", key);
+        self.misses.fetch_add(1, Ordering::Relaxed);
         None
     }
 
+    /// Cumulative hits and misses of `get` since startup
+    pub fn lookups(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+
+    /// Get data from cache even if the entry has expired
+    pub async fn get_expired(&self, key: &str) -> Option<RatingData> {
+        let cache = self.cache.read().await;
+        cache.get(key).map(|entry| entry.data.clone())
+    }
+
     /// Store data in cache
-    pub async fn set(&self, key: String, data: HashMap<String, f64>) {
+    pub async fn set(&self, key: String, data: RatingData) {
         let entry = CacheEntry::new(data, self.default_ttl);
         let mut cache = self.cache.write().await;
         cache.insert(key.clone(), entry);
+        drop(cache);
         info!("Cached data for key: {} (TTL: {:?})", key, self.default_ttl);
+        self.persist().await;
     }
 
     /// Store data in cache with custom TTL
-    pub async fn set_with_ttl(&self, key: String, data: HashMap<String, f64>, ttl: Duration) {
+    pub async fn set_with_ttl(&self, key: String, data: RatingData, ttl: Duration) {
         let entry = CacheEntry::new(data, ttl);
         let mut cache = self.cache.write().await;
         cache.insert(key.clone(), entry);
+        drop(cache);
         info!("Cached data for key: {} (TTL: {:?})", key, ttl);
+        self.persist().await;
     }
 
     /// Clear expired entries from cache
@@ -108,10 +294,14 @@ impl OpenStackCache {
 
     /// Clear all cache entries
     pub async fn clear(&self) {
-        let mut cache = self.cache.write().await;
-        let count = cache.len();
-        cache.clear();
+        let count = {
+            let mut cache = self.cache.write().await;
+            let count = cache.len();
+            cache.clear();
+            count
+        };
         info!("Cleared {} cache entries", count);
+        self.persist().await;
     }
 
     /// Get cache statistics
@@ -129,22 +319,63 @@ impl OpenStackCache {
             }
         }
 
+        let approx_bytes = cache.iter().map(|(key, entry)| key.len() + entry.data.approx_bytes()).sum();
+        let (hits, misses) = self.lookups();
         CacheStats {
             total_entries,
             valid_entries,
             expired_entries,
             default_ttl: self.default_ttl,
+            hits,
+            misses,
+            hit_ratio: hit_ratio(hits, misses),
+            approx_bytes,
         }
     }
 }
 
 /// Cache statistics
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct CacheStats {
     pub total_entries: usize,
     pub valid_entries: usize,
     pub expired_entries: usize,
+    #[serde(rename = "default_ttl_seconds", serialize_with = "serialize_secs")]
     pub default_ttl: Duration,
+    /// Cumulative lookups that found a valid entry
+    pub hits: u64,
+    /// Cumulative lookups that found no valid entry
+    pub misses: u64,
+    /// Share of lookups that hit, `0` before the first lookup
+    pub hit_ratio: f64,
+    /// Estimated memory held by the entries (see `RatingData::approx_bytes`)
+    pub approx_bytes: usize,
+}
+
+/// Share of `hits` among all lookups, `0` when there were none
+pub fn hit_ratio(hits: u64, misses: u64) -> f64 {
+    match hits + misses {
+        0 => 0.0,
+        lookups => hits as f64 / lookups as f64,
+    }
+}
+
+/// Seconds since the Unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Write the cache file through a temporary file so readers never see it half-written
+async fn write_atomically(path: &Path, persisted: &PersistedCache) -> std::io::Result<()> {
+    let json = serde_json::to_vec(persisted)?;
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+/// Serialize a duration as fractional seconds
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
 }
 
 impl Default for OpenStackCache {
@@ -152,3 +383,120 @@ impl Default for OpenStackCache {
         Self::new(Duration::from_secs(300)) // 5 minutes default TTL
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_discards_entries_with_another_schema_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let data = serde_json::json!({
+            "costs": { "compute": 5.0 },
+            "raw_costs": {},
+            "domain_costs": {},
+            "domain_raw_costs": {},
+            "by_endpoint": {},
+            "timeseries": []
+        });
+        let file = serde_json::json!({
+            "cache_schema_version": CACHE_SCHEMA_VERSION,
+            "entries": {
+                "current": {
+                    "cache_schema_version": CACHE_SCHEMA_VERSION,
+                    "data": data,
+                    "created_at": unix_now(),
+                    "ttl_seconds": 300
+                },
+                "outdated": {
+                    "cache_schema_version": CACHE_SCHEMA_VERSION + 1,
+                    "data": data,
+                    "created_at": unix_now(),
+                    "ttl_seconds": 300
+                }
+            }
+        });
+        std::fs::write(&path, file.to_string()).unwrap();
+
+        let cache = OpenStackCache::new(Duration::from_secs(300)).with_file(&path);
+        assert_eq!(cache.load().await, 1);
+        assert_eq!(cache.get("current").await.unwrap().costs["compute"], 5.0);
+        assert!(cache.get_expired("outdated").await.is_none());
+
+        // Writes rewrite the file without the discarded entry
+        cache.set("fresh".to_string(), RatingData::default()).await;
+        let reloaded = OpenStackCache::new(Duration::from_secs(300)).with_file(&path);
+        assert_eq!(reloaded.load().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writes_all_reach_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let cache = std::sync::Arc::new(OpenStackCache::new(Duration::from_secs(300)).with_file(&path));
+
+        let writes: Vec<_> = (0..16)
+            .map(|index| {
+                let cache = cache.clone();
+                tokio::spawn(async move { cache.set(format!("month-{}", index), RatingData::default()).await })
+            })
+            .collect();
+        for write in writes {
+            write.await.unwrap();
+        }
+
+        let reloaded = OpenStackCache::new(Duration::from_secs(300)).with_file(&path);
+        assert_eq!(reloaded.load().await, 16);
+    }
+
+    #[tokio::test]
+    async fn test_clear_empties_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let cache = OpenStackCache::new(Duration::from_secs(300)).with_file(&path);
+        cache.set("january".to_string(), RatingData::default()).await;
+        cache.clear().await;
+
+        let reloaded = OpenStackCache::new(Duration::from_secs(300)).with_file(&path);
+        assert_eq!(reloaded.load().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_lookups_count_hits_and_misses() {
+        let cache = OpenStackCache::new(Duration::from_secs(300));
+        assert_eq!(cache.stats().await.hit_ratio, 0.0);
+
+        assert!(cache.get("compute").await.is_none());
+        cache.set("compute".to_string(), RatingData::default()).await;
+        assert!(cache.get("compute").await.is_some());
+        assert!(cache.get("compute").await.is_some());
+        // Stale fallbacks are not lookups
+        cache.get_expired("compute").await;
+
+        let stats = cache.stats().await;
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert!((stats.hit_ratio - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_size_estimate_grows_with_cached_data() {
+        let cache = OpenStackCache::new(Duration::from_secs(300));
+        assert_eq!(cache.stats().await.approx_bytes, 0);
+
+        let mut rating = RatingData::default();
+        rating.costs.insert("compute".to_string(), 10.0);
+        cache.set("january".to_string(), rating.clone()).await;
+        let one_service = cache.stats().await.approx_bytes;
+        assert_eq!(one_service, "january".len() + "compute".len() + 8);
+
+        rating.costs.insert("storage".to_string(), 2.0);
+        rating.raw_costs.insert("storage".to_string(), 2.0);
+        cache.set("january".to_string(), rating.clone()).await;
+        let more_services = cache.stats().await.approx_bytes;
+        assert!(more_services > one_service);
+
+        cache.set("february".to_string(), rating).await;
+        assert!(cache.stats().await.approx_bytes > more_services);
+    }
+}
@@ -1,9 +1,77 @@
 //! Configuration management for the OpenStack Cost Dashboard
 
 use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
 use std::time::Duration;
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::currency_format::CurrencyFormat;
+use crate::rounding::RoundingMode;
+use crate::schedule::RefreshSchedule;
+use crate::sink::SinkKind;
 use tracing::{info, warn};
 
+/// Optional capabilities active at runtime, reported by `/api/info`
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FeatureFlags {
+    /// How rating data is obtained: the local CLI, or the CLI over `SSH_HOST`
+    pub data_backend: &'static str,
+    /// Where fetched data is cached: in memory only, or also in `CACHE_FILE`
+    pub cache_backend: &'static str,
+    /// Whether the server terminates TLS itself, which it never does (use a reverse proxy)
+    pub tls: bool,
+    pub auth: bool,
+    pub metrics: bool,
+    /// Grafana SimpleJSON datasource API
+    pub grafana: bool,
+    /// Cost-per-service trend at `/api/efficiency`
+    pub efficiency: bool,
+    pub anonymize_services: bool,
+    pub sampling: bool,
+}
+
+/// Attribute costs can additionally be split by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitBy {
+    /// Keystone domain (`domain_id` of each resource)
+    Domain,
+}
+
+impl FromStr for SplitBy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "domain" => Ok(SplitBy::Domain),
+            other => Err(format!("unknown split '{}' (expected domain)", other)),
+        }
+    }
+}
+
+/// Arguments the dashboard sets itself and that extra args must not override
+const RESERVED_ARGS: &[&str] = &[
+    "rating", "dataframes", "get", "-b", "-e", "-c", "-f", "--format", "--column",
+    "--os-auth-url", "--os-username", "--os-password", "--os-project-id",
+    "--os-region-name", "--os-user-domain-name", "--os-cloud", "--os-system-scope",
+];
+
+/// Characters that have no business in a CLI option and hint at injection attempts
+const SHELL_METACHARACTERS: &[char] = &[';', '|', '&', '$', '`', '<', '>', '(', ')', '{', '}', '\\', '\'', '"', '\n', '\r'];
+
+/// Configuration problems that prevent the dashboard from starting
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError(pub Vec<String>);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid configuration: {}", self.0.join("; "))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 /// Application configuration
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -11,12 +79,40 @@ pub struct Config {
     pub bind_address: String,
     /// Server port
     pub port: u16,
+    /// Admin listener bind address
+    pub admin_bind_address: String,
+    /// Separate port for metrics and admin endpoints (served on `port` when unset)
+    pub admin_port: Option<u16>,
     /// Data refresh interval in seconds
     pub refresh_interval: Duration,
+    /// Consecutive refresh failures before the refresh interval starts growing (0 disables backoff)
+    pub refresh_backoff_threshold: u32,
+    /// Longest interval the refresh backs off to
+    pub refresh_backoff_max: Duration,
+    /// Upper bound of the random delay added to each background refresh
+    pub refresh_jitter: Duration,
+    /// Cron expression the background refresh fires at instead of the fixed interval
+    pub refresh_cron: Option<String>,
+    /// Longest date range, in days, a single query may span
+    pub max_range_days: u32,
+    /// How long dataframe buckets are kept for `/api/rolling` (disabled when unset)
+    pub rolling_retention: Option<Duration>,
     /// Currency conversion rate (rating to currency)
     pub currency_rate: f64,
+    /// Use the rating as the cost without dividing by `currency_rate`
+    pub rating_is_currency: bool,
+    /// Additional currencies and their rates (rating to currency), in configured order
+    pub currency_rates: Vec<(String, f64)>,
     /// OpenStack CLI command name
     pub openstack_command: String,
+    /// Command run instead when `openstack_command` cannot be started
+    pub openstack_command_fallback: Option<String>,
+    /// Additional global options passed to the OpenStack CLI
+    pub openstack_extra_args: Vec<String>,
+    /// Command that rewrites the fetched cost map (JSON on stdin and stdout)
+    pub transform_command: Option<String>,
+    /// Maximum time the transform command may run
+    pub transform_timeout: Duration,
     /// OpenStack authentication URL
     pub os_auth_url: String,
     /// OpenStack username
@@ -25,26 +121,208 @@ pub struct Config {
     pub os_password: String,
     /// OpenStack project ID
     pub os_project_id: String,
+    /// Keystone system scope (`all`) used instead of a project scope, if any
+    pub os_system_scope: Option<String>,
     /// Openstack region name
     pub os_region_name: String,
     /// OpenStack user domain name
     pub os_user_domain_name: String,
+    /// Named clouds from `clouds.yaml` the dashboard can switch between
+    pub os_clouds: Vec<String>,
+    /// Named clouds of separate CloudKitty deployments whose costs are merged
+    pub rating_endpoints: Vec<String>,
+    /// Bearer token required by mutating API endpoints (disabled when unset)
+    pub api_token: Option<String>,
+    /// Bearer tokens limited to a list of services on the chart endpoints
+    pub token_scopes: Vec<(String, Vec<String>)>,
     /// Cache TTL in seconds
     pub cache_ttl_seconds: u64,
+    /// File the cache is persisted to and warmed up from at startup
+    pub cache_file: Option<String>,
+    /// Serve only what another instance persisted to `cache_file`, never running the CLI
+    pub read_only_replica: bool,
+    /// Divisor applied to displayed costs (e.g. 1000 to show thousands)
+    pub cost_scale: f64,
+    /// Unit shown next to scaled costs (e.g. "k$")
+    pub cost_unit_label: String,
+    /// ICU-style pattern formatted totals are written with (prefix label when unset)
+    pub currency_format: Option<CurrencyFormat>,
+    /// Decimal places costs are rounded to (no rounding when unset)
+    pub cost_precision: Option<u32>,
+    /// Rounding rule applied when `cost_precision` is set
+    pub rounding_mode: RoundingMode,
+    /// Fraction of dataframe rows processed per refresh (0.0 exclusive to 1.0)
+    pub sample_rate: f64,
+    /// Resource states counted in the costs, lowercased (all resources when empty)
+    pub resource_state_filter: Vec<String>,
+    /// Highest cost shown for a single service, in display units (no cap when unset)
+    pub service_cost_cap: Option<f64>,
+    /// Smallest change, in percent of the total or of a service, that replaces the chart data
+    pub min_change_percent: f64,
+    /// Accept HTTP/2 (cleartext prior knowledge) connections alongside HTTP/1.1
+    pub http2_enabled: bool,
+    /// Idle timeout for keep-alive connections (hyper defaults when unset)
+    pub keep_alive_timeout: Option<Duration>,
+    /// Exit after this long without HTTP requests (disabled when unset)
+    pub idle_shutdown: Option<Duration>,
+    /// Log one line per HTTP request with its status and latency
+    pub access_log: bool,
+    /// Query parameters whose values are masked in the access log
+    pub access_log_redact_params: Vec<String>,
+    /// Maximum number of HTTP connections open at once, across the public and admin listeners
+    pub max_connections: usize,
+    /// Replace service names with stable pseudonyms in responses
+    pub anonymize_services: bool,
+    /// Maximum number of characters in a chart label (no truncation when unset)
+    pub max_label_length: Option<usize>,
+    /// Keep the last raw CLI output for `/api/debug/last-output`
+    pub debug_capture_output: bool,
+    /// Serve the redacted command a fetch would run at `/api/debug/command`
+    pub debug_command_enabled: bool,
+    /// Add a `Server-Timing` header with the request's fetch, processing and total durations
+    pub server_timing: bool,
+    /// Reject CLI output that is not valid UTF-8 instead of replacing invalid bytes
+    pub strict_utf8: bool,
+    /// Retry with only the `Resources` column when the CLI output cannot be parsed
+    pub parse_fallback: bool,
+    /// Parse CLI output while it is read instead of buffering it first
+    pub streaming_parse: bool,
+    /// Log CLI error output at `warn` even when the command succeeds
+    pub warn_on_stderr: bool,
+    /// Refuse to start without a complete set of OpenStack credentials
+    pub require_credentials: bool,
+    /// Attribute costs are additionally split by, if any
+    pub split_by: Option<SplitBy>,
+    /// Categories services are rolled up into, in configured order
+    pub service_categories: Vec<(String, Vec<String>)>,
+    /// Regex whose first capture group names the group a service is totalled under
+    pub group_regex: Option<String>,
+    /// Title shown in the dashboard page and browser tab
+    pub dashboard_title: String,
+    /// Optional logo image URL shown above the title
+    pub dashboard_logo_url: Option<String>,
+    /// Colors the charts cycle through, as CSS hex colors
+    pub chart_palette: Vec<String>,
+    /// Version appended to frontend asset URLs so browsers refetch them after a deploy
+    pub asset_version: String,
+    /// TrueType font used for text in server-rendered chart images
+    pub chart_font: String,
+    /// Directory served under `/static` (disabled when unset)
+    pub static_dir: Option<String>,
+    /// Maximum number of snapshots kept in the history buffer
+    pub history_max_points: usize,
+    /// Ranges whose fetch timings are kept for `/api/stats/ranges`
+    pub range_stats_max: usize,
+    /// JSON lines file history snapshots are persisted to and restored from
+    pub history_file: Option<String>,
+    /// Size past which the history file is rotated
+    pub history_file_max_bytes: u64,
+    /// Number of recent snapshots included in each sparkline
+    pub sparkline_points: usize,
+    /// Directory of exported dataframe JSON files loaded once at startup
+    pub backfill_dir: Option<String>,
+    /// JSON file of planned per-service costs compared by `/api/variance`
+    pub baseline_file: Option<String>,
+    /// Content-Security-Policy header value for all responses
+    pub csp_policy: String,
+    /// X-Frame-Options header value for all responses
+    pub frame_options: String,
+    /// Number of times a failed OpenStack command is retried
+    pub fetch_retries: u32,
+    /// Delay between OpenStack command retries
+    pub fetch_retry_delay: Duration,
+    /// Serve the expired cached value when every fetch attempt fails
+    pub serve_expired_on_exhaustion: bool,
+    /// Most OpenStack commands run at once; further fetches wait for a free slot
+    pub max_concurrent_fetches: usize,
+    /// How long a fetch waits for a free slot before giving up
+    pub fetch_queue_timeout: Duration,
+    /// Expose Prometheus metrics at `/metrics`
+    pub metrics_enabled: bool,
+    /// Serve the Grafana SimpleJSON datasource API under `/grafana`
+    pub grafana_enabled: bool,
+    /// Serve the cost-per-service trend at `/api/efficiency`
+    pub efficiency_enabled: bool,
+    /// Reuse the chart built from a cache entry instead of processing it again
+    pub cache_processed_chart: bool,
+    /// Fetch only today live and the rest of the range from the cache
+    pub incremental_fetch: bool,
+    /// Assemble ranges from per-day fetches, keeping the totals of completed days
+    pub daily_aggregates: bool,
+    /// Fraction of rows that may lack a service or rating before the output counts as schema drift
+    pub schema_drift_threshold: f64,
+    /// Share of the total (0-1) above which a service is in the "major" bucket
+    pub major_share_threshold: f64,
+    /// Share of the total (0-1) above which a service is in the "moderate" bucket
+    pub moderate_share_threshold: f64,
+    /// Day of the month fiscal periods begin on (1-28)
+    pub fiscal_month_start_day: u32,
+    /// Tokio worker threads; 1 runs everything on the current thread
+    pub worker_threads: usize,
+    /// Host the OpenStack CLI is run on over SSH (run locally when unset)
+    pub ssh_host: Option<String>,
+    /// User to log in to `ssh_host` as (the SSH default when unset)
+    pub ssh_user: Option<String>,
+    /// Private key file for `ssh_host` (the SSH default when unset)
+    pub ssh_key: Option<String>,
+    /// Where successful refreshes are published
+    pub sink: SinkKind,
+    /// Connection URL of the sink (a path for the `file` sink)
+    pub sink_url: Option<String>,
+    /// Reject unknown query parameters on date-range endpoints instead of ignoring them
+    pub strict_query_params: bool,
+    /// Include pre-conversion ratings as `raw_values` in chart data
+    pub include_raw_values: bool,
+    /// Include the number of dataframes and resource rows fetched in `/api/data`
+    pub include_row_counts: bool,
+    /// Include how concentrated spend is among services in `/api/data`
+    pub include_concentration: bool,
+    /// Include per-service changes since the previous refresh in `/api/data`
+    pub include_deltas: bool,
+    /// Include the daily run-rate and monthly projection of the queried range in `/api/data`
+    pub include_run_rate: bool,
+    /// End queries at the last second of the end date so that day's charges are included
+    pub end_inclusive: bool,
+    /// Log an alert when a service incurs cost for the first time
+    pub alert_on_new_service: bool,
+    /// Time zone used for displayed timestamps (server local time when unset)
+    pub display_timezone: Option<Tz>,
+    /// strftime-style format for displayed timestamps
+    pub date_display_format: String,
 }
 
+/// Default CSP allowing the inline script/styles and the Chart.js CDN used by the template
+pub const DEFAULT_CSP_POLICY: &str = "default-src 'self'; script-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; connect-src 'self'; frame-ancestors 'none'";
+
+/// Default font for server-rendered charts, as installed by the DejaVu font packages
+pub const DEFAULT_CHART_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+
+/// Default chart colors, matching the bundled dashboard
+pub const DEFAULT_CHART_PALETTE: &[&str] = &[
+    "#3498db", "#e74c3c", "#2ecc71", "#f39c12", "#9b59b6",
+    "#1abc9c", "#34495e", "#e67e22", "#95a5a6", "#f1c40f",
+];
+
 impl Config {
     /// Create a new configuration with defaults and environment overrides
     pub fn new() -> Self {
         info!("Loading configuration from environment variables...");
-        
-        let bind_address = env::var("BIND_ADDRESS").unwrap_or_else(|_| {
+        Self::from_source(|name| env::var(name).ok())
+    }
+
+    /// Create a configuration from an arbitrary variable source
+    pub fn from_source<F>(get: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let bind_address = get("BIND_ADDRESS").unwrap_or_else(|| {
             info!("Using default BIND_ADDRESS: 0.0.0.0");
             "0.0.0.0".to_string()
         });
         
-        let port = env::var("PORT")
-            .unwrap_or_else(|_| {
+        let port = get("PORT")
+            .unwrap_or_else(|| {
                 info!("Using default PORT: 3001");
                 "3001".to_string()
             })
@@ -54,8 +332,8 @@ impl Config {
                 3001
             });
         
-        let refresh_interval_secs = env::var("REFRESH_INTERVAL_SECONDS")
-            .unwrap_or_else(|_| {
+        let refresh_interval_secs = get("REFRESH_INTERVAL_SECONDS")
+            .unwrap_or_else(|| {
                 info!("Using default REFRESH_INTERVAL_SECONDS: 300");
                 "300".to_string()
             })
@@ -65,8 +343,24 @@ impl Config {
                 300
             });
         
-        let currency_rate = env::var("CURRENCY_RATE")
-            .unwrap_or_else(|_| {
+        let refresh_jitter_secs = parse_var(&get, "REFRESH_JITTER_SECONDS", 0_u64);
+        let refresh_cron = optional_var(&get, "REFRESH_CRON");
+        let max_range_days = parse_var(&get, "MAX_RANGE_DAYS", 366_u32);
+        let rolling_retention = optional_var(&get, "ROLLING_RETENTION_HOURS").and_then(|value| {
+            value.parse::<u64>()
+                .ok()
+                .filter(|hours| *hours > 0)
+                .map(|hours| Duration::from_secs(hours * 3600))
+                .or_else(|| {
+                    warn!("Invalid ROLLING_RETENTION_HOURS value '{}', rolling totals are disabled", value);
+                    None
+                })
+        });
+        let refresh_backoff_threshold = parse_var(&get, "REFRESH_BACKOFF_THRESHOLD", 0_u32);
+        let refresh_backoff_max_secs = parse_var(&get, "REFRESH_BACKOFF_MAX_SECONDS", 3600_u64);
+        
+        let currency_rate = get("CURRENCY_RATE")
+            .unwrap_or_else(|| {
                 info!("Using default CURRENCY_RATE: 55.5");
                 "55.5".to_string()
             })
@@ -76,44 +370,109 @@ impl Config {
                 55.5
             });
         
-        let openstack_command = env::var("OPENSTACK_COMMAND").unwrap_or_else(|_| {
+        let rating_is_currency = flag_var(&get, "RATING_IS_CURRENCY", false);
+        let currency_rate = if rating_is_currency {
+            currency_rate
+        } else {
+            let min_sane = parse_var(&get, "CURRENCY_RATE_MIN_SANE", 0.01_f64);
+            let max_sane = parse_var(&get, "CURRENCY_RATE_MAX_SANE", 10_000_f64);
+            let auto_invert = flag_var(&get, "AUTO_INVERT_SUSPICIOUS_RATE", false);
+            let (checked, warnings) = check_currency_rate(currency_rate, min_sane, max_sane, auto_invert);
+            for warning in warnings {
+                warn!("{}", warning);
+            }
+            checked
+        };
+        
+        let currency_rates = get("CURRENCY_RATES")
+            .map(|value| parse_currency_rates(&value))
+            .unwrap_or_default();
+        
+        let rating_endpoints: Vec<String> = get("RATING_ENDPOINTS")
+            .map(|value| {
+                value.split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        
+        let openstack_command = get("OPENSTACK_COMMAND").unwrap_or_else(|| {
             info!("Using default OPENSTACK_COMMAND: openstack");
             "openstack".to_string()
         });
         
+        let openstack_command_fallback = optional_var(&get, "OPENSTACK_COMMAND_FALLBACK");
+        
+        let openstack_extra_args: Vec<String> = get("OPENSTACK_EXTRA_ARGS")
+            .map(|value| value.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default();
+        
+        let transform_command = optional_var(&get, "TRANSFORM_COMMAND");
+        let transform_timeout_secs = parse_var(&get, "TRANSFORM_TIMEOUT_SECONDS", 10_u64);
+        
         // Load OpenStack authentication variables
-        let os_auth_url = env::var("OS_AUTH_URL").unwrap_or_else(|_| {
+        let os_auth_url = get("OS_AUTH_URL").unwrap_or_else(|| {
             warn!("OS_AUTH_URL not set - OpenStack authentication may fail");
             String::new()
         });
+        let os_auth_url_append_v3 = flag_var(&get, "OS_AUTH_URL_APPEND_V3", true);
+        // Invalid URLs are kept as given and reported by `validate`
+        let os_auth_url = match normalize_auth_url(&os_auth_url, os_auth_url_append_v3) {
+            Ok(normalized) if normalized != os_auth_url => {
+                info!("Normalized OS_AUTH_URL from '{}' to '{}'", os_auth_url, normalized);
+                normalized
+            }
+            _ => os_auth_url,
+        };
         
-        let os_username = env::var("OS_USERNAME").unwrap_or_else(|_| {
+        let os_username = get("OS_USERNAME").unwrap_or_else(|| {
             warn!("OS_USERNAME not set - OpenStack authentication may fail");
             String::new()
         });
         
-        let os_password = env::var("OS_PASSWORD").unwrap_or_else(|_| {
+        let os_password = get("OS_PASSWORD").unwrap_or_else(|| {
             warn!("OS_PASSWORD not set - OpenStack authentication may fail");
             String::new()
         });
         
-        let os_project_id = env::var("OS_PROJECT_ID").unwrap_or_else(|_| {
-            warn!("OS_PROJECT_ID not set - OpenStack authentication may fail");
+        let os_system_scope = optional_var(&get, "OS_SYSTEM_SCOPE");
+        let os_project_id = get("OS_PROJECT_ID").unwrap_or_else(|| {
+            // A system-scoped token covers every project
+            if os_system_scope.is_none() {
+                warn!("OS_PROJECT_ID not set - OpenStack authentication may fail");
+            }
             String::new()
         });
         
-        let os_region_name = env::var("OS_REGION_NAME").unwrap_or_else(|_| {
+        let os_region_name = get("OS_REGION_NAME").unwrap_or_else(|| {
             warn!("OS_REGION_NAME not set - OpenStack data may not be useful");
             String::new()
         });
         
-        let os_user_domain_name = env::var("OS_USER_DOMAIN_NAME").unwrap_or_else(|_| {
+        let os_user_domain_name = get("OS_USER_DOMAIN_NAME").unwrap_or_else(|| {
             info!("Using default OS_USER_DOMAIN_NAME: Default");
             "Default".to_string()
         });
         
-        let cache_ttl_seconds = env::var("CACHE_TTL_SECONDS")
-            .unwrap_or_else(|_| {
+        let os_clouds: Vec<String> = get("OS_CLOUDS")
+            .map(|value| {
+                value.split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        
+        let api_token = optional_var(&get, "API_TOKEN");
+        let token_scopes = get("TOKEN_SCOPES")
+            .map(|value| parse_token_scopes(&value))
+            .unwrap_or_default();
+        
+        let cache_ttl_seconds = get("CACHE_TTL_SECONDS")
+            .unwrap_or_else(|| {
                 info!("Using default CACHE_TTL_SECONDS: 1800");
                 "1800".to_string()
             })
@@ -123,47 +482,886 @@ impl Config {
                 1800
             });
         
+        let mut cost_scale = parse_var(&get, "COST_SCALE", 1.0_f64);
+        if !(cost_scale.is_finite() && cost_scale > 0.0) {
+            warn!("COST_SCALE must be a positive number, using default 1.0: got {}", cost_scale);
+            cost_scale = 1.0;
+        }
+        let cost_unit_label = get("COST_UNIT_LABEL").unwrap_or_else(|| "€".to_string());
+        let currency_format = optional_var(&get, "CURRENCY_FORMAT_PATTERN").and_then(|value| {
+            value.parse::<CurrencyFormat>()
+                .map_err(|e| warn!("Invalid CURRENCY_FORMAT_PATTERN value, prefixing COST_UNIT_LABEL instead: {}", e))
+                .ok()
+        });
+        
+        let cost_precision = optional_var(&get, "COST_PRECISION").and_then(|value| {
+            value.parse::<u32>()
+                .map_err(|e| warn!("Invalid COST_PRECISION value, costs will not be rounded: {}", e))
+                .ok()
+        });
+        let rounding_mode = parse_var(&get, "ROUNDING_MODE", RoundingMode::HalfUp);
+        
+        let mut sample_rate = parse_var(&get, "SAMPLE_RATE", 1.0_f64);
+        if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+            warn!("SAMPLE_RATE must be in (0.0, 1.0], using default 1.0: got {}", sample_rate);
+            sample_rate = 1.0;
+        }
+        let resource_state_filter: Vec<String> = get("RESOURCE_STATE_FILTER")
+            .unwrap_or_default()
+            .split(',')
+            .map(|state| state.trim().to_ascii_lowercase())
+            .filter(|state| !state.is_empty())
+            .collect();
+        let service_cost_cap = optional_var(&get, "SERVICE_COST_CAP").and_then(|value| {
+            value.parse::<f64>()
+                .ok()
+                .filter(|cap| *cap > 0.0 && cap.is_finite())
+                .or_else(|| {
+                    warn!("Invalid SERVICE_COST_CAP value '{}', service costs are not capped", value);
+                    None
+                })
+        });
+        let mut min_change_percent = parse_var(&get, "MIN_CHANGE_PERCENT", 0.0_f64);
+        if !(min_change_percent >= 0.0 && min_change_percent.is_finite()) {
+            warn!("MIN_CHANGE_PERCENT must be a positive number, using default 0: got {}", min_change_percent);
+            min_change_percent = 0.0;
+        }
+        
+        let access_log = flag_var(&get, "ACCESS_LOG", true);
+        let access_log_redact_params: Vec<String> = get("ACCESS_LOG_REDACT_PARAMS")
+            .unwrap_or_else(|| "token,password,secret,api_key".to_string())
+            .split(',')
+            .map(|param| param.trim().to_ascii_lowercase())
+            .filter(|param| !param.is_empty())
+            .collect();
+        
+        let http2_enabled = flag_var(&get, "HTTP2_ENABLED", false);
+        let keep_alive_timeout = optional_var(&get, "KEEP_ALIVE_TIMEOUT_SECONDS").and_then(|value| {
+            value.parse::<u64>()
+                .map(Duration::from_secs)
+                .map_err(|e| warn!("Invalid KEEP_ALIVE_TIMEOUT_SECONDS value, using server defaults: {}", e))
+                .ok()
+        });
+        
+        let idle_shutdown = optional_var(&get, "IDLE_SHUTDOWN_SECONDS").and_then(|value| {
+            value.parse::<u64>()
+                .ok()
+                .filter(|seconds| *seconds > 0)
+                .map(Duration::from_secs)
+                .or_else(|| {
+                    warn!("Invalid IDLE_SHUTDOWN_SECONDS value '{}', idle shutdown is disabled", value);
+                    None
+                })
+        });
+        
+        let mut max_connections = parse_var(&get, "MAX_CONNECTIONS", 1024_usize);
+        if max_connections == 0 {
+            warn!("MAX_CONNECTIONS must be at least 1, using default 1024");
+            max_connections = 1024;
+        }
+        
+        let anonymize_services = flag_var(&get, "ANONYMIZE_SERVICES", false);
+        let max_label_length = optional_var(&get, "MAX_LABEL_LENGTH").and_then(|value| {
+            value.parse::<usize>()
+                .ok()
+                .filter(|length| *length > 0)
+                .or_else(|| {
+                    warn!("MAX_LABEL_LENGTH must be a positive integer, labels will not be truncated: got {}", value);
+                    None
+                })
+        });
+        let debug_capture_output = flag_var(&get, "DEBUG_CAPTURE_OUTPUT", false);
+        let debug_command_enabled = flag_var(&get, "DEBUG_COMMAND_ENABLED", false);
+        let server_timing = flag_var(&get, "SERVER_TIMING", false);
+        let strict_utf8 = flag_var(&get, "STRICT_UTF8", false);
+        let parse_fallback = flag_var(&get, "PARSE_FALLBACK", true);
+        let streaming_parse = flag_var(&get, "STREAMING_PARSE", false);
+        let warn_on_stderr = flag_var(&get, "WARN_ON_STDERR", false);
+        let require_credentials = flag_var(&get, "REQUIRE_CREDENTIALS", false);
+        let split_by = optional_var(&get, "SPLIT_BY").and_then(|value| {
+            value.parse::<SplitBy>()
+                .map_err(|e| warn!("Invalid SPLIT_BY value, costs will not be split: {}", e))
+                .ok()
+        });
+        let service_categories = get("SERVICE_CATEGORIES")
+            .map(|value| parse_categories(&value))
+            .unwrap_or_default();
+        let group_regex = optional_var(&get, "GROUP_REGEX");
+        
+        let dashboard_title = get("DASHBOARD_TITLE").unwrap_or_else(|| {
+            info!("Using default DASHBOARD_TITLE: OpenStack Cost Dashboard");
+            "OpenStack Cost Dashboard".to_string()
+        });
+        
+        let dashboard_logo_url = optional_var(&get, "DASHBOARD_LOGO_URL");
+        let chart_palette = get("CHART_PALETTE")
+            .map(|value| parse_palette(&value))
+            .filter(|palette| !palette.is_empty())
+            .unwrap_or_else(|| DEFAULT_CHART_PALETTE.iter().map(|color| color.to_string()).collect());
+        let asset_version = get("ASSET_VERSION")
+            .unwrap_or_else(|| format!("{}-{}", env!("CARGO_PKG_VERSION"), env!("GIT_COMMIT")));
+        let chart_font = get("CHART_FONT").unwrap_or_else(|| DEFAULT_CHART_FONT.to_string());
+        let static_dir = optional_var(&get, "STATIC_DIR");
+        
+        let history_max_points = parse_var(&get, "HISTORY_MAX_POINTS", 288_usize);
+        let range_stats_max = parse_var(&get, "RANGE_STATS_MAX", crate::metrics::DEFAULT_MAX_RANGES);
+        let history_file = optional_var(&get, "HISTORY_FILE");
+        let history_file_max_bytes = parse_var(&get, "HISTORY_FILE_MAX_BYTES", 1_048_576_u64);
+        let sparkline_points = parse_var(&get, "SPARKLINE_POINTS", 24_usize);
+        let backfill_dir = optional_var(&get, "BACKFILL_DIR");
+        let baseline_file = optional_var(&get, "BASELINE_FILE");
+        let cache_file = optional_var(&get, "CACHE_FILE");
+        let read_only_replica = flag_var(&get, "READ_ONLY_REPLICA", false);
+        
+        let csp_policy = get("CSP_POLICY").unwrap_or_else(|| DEFAULT_CSP_POLICY.to_string());
+        let frame_options = get("X_FRAME_OPTIONS").unwrap_or_else(|| "DENY".to_string());
+        
+        let fetch_retries = parse_var(&get, "FETCH_RETRIES", 0_u32);
+        let fetch_retry_delay_ms = parse_var(&get, "FETCH_RETRY_DELAY_MS", 1000_u64);
+        let serve_expired_on_exhaustion = flag_var(&get, "SERVE_EXPIRED_ON_EXHAUSTION", true);
+        let mut max_concurrent_fetches = parse_var(&get, "MAX_CONCURRENT_FETCHES", 4_usize);
+        if max_concurrent_fetches == 0 {
+            warn!("MAX_CONCURRENT_FETCHES must be at least 1, using default 4");
+            max_concurrent_fetches = 4;
+        }
+        let fetch_queue_timeout_ms = parse_var(&get, "FETCH_QUEUE_TIMEOUT_MS", 30_000_u64);
+        
+        let metrics_enabled = flag_var(&get, "METRICS_ENABLED", true);
+        let grafana_enabled = flag_var(&get, "GRAFANA_ENABLED", false);
+        let efficiency_enabled = flag_var(&get, "EFFICIENCY_ENABLED", false);
+        let cache_processed_chart = flag_var(&get, "CACHE_PROCESSED_CHART", false);
+        let incremental_fetch = flag_var(&get, "INCREMENTAL_FETCH", false);
+        let daily_aggregates = flag_var(&get, "DAILY_AGGREGATES", false);
+        let mut worker_threads = parse_var(&get, "TOKIO_WORKER_THREADS", 2_usize);
+        if worker_threads == 0 {
+            warn!("TOKIO_WORKER_THREADS must be at least 1, using default 2");
+            worker_threads = 2;
+        }
+        let ssh_host = optional_var(&get, "SSH_HOST");
+        let ssh_user = optional_var(&get, "SSH_USER");
+        let ssh_key = optional_var(&get, "SSH_KEY");
+        let mut fiscal_month_start_day = parse_var(&get, "FISCAL_MONTH_START_DAY", 1_u32);
+        if !(1..=28).contains(&fiscal_month_start_day) {
+            warn!("FISCAL_MONTH_START_DAY must be between 1 and 28, using default 1");
+            fiscal_month_start_day = 1;
+        }
+        let mut schema_drift_threshold = parse_var(&get, "SCHEMA_DRIFT_THRESHOLD", 0.1_f64);
+        if !(0.0..=1.0).contains(&schema_drift_threshold) {
+            warn!("SCHEMA_DRIFT_THRESHOLD must be between 0 and 1, using default 0.1");
+            schema_drift_threshold = 0.1;
+        }
+        let mut major_share_threshold = parse_var(&get, "MAJOR_SHARE_THRESHOLD", 0.1_f64);
+        if !(0.0..=1.0).contains(&major_share_threshold) {
+            warn!("MAJOR_SHARE_THRESHOLD must be between 0 and 1, using default 0.1");
+            major_share_threshold = 0.1;
+        }
+        let mut moderate_share_threshold = parse_var(&get, "MODERATE_SHARE_THRESHOLD", 0.02_f64);
+        if !(0.0..=1.0).contains(&moderate_share_threshold) {
+            warn!("MODERATE_SHARE_THRESHOLD must be between 0 and 1, using default 0.02");
+            moderate_share_threshold = 0.02;
+        }
+        let sink = parse_var(&get, "SINK", SinkKind::None);
+        let sink_url = optional_var(&get, "SINK_URL");
+        let strict_query_params = flag_var(&get, "STRICT_QUERY_PARAMS", false);
+        
+        let include_raw_values = flag_var(&get, "INCLUDE_RAW_VALUES", false);
+        let include_deltas = flag_var(&get, "INCLUDE_DELTAS", false);
+        let include_row_counts = flag_var(&get, "INCLUDE_ROW_COUNTS", false);
+        let include_concentration = flag_var(&get, "INCLUDE_CONCENTRATION", false);
+        let include_run_rate = flag_var(&get, "INCLUDE_RUN_RATE", true);
+        let end_inclusive = flag_var(&get, "END_INCLUSIVE", true);
+        let alert_on_new_service = flag_var(&get, "ALERT_ON_NEW_SERVICE", false);
+        
+        let display_timezone = optional_var(&get, "DISPLAY_TIMEZONE").and_then(|name| {
+            name.parse::<Tz>()
+                .map_err(|e| warn!("Invalid DISPLAY_TIMEZONE value, using server local time: {}", e))
+                .ok()
+        });
+        
+        // With an explicit zone the offset is shown so distributed readers know which one
+        let default_date_format = if display_timezone.is_some() {
+            "%Y-%m-%d %H:%M:%S %:z"
+        } else {
+            "%Y-%m-%d %H:%M:%S"
+        };
+        let date_display_format = get("DATE_DISPLAY_FORMAT").unwrap_or_else(|| default_date_format.to_string());
+        
+        let admin_bind_address = get("ADMIN_BIND_ADDRESS").unwrap_or_else(|| bind_address.clone());
+        let admin_port = optional_var(&get, "ADMIN_PORT").and_then(|value| {
+            value.parse::<u16>()
+                .map_err(|e| warn!("Invalid ADMIN_PORT value, serving admin endpoints on the main port: {}", e))
+                .ok()
+        });
+        
         let config = Self {
             bind_address,
             port,
+            admin_bind_address,
+            admin_port,
             refresh_interval: Duration::from_secs(refresh_interval_secs),
+            refresh_jitter: Duration::from_secs(refresh_jitter_secs),
+            refresh_cron,
+            max_range_days,
+            rolling_retention,
+            refresh_backoff_threshold,
+            refresh_backoff_max: Duration::from_secs(refresh_backoff_max_secs),
             currency_rate,
+            rating_is_currency,
+            currency_rates,
             openstack_command,
+            openstack_command_fallback,
+            openstack_extra_args,
+            transform_command,
+            transform_timeout: Duration::from_secs(transform_timeout_secs),
             os_auth_url,
             os_username,
             os_password,
             os_project_id,
+            os_system_scope,
             os_region_name,
             os_user_domain_name,
+            os_clouds,
+            rating_endpoints,
+            api_token,
+            token_scopes,
             cache_ttl_seconds,
+            cache_file,
+            read_only_replica,
+            cost_scale,
+            cost_unit_label,
+            currency_format,
+            cost_precision,
+            rounding_mode,
+            sample_rate,
+            resource_state_filter,
+            service_cost_cap,
+            min_change_percent,
+            http2_enabled,
+            keep_alive_timeout,
+            idle_shutdown,
+            access_log,
+            access_log_redact_params,
+            max_connections,
+            anonymize_services,
+            max_label_length,
+            debug_capture_output,
+            debug_command_enabled,
+            server_timing,
+            strict_utf8,
+            parse_fallback,
+            streaming_parse,
+            warn_on_stderr,
+            require_credentials,
+            split_by,
+            service_categories,
+            group_regex,
+            dashboard_title,
+            dashboard_logo_url,
+            chart_palette,
+            asset_version,
+            chart_font,
+            static_dir,
+            history_max_points,
+            range_stats_max,
+            history_file,
+            history_file_max_bytes,
+            sparkline_points,
+            backfill_dir,
+            baseline_file,
+            csp_policy,
+            frame_options,
+            fetch_retries,
+            fetch_retry_delay: Duration::from_millis(fetch_retry_delay_ms),
+            serve_expired_on_exhaustion,
+            max_concurrent_fetches,
+            fetch_queue_timeout: Duration::from_millis(fetch_queue_timeout_ms),
+            metrics_enabled,
+            grafana_enabled,
+            efficiency_enabled,
+            cache_processed_chart,
+            incremental_fetch,
+            daily_aggregates,
+            schema_drift_threshold,
+            major_share_threshold,
+            moderate_share_threshold,
+            fiscal_month_start_day,
+            worker_threads,
+            ssh_host,
+            ssh_user,
+            ssh_key,
+            sink,
+            sink_url,
+            strict_query_params,
+            include_raw_values,
+            include_deltas,
+            include_row_counts,
+            include_concentration,
+            include_run_rate,
+            end_inclusive,
+            alert_on_new_service,
+            display_timezone,
+            date_display_format,
         };
         
         info!("Configuration loaded successfully:");
         info!("  Server: {}", config.server_address());
+        if let Some(admin_address) = config.admin_address() {
+            info!("  Admin server: {}", admin_address);
+        }
+        info!("  Max open connections: {}", config.max_connections);
         info!("  Refresh interval: {}s", refresh_interval_secs);
-        info!("  Currency rate: {}", config.currency_rate);
+        if config.rating_is_currency {
+            info!("  Rating is already in the display currency");
+        } else {
+            info!("  Currency rate: {}", config.currency_rate);
+        }
         info!("  OpenStack command: {}", config.openstack_command);
+        if config.anonymize_services {
+            info!("  Service names are anonymized");
+        }
+        if config.sample_rate < 1.0 {
+            info!("  Sampling {:.0}% of dataframe rows", config.sample_rate * 100.0);
+        }
         
         config
     }
 
     /// Get the full server bind address
     pub fn server_address(&self) -> String {
-        format!("{}:{}", self.bind_address, self.port)
+        host_port(&self.bind_address, self.port)
+    }
+
+    /// Check the configuration for values that must prevent startup
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        for arg in &self.openstack_extra_args {
+            if arg.contains(SHELL_METACHARACTERS) {
+                problems.push(format!("OPENSTACK_EXTRA_ARGS contains shell metacharacters: {}", arg));
+            }
+            let flag = arg.split('=').next().unwrap_or(arg);
+            if RESERVED_ARGS.contains(&flag) {
+                problems.push(format!("OPENSTACK_EXTRA_ARGS must not override {}", flag));
+            }
+        }
+
+        if self.require_credentials {
+            let missing = self.missing_credentials();
+            if !missing.is_empty() {
+                problems.push(format!(
+                    "REQUIRE_CREDENTIALS is set but no credentials are complete: password authentication is missing {} \
+                     (or name clouds.yaml entries in OS_CLOUDS or RATING_ENDPOINTS)",
+                    missing.join(", ")
+                ));
+            }
+        }
+
+        if let Some(dir) = &self.static_dir
+            && !std::path::Path::new(dir).is_dir()
+        {
+            problems.push(format!("STATIC_DIR is not a directory: {}", dir));
+        }
+
+        if self.moderate_share_threshold > self.major_share_threshold {
+            problems.push(format!(
+                "MODERATE_SHARE_THRESHOLD ({}) must not exceed MAJOR_SHARE_THRESHOLD ({})",
+                self.moderate_share_threshold, self.major_share_threshold
+            ));
+        }
+
+        if self.read_only_replica && self.cache_file.is_none() {
+            problems.push("READ_ONLY_REPLICA requires CACHE_FILE, the cache the primary persists".to_string());
+        }
+
+        if let Some(pattern) = &self.group_regex {
+            match regex::Regex::new(pattern) {
+                Ok(regex) if regex.captures_len() < 2 => {
+                    problems.push(format!("GROUP_REGEX needs a capture group: {}", pattern));
+                }
+                Ok(_) => {}
+                Err(e) => problems.push(format!("GROUP_REGEX is invalid: {}", e)),
+            }
+        }
+
+        // Keystone only knows the `all` system scope
+        if let Some(scope) = &self.os_system_scope
+            && scope != "all"
+        {
+            problems.push(format!("OS_SYSTEM_SCOPE must be `all`, got {}", scope));
+        }
+
+        // Both end up in the destination argument, where a leading dash would be an option
+        for (name, value) in [("SSH_HOST", &self.ssh_host), ("SSH_USER", &self.ssh_user)] {
+            if let Some(value) = value
+                && (value.starts_with('-') || value.contains(SHELL_METACHARACTERS) || value.contains(char::is_whitespace))
+            {
+                problems.push(format!("{} is invalid: {}", name, value));
+            }
+        }
+        if self.ssh_host.is_none() && (self.ssh_user.is_some() || self.ssh_key.is_some()) {
+            problems.push("SSH_USER and SSH_KEY need SSH_HOST".to_string());
+        }
+
+        if !self.sink.is_available() {
+            problems.push(format!("SINK={} is not supported by this build (use file or none)", self.sink));
+        } else if self.sink != SinkKind::None && self.sink_url.is_none() {
+            problems.push(format!("SINK={} needs SINK_URL", self.sink));
+        }
+
+        // The month-to-date default must always fit
+        if self.max_range_days < 31 {
+            problems.push(format!("MAX_RANGE_DAYS must be at least 31, got {}", self.max_range_days));
+        }
+
+        if let Some(expression) = &self.refresh_cron
+            && let Err(e) = RefreshSchedule::parse(expression, self.display_timezone)
+        {
+            problems.push(format!("REFRESH_CRON is invalid: {}", e));
+        }
+
+        if let Err(e) = normalize_auth_url(&self.os_auth_url, false) {
+            problems.push(format!("OS_AUTH_URL is invalid: {}", e));
+        }
+
+        for (index, cloud) in self.os_clouds.iter().enumerate() {
+            if cloud.contains(SHELL_METACHARACTERS) || cloud.contains(char::is_whitespace) {
+                problems.push(format!("OS_CLOUDS contains an invalid cloud name: {}", cloud));
+            }
+            if self.os_clouds[..index].contains(cloud) {
+                problems.push(format!("OS_CLOUDS lists {} more than once", cloud));
+            }
+        }
+
+        for (index, endpoint) in self.rating_endpoints.iter().enumerate() {
+            if endpoint.contains(SHELL_METACHARACTERS) || endpoint.contains(char::is_whitespace) {
+                problems.push(format!("RATING_ENDPOINTS contains an invalid cloud name: {}", endpoint));
+            }
+            if self.rating_endpoints[..index].contains(endpoint) {
+                problems.push(format!("RATING_ENDPOINTS lists {} more than once", endpoint));
+            }
+        }
+
+        // Every fetch queries all endpoints, so switching clouds would silently do nothing
+        if !self.os_clouds.is_empty() && !self.rating_endpoints.is_empty() {
+            problems.push("OS_CLOUDS and RATING_ENDPOINTS cannot be combined; list every cloud to sum in RATING_ENDPOINTS".to_string());
+        }
+
+        // ssh itself always starts; a missing remote CLI exits 127 and would never fall back
+        if self.ssh_host.is_some() && self.openstack_command_fallback.is_some() {
+            problems.push("OPENSTACK_COMMAND_FALLBACK cannot be used with SSH_HOST".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    /// Password-authentication variables that are unset, or none when clouds.yaml is used
+    fn missing_credentials(&self) -> Vec<&'static str> {
+        if !self.os_clouds.is_empty() || !self.rating_endpoints.is_empty() {
+            return Vec::new();
+        }
+        [
+            ("OS_AUTH_URL", &self.os_auth_url),
+            ("OS_USERNAME", &self.os_username),
+            ("OS_PASSWORD", &self.os_password),
+            ("OS_PROJECT_ID", &self.os_project_id),
+        ]
+        .into_iter()
+        .filter(|(name, _)| *name != "OS_PROJECT_ID" || self.os_system_scope.is_none())
+        .filter(|(_, value)| value.is_empty())
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    /// Create a configuration containing only the given variables
+    #[cfg(test)]
+    pub fn from_pairs(vars: &[(&str, &str)]) -> Self {
+        Self::from_source(|name| {
+            vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+        })
+    }
+
+    /// Report which optional features are enabled by this configuration
+    pub fn features(&self) -> FeatureFlags {
+        FeatureFlags {
+            data_backend: if self.ssh_host.is_some() { "ssh" } else { "cli" },
+            cache_backend: if self.cache_file.is_some() { "file" } else { "memory" },
+            tls: false,
+            auth: self.api_token.is_some() || !self.token_scopes.is_empty(),
+            metrics: self.metrics_enabled,
+            grafana: self.grafana_enabled,
+            efficiency: self.efficiency_enabled,
+            anonymize_services: self.anonymize_services,
+            sampling: self.sample_rate < 1.0,
+        }
+    }
+
+    /// Get the admin listener bind address, if a separate admin port is configured
+    pub fn admin_address(&self) -> Option<String> {
+        self.admin_port.map(|port| host_port(&self.admin_bind_address, port))
     }
 
     /// Get the public server URL for display
     pub fn public_url(&self) -> String {
-        if self.bind_address == "0.0.0.0" {
+        if self.bind_address == "0.0.0.0" || self.bind_address == "::" {
             format!("http://localhost:{}", self.port)
         } else {
-            format!("http://{}:{}", self.bind_address, self.port)
+            format!("http://{}", host_port(&self.bind_address, self.port))
         }
     }
 }
 
+/// Join a host and port, bracketing IPv6 addresses (`[::1]:3001`)
+///
+/// Any host containing a colon is taken as IPv6; already bracketed hosts are kept as is.
+fn host_port(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Read and parse a variable, falling back to a default when unset or invalid
+fn parse_var<F, T>(get: &F, name: &str, default: T) -> T
+where
+    F: Fn(&str) -> Option<String>,
+    T: FromStr + Display,
+    T::Err: Display,
+{
+    match get(name) {
+        Some(value) => value.parse().unwrap_or_else(|e| {
+            warn!("Invalid {} value, using default {}: {}", name, default, e);
+            default
+        }),
+        None => {
+            info!("Using default {}: {}", name, default);
+            default
+        }
+    }
+}
+
+/// Give a Keystone URL an explicit scheme and, when it has no path, the `/v3` API version
+///
+/// An empty URL is left unset. Schemes other than http and https and URLs without
+/// a usable host are rejected.
+fn normalize_auth_url(url: &str, append_v3: bool) -> Result<String, String> {
+    let url = url.trim();
+    if url.is_empty() {
+        return Ok(String::new());
+    }
+
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+        None => ("https".to_string(), url),
+    };
+    if scheme != "http" && scheme != "https" {
+        return Err(format!("unsupported scheme '{}' in '{}' (expected http or https)", scheme, url));
+    }
+
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let valid_host = !host.is_empty()
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'));
+    if !valid_host {
+        return Err(format!("'{}' does not contain a valid host", url));
+    }
+
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        let version = if append_v3 { "/v3" } else { "" };
+        Ok(format!("{}://{}{}", scheme, host, version))
+    } else {
+        Ok(format!("{}://{}/{}", scheme, host, path))
+    }
+}
+
+/// Parse a comma-separated list of `#rgb`/`#rrggbb` colors, skipping invalid ones
+fn parse_palette(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|color| !color.is_empty())
+        .filter_map(|color| {
+            let hex = color.strip_prefix('#').unwrap_or("");
+            let valid = matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit());
+            if !valid {
+                warn!("Ignoring invalid CHART_PALETTE color '{}': expected #rgb or #rrggbb", color);
+            }
+            valid.then(|| color.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+/// Check a currency rate for an impossible sign or an implausible size
+///
+/// Returns the rate to use and the warnings to log. A zero rate falls back to the
+/// default and a negative one loses its sign. A rate outside `min_sane..=max_sane`
+/// was probably entered the wrong way round; with `auto_invert` it is replaced by
+/// its reciprocal when that is in range, otherwise it is only reported.
+fn check_currency_rate(rate: f64, min_sane: f64, max_sane: f64, auto_invert: bool) -> (f64, Vec<String>) {
+    let mut warnings = Vec::new();
+    if rate == 0.0 || !rate.is_finite() {
+        warnings.push(format!("CURRENCY_RATE {} cannot convert ratings, using default 55.5", rate));
+        return (55.5, warnings);
+    }
+    let mut rate = rate;
+    if rate < 0.0 {
+        warnings.push(format!("CURRENCY_RATE {} is negative, using {}", rate, -rate));
+        rate = -rate;
+    }
+    if (min_sane..=max_sane).contains(&rate) {
+        return (rate, warnings);
+    }
+    let inverse = 1.0 / rate;
+    let mut warning = format!(
+        "CURRENCY_RATE {} is outside the plausible range {} to {}; costs are the rating divided by it, \
+         so it may have been entered inverted (did you mean {}?)",
+        rate, min_sane, max_sane, inverse
+    );
+    if auto_invert && (min_sane..=max_sane).contains(&inverse) {
+        warning.push_str(&format!(" Using {} as AUTO_INVERT_SUSPICIOUS_RATE is set", inverse));
+        rate = inverse;
+    }
+    warnings.push(warning);
+    (rate, warnings)
+}
+
+/// Parse `USD=55.5,EUR=60` into currency codes and positive rates
+fn parse_currency_rates(value: &str) -> Vec<(String, f64)> {
+    value
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(code, rate)| {
+                let rate = rate.trim().parse::<f64>().ok().filter(|rate| rate.is_finite() && *rate > 0.0)?;
+                Some((code.trim().to_ascii_uppercase(), rate)).filter(|(code, _)| !code.is_empty())
+            });
+            if parsed.is_none() {
+                warn!("Ignoring invalid CURRENCY_RATES entry '{}': expected CODE=positive_rate", entry.trim());
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Parse `category=svc1,svc2;category=svc3` into categories and their services
+fn parse_categories(value: &str) -> Vec<(String, Vec<String>)> {
+    value
+        .split(';')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| match entry.split_once('=') {
+            Some((category, services)) if !category.trim().is_empty() => Some((
+                category.trim().to_string(),
+                services.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect(),
+            )),
+            _ => {
+                warn!("Ignoring invalid SERVICE_CATEGORIES entry '{}': expected category=svc1,svc2", entry.trim());
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parse `token=svc1,svc2;token=svc3` into tokens and the services they may view
+///
+/// Invalid entries are skipped without echoing them, as they may hold a token.
+fn parse_token_scopes(value: &str) -> Vec<(String, Vec<String>)> {
+    value
+        .split(';')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| match entry.split_once('=') {
+            Some((token, services)) if !token.trim().is_empty() => Some((
+                token.trim().to_string(),
+                services.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect(),
+            )),
+            _ => {
+                warn!("Ignoring invalid TOKEN_SCOPES entry: expected token=svc1,svc2");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Read an optional variable, treating empty values as unset
+fn optional_var<F>(get: &F, name: &str) -> Option<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    get(name).filter(|value| !value.trim().is_empty())
+}
+
+/// Read a boolean flag accepting `true/false`, `1/0`, `yes/no` and `on/off`
+fn flag_var<F>(get: &F, name: &str, default: bool) -> bool
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match get(name).map(|value| value.trim().to_ascii_lowercase()) {
+        Some(value) => match value.as_str() {
+            "true" | "1" | "yes" | "on" => true,
+            "false" | "0" | "no" | "off" => false,
+            _ => {
+                warn!("Invalid {} value, using default {}: {}", name, default, value);
+                default
+            }
+        },
+        None => default,
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_implausible_currency_rate_is_reported_and_optionally_inverted() {
+        let (rate, warnings) = check_currency_rate(55.5, 0.01, 10_000.0, false);
+        assert_eq!(rate, 55.5);
+        assert!(warnings.is_empty());
+
+        let (rate, warnings) = check_currency_rate(0.005, 0.01, 10_000.0, false);
+        assert_eq!(rate, 0.005, "only warns by default");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("may have been entered inverted"), "{}", warnings[0]);
+
+        let (rate, warnings) = check_currency_rate(0.005, 0.01, 10_000.0, true);
+        assert_eq!(rate, 200.0);
+        assert!(warnings[0].contains("Using 200"), "{}", warnings[0]);
+
+        // An inverse that is implausible too is not used
+        assert_eq!(check_currency_rate(1e-6, 0.01, 10_000.0, true).0, 1e-6);
+        assert_eq!(check_currency_rate(-55.5, 0.01, 10_000.0, false), (55.5, vec!["CURRENCY_RATE -55.5 is negative, using 55.5".to_string()]));
+        assert_eq!(check_currency_rate(0.0, 0.01, 10_000.0, false).0, 55.5);
+
+        let config = Config::from_pairs(&[("CURRENCY_RATE", "0.005"), ("AUTO_INVERT_SUSPICIOUS_RATE", "true")]);
+        assert_eq!(config.currency_rate, 200.0);
+        let config = Config::from_pairs(&[("CURRENCY_RATE", "0.005"), ("CURRENCY_RATE_MIN_SANE", "0.001")]);
+        assert_eq!(config.currency_rate, 0.005);
+    }
+
+    #[test]
+    fn test_command_fallback_is_rejected_over_ssh() {
+        let config = Config::from_pairs(&[("SSH_HOST", "bastion.example.com"), ("OPENSTACK_COMMAND_FALLBACK", "/usr/bin/openstack")]);
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("OPENSTACK_COMMAND_FALLBACK cannot be used with SSH_HOST"), "{}", error);
+        assert!(Config::from_pairs(&[("OPENSTACK_COMMAND_FALLBACK", "/usr/bin/openstack")]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_cloud_switching_and_rating_endpoints_are_exclusive() {
+        let config = Config::from_pairs(&[("OS_CLOUDS", "east,west"), ("RATING_ENDPOINTS", "region-a,region-b")]);
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("OS_CLOUDS and RATING_ENDPOINTS cannot be combined"), "{}", error);
+        assert!(Config::from_pairs(&[("RATING_ENDPOINTS", "region-a,region-b")]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_refresh_backoff_is_opt_in() {
+        assert_eq!(Config::from_pairs(&[]).refresh_backoff_threshold, 0);
+        assert_eq!(Config::from_pairs(&[("REFRESH_BACKOFF_THRESHOLD", "3")]).refresh_backoff_threshold, 3);
+    }
+
+    #[test]
+    fn test_extra_args_cannot_inject_credentials() {
+        let config = Config::from_pairs(&[("OPENSTACK_EXTRA_ARGS", "--insecure --os-password hunter2")]);
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.0, vec!["OPENSTACK_EXTRA_ARGS must not override --os-password".to_string()]);
+
+        let config = Config::from_pairs(&[("OPENSTACK_EXTRA_ARGS", "--os-password=hunter2")]);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_extra_args_reject_subcommands_and_metacharacters() {
+        assert!(Config::from_pairs(&[("OPENSTACK_EXTRA_ARGS", "rating")]).validate().is_err());
+        assert!(Config::from_pairs(&[("OPENSTACK_EXTRA_ARGS", "--insecure;rm")]).validate().is_err());
+        assert!(Config::from_pairs(&[("OPENSTACK_EXTRA_ARGS", "--insecure --os-interface public")]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_group_regex_must_compile_with_a_capture_group() {
+        assert!(Config::from_pairs(&[("GROUP_REGEX", "^(team[A-Z]-")]).validate().is_err());
+        assert!(Config::from_pairs(&[("GROUP_REGEX", "^team[A-Z]-")]).validate().is_err());
+        assert!(Config::from_pairs(&[("GROUP_REGEX", "^(team[A-Z])-")]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_bind_addresses_are_formatted_per_family() {
+        let ipv4 = Config::from_pairs(&[("BIND_ADDRESS", "127.0.0.1"), ("PORT", "3001")]);
+        assert_eq!(ipv4.server_address(), "127.0.0.1:3001");
+        assert_eq!(ipv4.public_url(), "http://127.0.0.1:3001");
+
+        let ipv6 = Config::from_pairs(&[("BIND_ADDRESS", "::1"), ("PORT", "3001"), ("ADMIN_PORT", "9090")]);
+        assert_eq!(ipv6.server_address(), "[::1]:3001");
+        assert_eq!(ipv6.public_url(), "http://[::1]:3001");
+        assert_eq!(ipv6.admin_address().as_deref(), Some("[::1]:9090"));
+        assert!(ipv6.server_address().parse::<std::net::SocketAddr>().is_ok());
+
+        let any = Config::from_pairs(&[("BIND_ADDRESS", "::"), ("PORT", "3001")]);
+        assert_eq!(any.server_address(), "[::]:3001");
+        assert_eq!(any.public_url(), "http://localhost:3001");
+
+        let hostname = Config::from_pairs(&[("BIND_ADDRESS", "dashboard.internal"), ("PORT", "8080")]);
+        assert_eq!(hostname.server_address(), "dashboard.internal:8080");
+        assert_eq!(hostname.public_url(), "http://dashboard.internal:8080");
+    }
+
+    #[test]
+    fn test_auth_url_without_scheme_gets_https() {
+        let config = Config::from_pairs(&[("OS_AUTH_URL", "keystone.example.com:5000/v3")]);
+        assert_eq!(config.os_auth_url, "https://keystone.example.com:5000/v3");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_auth_url_without_version_gets_v3() {
+        let config = Config::from_pairs(&[("OS_AUTH_URL", "http://keystone.example.com:5000/")]);
+        assert_eq!(config.os_auth_url, "http://keystone.example.com:5000/v3");
+
+        let config = Config::from_pairs(&[("OS_AUTH_URL", "https://keystone.example.com/identity/v3/")]);
+        assert_eq!(config.os_auth_url, "https://keystone.example.com/identity/v3");
+
+        let config = Config::from_pairs(&[
+            ("OS_AUTH_URL", "https://keystone.example.com:5000"),
+            ("OS_AUTH_URL_APPEND_V3", "false"),
+        ]);
+        assert_eq!(config.os_auth_url, "https://keystone.example.com:5000");
+    }
+
+    #[test]
+    fn test_invalid_auth_url_is_rejected() {
+        let config = Config::from_pairs(&[("OS_AUTH_URL", "ftp://keystone.example.com")]);
+        let error = config.validate().unwrap_err();
+        assert!(error.0[0].starts_with("OS_AUTH_URL is invalid: unsupported scheme 'ftp'"));
+
+        assert!(Config::from_pairs(&[("OS_AUTH_URL", "https:///v3")]).validate().is_err());
+        assert!(Config::from_pairs(&[("OS_AUTH_URL", "key stone:5000")]).validate().is_err());
+    }
+
+    #[test]
+    fn test_required_credentials_must_be_complete() {
+        let error = Config::from_pairs(&[("REQUIRE_CREDENTIALS", "true"), ("OS_AUTH_URL", "https://keystone.example.com")])
+            .validate()
+            .unwrap_err();
+        assert!(error.0[0].contains("missing OS_USERNAME, OS_PASSWORD, OS_PROJECT_ID"), "{}", error);
+
+        let password = Config::from_pairs(&[
+            ("REQUIRE_CREDENTIALS", "true"),
+            ("OS_AUTH_URL", "https://keystone.example.com"),
+            ("OS_USERNAME", "admin"),
+            ("OS_PASSWORD", "secret"),
+            ("OS_PROJECT_ID", "1234"),
+        ]);
+        assert!(password.validate().is_ok());
+        assert!(Config::from_pairs(&[("REQUIRE_CREDENTIALS", "true"), ("OS_CLOUDS", "prod")]).validate().is_ok());
+        assert!(Config::from_pairs(&[]).validate().is_ok());
+    }
+}
@@ -0,0 +1,149 @@
+//! Currency formatting patterns for the OpenStack Cost Dashboard
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Placeholder replaced by the currency symbol
+const SYMBOL: char = '¤';
+
+/// Amount layout parsed from an ICU-style pattern such as `#,##0.00 ¤` or `¤#,##0.00`
+///
+/// `#` and `0` are digit placeholders and `¤` is the currency symbol; any other
+/// character inside the number is a separator written as-is, so `# ##0,00 ¤` groups
+/// with spaces and uses a decimal comma. With two separators the last one is the
+/// decimal separator; a lone separator groups digits when it sits between `#` and
+/// exactly three placeholders (`#,##0`) and marks the decimals otherwise (`0.00`).
+/// Digits are grouped by three, and the places after the decimal separator are
+/// always written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrencyFormat {
+    pattern: String,
+    prefix: String,
+    suffix: String,
+    grouping: Option<char>,
+    decimal: Option<char>,
+    decimals: usize,
+}
+
+impl CurrencyFormat {
+    /// Write an amount with this layout, putting `symbol` where the pattern has `¤`
+    pub fn format(&self, amount: f64, symbol: &str) -> String {
+        let digits = format!("{:.*}", self.decimals, amount.abs());
+        let (integer, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+        let mut number = String::new();
+        for (index, digit) in integer.chars().enumerate() {
+            if index > 0
+                && (integer.len() - index) % 3 == 0
+                && let Some(grouping) = self.grouping
+            {
+                number.push(grouping);
+            }
+            number.push(digit);
+        }
+        if let Some(decimal) = self.decimal.filter(|_| !fraction.is_empty()) {
+            number.push(decimal);
+            number.push_str(fraction);
+        }
+        // Rounded away from zero, -0.001 is written without a sign
+        let sign = if amount < 0.0 && digits.chars().any(|digit| digit.is_ascii_digit() && digit != '0') { "-" } else { "" };
+        format!(
+            "{}{}{}{}",
+            sign,
+            self.prefix.replace(SYMBOL, symbol),
+            number,
+            self.suffix.replace(SYMBOL, symbol)
+        )
+    }
+}
+
+/// Write an amount with `pattern`, or after `symbol` with two decimals when there is none
+pub fn format_amount(pattern: Option<&CurrencyFormat>, amount: f64, symbol: &str) -> String {
+    match pattern {
+        Some(pattern) => pattern.format(amount, symbol),
+        None => format!("{}{:.2}", symbol, amount),
+    }
+}
+
+impl FromStr for CurrencyFormat {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let is_placeholder = |c: char| c == '#' || c == '0';
+        let (Some(start), Some(end)) = (pattern.find(is_placeholder), pattern.rfind(is_placeholder)) else {
+            return Err(format!("currency format '{}' has no digit placeholders (# or 0)", pattern));
+        };
+        let number: Vec<char> = pattern[start..=end].chars().collect();
+        if number.contains(&SYMBOL) {
+            return Err(format!("currency format '{}' has the symbol inside the number", pattern));
+        }
+        let separators: Vec<usize> = (0..number.len()).filter(|&index| !is_placeholder(number[index])).collect();
+        let (grouping, decimal) = match separators.as_slice() {
+            [] => (None, None),
+            [only] => {
+                let places = number.len() - only - 1;
+                if places == 3 && number[..*only].ends_with(&['#']) {
+                    (Some(*only), None)
+                } else {
+                    (None, Some(*only))
+                }
+            }
+            [first, .., last] => {
+                if separators.iter().any(|&index| index != *last && number[index] != number[*first]) {
+                    return Err(format!("currency format '{}' mixes grouping separators", pattern));
+                }
+                (Some(*first), Some(*last))
+            }
+        };
+        Ok(Self {
+            pattern: pattern.to_string(),
+            prefix: pattern[..start].to_string(),
+            suffix: pattern[end..].chars().skip(1).collect(),
+            grouping: grouping.map(|index| number[index]),
+            decimal: decimal.map(|index| number[index]),
+            decimals: decimal.map_or(0, |index| number.len() - index - 1),
+        })
+    }
+}
+
+impl fmt::Display for CurrencyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_after_the_number_with_space_grouping() {
+        let format: CurrencyFormat = "# ##0,00 ¤".parse().unwrap();
+        assert_eq!(format.format(1234567.891, "€"), "1 234 567,89 €");
+        assert_eq!(format.format(12.5, "€"), "12,50 €");
+        assert_eq!(format.format(-1500.0, "€"), "-1 500,00 €");
+    }
+
+    #[test]
+    fn test_symbol_before_the_number() {
+        let format: CurrencyFormat = "¤#,##0.00".parse().unwrap();
+        assert_eq!(format.format(1234.5, "$"), "$1,234.50");
+        assert_eq!(format.format(0.004, "$"), "$0.00");
+
+        let whole: CurrencyFormat = "¤ #,##0".parse().unwrap();
+        assert_eq!(whole.format(98765.4, "CHF"), "CHF 98,765");
+        let plain: CurrencyFormat = "0.000¤".parse().unwrap();
+        assert_eq!(plain.format(1234.5, "k$"), "1234.500k$");
+    }
+
+    #[test]
+    fn test_prefix_symbol_without_a_pattern() {
+        assert_eq!(format_amount(None, 1234.567, "€"), "€1234.57");
+    }
+
+    #[test]
+    fn test_invalid_patterns_are_rejected() {
+        assert!("¤".parse::<CurrencyFormat>().is_err());
+        assert!("#¤##0.00".parse::<CurrencyFormat>().is_err());
+        assert!("#,##0 ##0.00".parse::<CurrencyFormat>().is_err());
+    }
+}
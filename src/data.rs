@@ -1,301 +1,3490 @@
 //! Data fetching and processing for the OpenStack Cost Dashboard
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, LazyLock, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::process::Stdio;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio::process::Command;
-use tracing::{info, warn};
-use chrono::Local;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+use chrono::{DateTime, Datelike, Local, NaiveDate, Utc};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use regex::Regex;
+use sha2::{Digest, Sha256};
 
-use crate::models::{ChartData, ResourceWrapper};
-use crate::config::Config;
+use crate::models::{CapturedOutput, ChartData, CommandPreview, CurrencyCosts, FetchOutcome, RatingData, Resource, ResourceWrapper, TimeBucket};
+use crate::config::{Config, SplitBy};
 use crate::cache::OpenStackCache;
+use crate::history::HistoryPoint;
+use crate::metrics::{FetchResult, Metrics};
+use crate::rolling::{RollingCosts, RollingSum};
+use crate::currency_format::format_amount;
 
-/// Data service for fetching and processing OpenStack data
-#[derive(Clone)]
-pub struct DataService {
-    config: Config,
-    cache: Arc<OpenStackCache>,
+/// Fixed seed so sampled refreshes of the same payload are reproducible
+const SAMPLE_SEED: u64 = 0x5eed_cafe;
+
+/// Largest amount of raw CLI output kept for debugging
+const DEBUG_CAPTURE_MAX_BYTES: usize = 256 * 1024;
+
+/// Most charts kept with `CACHE_PROCESSED_CHART`, the oldest are dropped first
+const PROCESSED_CHARTS_MAX: usize = 64;
+
+/// Most day totals kept with `DAILY_AGGREGATES`, about ten years of one cloud
+const DAILY_TOTALS_MAX: usize = 3660;
+
+/// Values of secret-looking keys in captured output
+static SECRET_KEYS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)("?[\w-]*(?:password|secret|token|credential)[\w-]*"?\s*[:=]\s*)("[^"]*"|[^\s,}]+)"#)
+        .expect("secret pattern is valid")
+});
+
+/// Derive a stable pseudonym for a service name
+pub fn anonymize_service(name: &str) -> String {
+    let digest = Sha256::digest(name.as_bytes());
+    let hex: String = digest.iter().take(4).map(|byte| format!("{:02x}", byte)).collect();
+    format!("service-{}", hex)
 }
 
-impl DataService {
-    /// Create a new data service
-    pub fn new(config: Config, cache: Arc<OpenStackCache>) -> Self {
-        Self { config, cache }
+/// Whether a name has the shape of an `anonymize_service` pseudonym
+fn is_pseudonym(name: &str) -> bool {
+    name.strip_prefix("service-")
+        .is_some_and(|hex| hex.len() == 8 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Quote an argument for a POSIX shell, leaving plain words as they are
+fn shell_quote(arg: &str) -> Cow<'_, str> {
+    let plain = !arg.is_empty()
+        && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
     }
+}
 
-    /// Fetch data from OpenStack CLI with caching
-    pub async fn fetch_data(&self, begin_at: Option<String>, end_at: Option<String>) -> HashMap<String, f64> {
-        // Generate the date string in the same format as the shell command
-        let begin_at_date_string = self.get_date_string(begin_at);
-        let end_at_date_string = self.get_date_string(
-            Some(end_at.unwrap_or_else(|| Local::now().format("%Y-%m-%d").to_string()))
-        );
+/// Digest of the settings that change how `process_data` charts the same ratings
+fn processing_fingerprint(config: &Config) -> String {
+    let settings = format!(
+        "{:?}",
+        (
+            config.anonymize_services,
+            config.max_label_length,
+            config.cost_scale,
+            config.cost_precision,
+            config.rounding_mode,
+            (&config.currency_format, &config.cost_unit_label),
+            config.service_cost_cap,
+            config.include_raw_values,
+            config.include_concentration,
+            (config.major_share_threshold, config.moderate_share_threshold),
+            &config.currency_rates,
+            config.sample_rate,
+        )
+    );
+    format!("{:x}", Sha256::digest(settings.as_bytes()))[..16].to_string()
+}
 
-        if !self.check_date_validity(Some(begin_at_date_string.clone()), Some(end_at_date_string.clone())) {
-            return HashMap::new();
-        }
-        
-        // Build arguments with authentication parameters
-        let mut args = Vec::new();
-        
-        // Add authentication parameters if available
-        if !self.config.os_auth_url.is_empty() {
-            args.push("--os-auth-url".to_string());
-            args.push(self.config.os_auth_url.clone());
-        }
-        
-        if !self.config.os_username.is_empty() {
-            args.push("--os-username".to_string());
-            args.push(self.config.os_username.clone());
-        }
-        
-        if !self.config.os_password.is_empty() {
-            args.push("--os-password".to_string());
-            args.push(self.config.os_password.clone());
+/// Digest of the settings that change the ratings cached for the same command
+///
+/// Part of every cache key, so a reloaded configuration never reuses ratings
+/// converted or filtered with the previous one.
+fn rating_fingerprint(config: &Config) -> String {
+    let settings = format!(
+        "{:?}",
+        (
+            config.currency_rate,
+            config.rating_is_currency,
+            &config.transform_command,
+            &config.resource_state_filter,
+            config.sample_rate,
+            config.split_by,
+        )
+    );
+    format!("{:x}", Sha256::digest(settings.as_bytes()))[..16].to_string()
+}
+
+/// Map that drops its oldest entries beyond a fixed number
+#[derive(Debug)]
+struct BoundedMap<V> {
+    entries: HashMap<String, V>,
+    /// Keys in insertion order, oldest first
+    order: VecDeque<String>,
+    max_entries: usize,
+}
+
+impl<V> BoundedMap<V> {
+    fn new(max_entries: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            max_entries,
         }
-        
-        if !self.config.os_project_id.is_empty() {
-            args.push("--os-project-id".to_string());
-            args.push(self.config.os_project_id.clone());
+    }
+
+    fn get(&self, key: &str) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    fn insert(&mut self, key: String, value: V) {
+        if self.entries.insert(key.clone(), value).is_none() {
+            self.order.push_back(key);
         }
-        
-        if !self.config.os_region_name.is_empty() {
-            args.push("--os-region-name".to_string());
-            args.push(self.config.os_region_name.clone());
+        while self.entries.len() > self.max_entries
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.entries.remove(&oldest);
         }
-        
-        if !self.config.os_user_domain_name.is_empty() {
-            args.push("--os-user-domain-name".to_string());
-            args.push(self.config.os_user_domain_name.clone());
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.entries.retain(|key, _| keep(key));
+        self.order.retain(|key| keep(key));
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Key of a processed chart: the cache entry it was built from and the processing settings
+fn processed_key(cache_key: &str, fingerprint: &str) -> String {
+    format!("{}/{}", cache_key, fingerprint)
+}
+
+/// Synchronous view of an async reader, for parsers that pull their input
+///
+/// Only usable off the runtime's worker threads, e.g. in `spawn_blocking`.
+struct BlockingRead<R> {
+    inner: R,
+    handle: tokio::runtime::Handle,
+}
+
+impl<R: AsyncRead + Unpin> std::io::Read for BlockingRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.handle.block_on(self.inner.read(buf))
+    }
+}
+
+/// Reader replacing invalid UTF-8 with U+FFFD, for parsers that reject it
+///
+/// A character cut off at the end of one read is completed by the next before it
+/// is judged. The first replacement is logged with its offset, like `decode_output`.
+struct LossyUtf8<R> {
+    inner: R,
+    /// Valid UTF-8 not handed out yet, from `position` on
+    decoded: Vec<u8>,
+    position: usize,
+    /// Start of a character cut off at the end of the last read
+    carry: Vec<u8>,
+    /// Offset in the input of the first byte after `carry`
+    consumed: usize,
+    warned: bool,
+}
+
+impl<R> LossyUtf8<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            decoded: Vec::new(),
+            position: 0,
+            carry: Vec::new(),
+            consumed: 0,
+            warned: false,
         }
-        
-        // Add the main command arguments
-        args.extend([
-            "rating".to_string(),
-            "dataframes".to_string(),
-            "get".to_string(),
-            "-b".to_string(),
-            begin_at_date_string,
-            "-e".to_string(),
-            end_at_date_string,
-            "-c".to_string(),
-            "Resources".to_string(),
-            "-f".to_string(),
-            "json".to_string(),
-        ]);
-        
-        // Generate a cache key from command and args
-        let cache_key = self.cache.generate_key(&self.config.openstack_command, &args);
-        
-        // Check cache first
-        if let Some(cached_data) = self.cache.get(&cache_key).await {
-            info!("Using cached data for OpenStack query");
-            return cached_data;
+    }
+
+    fn replace(&mut self, offset: usize) {
+        if !self.warned {
+            warn!("OpenStack command output has invalid UTF-8 at byte offset {}, replacing invalid bytes", offset);
+            self.warned = true;
         }
-        
-        // Create a redacted version of args for logging
-        let redacted_args = self.redact_sensitive_args(&args);
-        info!("Executing command: {} {}", self.config.openstack_command, redacted_args.join(" "));
-        
-        let output = Command::new(&self.config.openstack_command)
-            .args(&args)
-            .output()
-            .await;
+        self.decoded.extend_from_slice(char::REPLACEMENT_CHARACTER.to_string().as_bytes());
+    }
+}
 
-        match output {
-            Ok(output) if output.status.success() => {
-                let json_str = String::from_utf8_lossy(&output.stdout);
-                match serde_json::from_str::<Vec<ResourceWrapper>>(&json_str) {
-                    Ok(resources) => {
-                        let data_map = self.process_resources(resources);
-                        info!("Successfully fetched data for {} services", data_map.len());
-                        
-                        // Cache the result with configured TTL
-                        self.cache.set(
-                            cache_key.clone(),
-                            data_map.clone()
-                        ).await;
-                        
-                        data_map
+impl<R: std::io::Read> std::io::Read for LossyUtf8<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.decoded.len() {
+            let mut chunk = std::mem::take(&mut self.carry);
+            let start = self.consumed - chunk.len();
+            let carried = chunk.len();
+            chunk.resize(carried + 8 * 1024, 0);
+            let read = self.inner.read(&mut chunk[carried..])?;
+            chunk.truncate(carried + read);
+            self.consumed += read;
+            if chunk.is_empty() {
+                return Ok(0);
+            }
+            self.decoded.clear();
+            self.position = 0;
+            let mut rest = chunk.as_slice();
+            while !rest.is_empty() {
+                let e = match std::str::from_utf8(rest) {
+                    Ok(valid) => {
+                        self.decoded.extend_from_slice(valid.as_bytes());
+                        break;
                     }
-                    Err(e) => {
-                        warn!("Failed to parse JSON data: {}", e);
-                        warn!("Raw output: {}", json_str);
-                        HashMap::new()
+                    Err(e) => e,
+                };
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                self.decoded.extend_from_slice(valid);
+                let offset = start + chunk.len() - invalid.len();
+                match e.error_len() {
+                    Some(len) => {
+                        self.replace(offset);
+                        rest = &invalid[len..];
+                    }
+                    // Cut off by the end of this read, unless the output ended there
+                    None if read > 0 => {
+                        self.carry = invalid.to_vec();
+                        break;
+                    }
+                    None => {
+                        self.replace(offset);
+                        break;
                     }
                 }
             }
-            Ok(output) => {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                warn!("OpenStack command failed with status: {}", output.status);
-                if !stderr.is_empty() {
-                    warn!("Error output: {}", stderr.trim());
-                }
-                if !stdout.is_empty() {
-                    warn!("Standard output: {}", stdout.trim());
+        }
+        let count = buf.len().min(self.decoded.len() - self.position);
+        buf[..count].copy_from_slice(&self.decoded[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Row counts for `DataService::check_schema`
+#[derive(Debug, Default)]
+struct SchemaTally {
+    rows: usize,
+    missing_service: usize,
+    missing_rating: usize,
+    incomplete: usize,
+    /// First row lacking a service or rating
+    sample: Option<Resource>,
+}
+
+impl SchemaTally {
+    fn record(&mut self, row: &Resource) {
+        self.rows += 1;
+        self.missing_service += usize::from(row.service.is_empty());
+        self.missing_rating += usize::from(row.rating.is_nan());
+        if row.service.is_empty() || row.rating.is_nan() {
+            self.incomplete += 1;
+            self.sample.get_or_insert_with(|| row.clone());
+        }
+    }
+}
+
+/// Per-service totals and time series of dataframes added one at a time
+///
+/// Dataframes sharing the same `Begin`/`End` window are merged into one
+/// bucket; dataframes without a window only contribute to the totals.
+///
+/// When `sample_rate` is below 1.0 only a seeded random subset of rows is
+/// accumulated and the totals are scaled up by the inverse rate, trading
+/// accuracy for speed on very large payloads.
+struct ResourceTotals {
+    config: Arc<Config>,
+    rng: StdRng,
+    costs: HashMap<String, f64>,
+    raw_costs: HashMap<String, f64>,
+    domain_costs: HashMap<String, f64>,
+    domain_raw_costs: HashMap<String, f64>,
+    buckets: HashMap<(String, String), HashMap<String, f64>>,
+    dataframe_count: usize,
+    schema: SchemaTally,
+}
+
+impl ResourceTotals {
+    fn new(config: Arc<Config>) -> Self {
+        Self {
+            config,
+            rng: StdRng::seed_from_u64(SAMPLE_SEED),
+            costs: HashMap::new(),
+            raw_costs: HashMap::new(),
+            domain_costs: HashMap::new(),
+            domain_raw_costs: HashMap::new(),
+            buckets: HashMap::new(),
+            dataframe_count: 0,
+            schema: SchemaTally::default(),
+        }
+    }
+
+    /// Whether a resource in `state` counts towards the costs under `RESOURCE_STATE_FILTER`
+    ///
+    /// Without a filter every resource counts; with one, resources without a state are left out.
+    fn state_included(&self, state: Option<&str>) -> bool {
+        let filter = &self.config.resource_state_filter;
+        filter.is_empty() || state.is_some_and(|state| filter.iter().any(|allowed| allowed.eq_ignore_ascii_case(state)))
+    }
+
+    fn add(&mut self, wrapped: ResourceWrapper) {
+        self.dataframe_count += 1;
+        let sample_rate = self.config.sample_rate;
+        let split_by_domain = self.config.split_by == Some(SplitBy::Domain);
+        let window = wrapped.begin.zip(wrapped.end);
+        for resource in wrapped.resources.into_iter() {
+            self.schema.record(&resource);
+            if resource.service.is_empty() || !self.state_included(resource.state.as_deref()) {
+                continue;
+            }
+            if sample_rate < 1.0 && !self.rng.gen_bool(sample_rate) {
+                continue;
+            }
+            let rating = resource.rating;
+            if rating.is_finite() {
+                let cost = if self.config.rating_is_currency {
+                    rating
+                } else {
+                    rating / self.config.currency_rate
+                };
+                if let Some(window) = &window {
+                    *self.buckets
+                        .entry(window.clone())
+                        .or_default()
+                        .entry(resource.service.clone())
+                        .or_insert(0.0) += cost;
                 }
-                
-                // Check for common authentication errors
-                if stderr.contains("auth-url") || stderr.contains("Missing value") {
-                    warn!("OpenStack authentication not configured. Please set up your OpenStack credentials.");
-                    warn!("You can do this by sourcing an OpenStack RC file or setting environment variables.");
-                    warn!("Example: source ~/openstack-rc.sh");
+                if split_by_domain {
+                    let domain = resource.domain_id.clone().unwrap_or_else(|| "unknown".to_string());
+                    *self.domain_raw_costs.entry(domain.clone()).or_insert(0.0) += rating;
+                    *self.domain_costs.entry(domain).or_insert(0.0) += cost;
                 }
-                
-                HashMap::new()
-            }
-            Err(e) => {
-                warn!("Failed to execute OpenStack command: {}", e);
-                warn!("Make sure the OpenStack CLI is installed and in your PATH");
-                HashMap::new()
+                *self.raw_costs.entry(resource.service.clone()).or_insert(0.0) += rating;
+                *self.costs.entry(resource.service).or_insert(0.0) += cost;
             }
         }
     }
 
-    /// Process fetched resources into a hashmap
-    fn process_resources(&self, resources: Vec<ResourceWrapper>) -> HashMap<String, f64> {
-        let mut data_map = HashMap::new();
-        for wrapped in resources.into_iter() {
-            for resource in wrapped.resources.into_iter() {
-                if let Ok(rating) = resource.rating.parse::<f64>() {
-                    let cost = rating / self.config.currency_rate;
-                    *data_map.entry(resource.service).or_insert(0.0) += cost;
-                }
+    fn finish(mut self) -> RatingData {
+        let sample_rate = self.config.sample_rate;
+        if sample_rate < 1.0 {
+            for cost in self.costs
+                .values_mut()
+                .chain(self.raw_costs.values_mut())
+                .chain(self.domain_costs.values_mut())
+                .chain(self.domain_raw_costs.values_mut())
+                .chain(self.buckets.values_mut().flat_map(|b| b.values_mut()))
+            {
+                *cost /= sample_rate;
             }
         }
-        data_map
+
+        let mut timeseries: Vec<TimeBucket> = self.buckets
+            .into_iter()
+            .map(|((begin, end), services)| TimeBucket {
+                begin,
+                end,
+                total_cost: services.values().sum(),
+                services,
+            })
+            .collect();
+        timeseries.sort_by(|a, b| a.begin.cmp(&b.begin).then_with(|| a.end.cmp(&b.end)));
+
+        RatingData {
+            costs: self.costs,
+            raw_costs: self.raw_costs,
+            domain_costs: self.domain_costs,
+            domain_raw_costs: self.domain_raw_costs,
+            timeseries,
+            dataframe_count: self.dataframe_count,
+            row_count: self.schema.rows,
+            ..RatingData::default()
+        }
     }
+}
 
-    /// Process data into chart-ready format
-    pub fn process_data(&self, data: HashMap<String, f64>) -> ChartData {
-        let mut sorted_data: Vec<(String, f64)> = data.iter().map(|(k, v)| (k.clone(), *v)).collect();
-        sorted_data.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+/// Visitor adding each dataframe of the CLI's JSON array to the totals as it is parsed
+struct Dataframes<'a>(&'a mut ResourceTotals);
 
-        let labels: Vec<String> = sorted_data.iter().map(|(service, _)| service.clone()).collect();
-        let values: Vec<f64> = sorted_data.iter().map(|(_, cost)| *cost).collect();
-        let total_cost: f64 = values.iter().sum();
-        let service_count = labels.len();
-        let average_cost = if service_count > 0 {
-            total_cost / service_count as f64
-        } else {
-            0.0
-        };
+impl<'de> serde::de::Visitor<'de> for Dataframes<'_> {
+    type Value = ();
 
-        ChartData {
-            labels,
-            values,
-            total_cost,
-            service_count,
-            average_cost,
-            last_updated: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a list of dataframes")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(wrapped) = seq.next_element::<ResourceWrapper>()? {
+            self.0.add(wrapped);
         }
+        Ok(())
     }
+}
 
-    pub fn check_date_validity(&self, start: Option<String>, end: Option<String>) -> bool {
-        if let (Some(start), Some(end)) = (start, end) {
-            let start_date = chrono::NaiveDate::parse_from_str(&start, "%Y-%m-%dT%H:%M:%S%z");
-            let end_date = chrono::NaiveDate::parse_from_str(&end, "%Y-%m-%dT%H:%M:%S%z");
+/// Parse the CLI's JSON output from `reader` into `totals`, one dataframe at a time
+fn parse_dataframes(reader: impl std::io::Read, totals: &mut ResourceTotals) -> serde_json::Result<()> {
+    use serde::Deserializer;
 
-            match (start_date, end_date) {
-                (Ok(start_date), Ok(end_date)) => {
-                    let current_date = Local::now().date_naive();
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    (&mut deserializer).deserialize_seq(Dataframes(totals))?;
+    deserializer.end()
+}
 
-                    if start_date > end_date {
-                        warn!("Start date {} is after end date {}", start, end);
-                        false
-                    } else if start_date >= current_date || end_date > current_date {
-                        warn!("Date range cannot be in the future: start {}, end {}", start, end);
-                        false
-                    } else {
-                        true
-                    }
-                }
-                (Err(e), _) => {
-                    warn!("Invalid start date format '{}': {}", start, e);
-                    false
-                }
-                (_, Err(e)) => {
-                    warn!("Invalid end date format '{}': {}", end, e);
-                    false
-                }
+/// CLI arguments requesting only the `Resources` column
+///
+/// Drops the `Begin` and `End` columns, so the rows lose their time window.
+fn resources_column_only(args: &[String]) -> Vec<String> {
+    let mut narrowed = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-c" {
+            match args.next() {
+                Some(column) if column == "Resources" => narrowed.extend([arg.clone(), column.clone()]),
+                _ => continue,
             }
         } else {
-            true // If no dates provided, assume valid
+            narrowed.push(arg.clone());
         }
     }
+    narrowed
+}
 
-    /// Get the formatted date string that would be used in the OpenStack command
-    pub fn get_date_string(&self, date: Option<String>) -> String {
-        match date {
-            Some(d) => {
-                // Parse the provided date string
-                match chrono::NaiveDate::parse_from_str(&d, "%Y-%m-%d") {
-                    Ok(date) => date.format("%Y-%m-%dT00:00:00+00:00").to_string(),
-                    Err(e) => {
-                        warn!("Invalid date format '{}', using current date: {}", d, e);
-                        Local::now().format("%Y-%m-01T00:00:00+00:00").to_string()
-                    }
-                }
-            }
-            None => Local::now().format("%Y-%m-01T00:00:00+00:00").to_string(),
+/// Items of a list parallel to the services whose `keep` flag is set
+///
+/// Empty lists (optional parallel vectors) stay empty.
+fn retain<T: Clone>(items: &[T], keep: &[bool]) -> Vec<T> {
+    items.iter().zip(keep).filter(|(_, keep)| **keep).map(|(item, _)| item.clone()).collect()
+}
+
+/// Percentile `p` (0 to 1) of ascending `values`, or zero when there are none
+///
+/// Interpolates linearly between the two closest ranks (rank `p * (n - 1)`),
+/// the same method as the default of numpy and spreadsheet `PERCENTILE`.
+fn percentile(values: &[f64], p: f64) -> f64 {
+    let Some(last) = values.len().checked_sub(1) else {
+        return 0.0;
+    };
+    let rank = p * last as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    values[lower] + (values[upper] - values[lower]) * (rank - lower as f64)
+}
+
+/// Herfindahl-Hirschman index of costs: the sum of each one's squared share of the total
+///
+/// 1 when a single service holds all the spend, 1/n when n services spend equally;
+/// 0 without any spend.
+fn herfindahl(values: &[f64]) -> f64 {
+    let total: f64 = values.iter().sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+    values.iter().map(|value| (value / total).powi(2)).sum()
+}
+
+/// Bucket of a service by its share of the total: above `major`, above `moderate`, or neither
+///
+/// Without any spend every service is "minor".
+fn share_bucket(cost: f64, total: f64, major: f64, moderate: f64) -> &'static str {
+    let share = if total > 0.0 { cost / total } else { 0.0 };
+    if share > major {
+        "major"
+    } else if share > moderate {
+        "moderate"
+    } else {
+        "minor"
+    }
+}
+
+/// Shorten a label to at most `max` characters, ending it with an ellipsis when cut
+fn truncate_label(label: &str, max: usize) -> String {
+    if label.chars().count() <= max {
+        return label.to_string();
+    }
+    let mut truncated: String = label.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Add the costs of `other` into `into`, summing services, domains and matching time windows
+fn merge_rating(into: &mut RatingData, other: &RatingData) {
+    let maps = [
+        (&mut into.costs, &other.costs),
+        (&mut into.raw_costs, &other.raw_costs),
+        (&mut into.domain_costs, &other.domain_costs),
+        (&mut into.domain_raw_costs, &other.domain_raw_costs),
+    ];
+    for (target, source) in maps {
+        for (key, cost) in source {
+            *target.entry(key.clone()).or_insert(0.0) += cost;
         }
     }
-    
-    /// Create a redacted version of command arguments for safe logging
-    fn redact_sensitive_args(&self, args: &[String]) -> Vec<String> {
-        let mut redacted_args = Vec::new();
-        let mut i = 0;
-        
-        while i < args.len() {
-            let arg = &args[i];
-            
-            // Check if this is a sensitive parameter flag
-            if arg == "--os-password" || arg == "--os-project-id" {
-                redacted_args.push(arg.clone());
-                // If there's a next argument (the value), replace it with [REDACTED]
-                if i + 1 < args.len() {
-                    redacted_args.push("[REDACTED]".to_string());
-                    i += 1; // Skip the next argument since we've processed it
+
+    into.dataframe_count += other.dataframe_count;
+    into.row_count += other.row_count;
+
+    for bucket in &other.timeseries {
+        let existing = into.timeseries
+            .iter_mut()
+            .find(|existing| existing.begin == bucket.begin && existing.end == bucket.end);
+        match existing {
+            Some(existing) => {
+                existing.total_cost += bucket.total_cost;
+                for (service, cost) in &bucket.services {
+                    *existing.services.entry(service.clone()).or_insert(0.0) += cost;
                 }
-            } else {
-                redacted_args.push(arg.clone());
             }
-            
-            i += 1;
+            None => into.timeseries.push(bucket.clone()),
         }
-        
-        redacted_args
     }
+    into.timeseries.sort_by(|a, b| a.begin.cmp(&b.begin).then_with(|| a.end.cmp(&b.end)));
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use regex::Regex;
+/// Begin, end and date of each whole day the range from `begin_at` to `end_at` touches
+///
+/// Both are command date strings; a day is included when its midnight falls before `end_at`.
+fn day_ranges(begin_at: &str, end_at: &str) -> Vec<(NaiveDate, String, String)> {
+    let Ok(mut day) = NaiveDate::parse_from_str(&begin_at[..begin_at.len().min(10)], "%Y-%m-%d") else {
+        return Vec::new();
+    };
+    let midnight = |day: NaiveDate| day.format("%Y-%m-%dT00:00:00+00:00").to_string();
+    let mut days = Vec::new();
+    while midnight(day).as_str() < end_at {
+        let Some(next) = day.succ_opt() else { break };
+        days.push((day, midnight(day), midnight(next)));
+        day = next;
+    }
+    days
+}
 
-    #[test]
-    fn test_date_formatting() {
-        let config = Config {
-            bind_address: "0.0.0.0".to_string(),
-            port: 3001,
-            refresh_interval: std::time::Duration::from_secs(300),
-            currency_rate: 55.5,
-            openstack_command: "openstack".to_string(),
-            os_auth_url: String::new(),
-            os_username: String::new(),
-            os_password: String::new(),
-            os_project_id: String::new(),
-            os_region_name: "rc3-a".to_string(),
-            os_user_domain_name: "Default".to_string(),
-            cache_ttl_seconds: 300,
+/// Error raised by a single OpenStack command execution
+#[derive(Debug)]
+pub enum FetchError {
+    /// The command could not be started
+    Spawn(std::io::Error),
+    /// The command exited with a failure status
+    CommandFailed(String),
+    /// The command output was not a valid dataframes payload
+    Parse(serde_json::Error),
+    /// The command output was not valid UTF-8 (only with `STRICT_UTF8`)
+    InvalidOutput(std::str::Utf8Error),
+    /// No `MAX_CONCURRENT_FETCHES` slot freed up within the queue timeout
+    QueueTimeout(std::time::Duration),
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::Spawn(e) => write!(f, "failed to execute OpenStack command: {}", e),
+            FetchError::CommandFailed(status) => write!(f, "OpenStack command failed with status: {}", status),
+            FetchError::Parse(e) => write!(f, "failed to parse JSON data: {}", e),
+            FetchError::InvalidOutput(e) => write!(f, "OpenStack command output is not valid UTF-8: {}", e),
+            FetchError::QueueTimeout(timeout) => write!(f, "no fetch slot became free within {:?}", timeout),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+/// Data service for fetching and processing OpenStack data
+#[derive(Clone)]
+pub struct DataService {
+    /// Current configuration, replaced as a whole by `reload`
+    config: Arc<RwLock<Arc<Config>>>,
+    cache: Arc<OpenStackCache>,
+    metrics: Arc<Metrics>,
+    active_cloud: Arc<RwLock<Option<String>>>,
+    last_output: Arc<RwLock<Option<CapturedOutput>>>,
+    /// Recent dataframe buckets for rolling totals (only with `ROLLING_RETENTION_HOURS`)
+    rolling: Option<Arc<RwLock<RollingCosts>>>,
+    /// Slots bounding how many OpenStack commands run at once
+    fetch_permits: Arc<Semaphore>,
+    /// Compiled `GROUP_REGEX`, if set
+    group_regex: Arc<RwLock<Option<Regex>>>,
+    /// Charts built per cache entry and processing settings (only with `CACHE_PROCESSED_CHART`)
+    processed_charts: Option<Arc<RwLock<BoundedMap<ChartData>>>>,
+    /// Whether the last CLI output had the expected fields (see `SCHEMA_DRIFT_THRESHOLD`)
+    schema_ok: Arc<AtomicBool>,
+    /// Set once a fetch has succeeded, gating `/api/ready`
+    ready: Arc<AtomicBool>,
+    /// Per-service totals of completed days by cloud and day (only with `DAILY_AGGREGATES`)
+    daily: Option<Arc<RwLock<BoundedMap<RatingData>>>>,
+}
+
+impl DataService {
+    /// Create a new data service
+    pub fn new(config: Config, cache: Arc<OpenStackCache>) -> Self {
+        let active_cloud = config.os_clouds.first().cloned();
+        let rolling = config.rolling_retention.map(|retention| Arc::new(RwLock::new(RollingCosts::new(retention))));
+        let fetch_permits = Arc::new(Semaphore::new(config.max_concurrent_fetches));
+        // Invalid patterns were rejected by `Config::validate` at startup
+        let group_regex = config.group_regex.as_deref().and_then(|pattern| Regex::new(pattern).ok());
+        let processed_charts = config.cache_processed_chart.then(|| Arc::new(RwLock::new(BoundedMap::new(PROCESSED_CHARTS_MAX))));
+        let daily = config.daily_aggregates.then(|| Arc::new(RwLock::new(BoundedMap::new(DAILY_TOTALS_MAX))));
+        let max_ranges = config.range_stats_max;
+        Self {
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            cache,
+            metrics: Arc::new(Metrics::new().with_max_ranges(max_ranges)),
+            active_cloud: Arc::new(RwLock::new(active_cloud)),
+            last_output: Arc::new(RwLock::new(None)),
+            rolling,
+            fetch_permits,
+            group_regex: Arc::new(RwLock::new(group_regex)),
+            processed_charts,
+            schema_ok: Arc::new(AtomicBool::new(true)),
+            ready: Arc::new(AtomicBool::new(false)),
+            daily,
+        }
+    }
+
+    /// Current configuration
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Apply a reloaded configuration to subsequent fetches and processing
+    ///
+    /// Cached ratings and daily totals are kept: their keys include the settings
+    /// they were converted with (see `cache_scope`), so only those still matching
+    /// are reused. The processed charts are dropped.
+    pub fn reload(&self, config: Config) {
+        let group_regex = config.group_regex.as_deref().and_then(|pattern| Regex::new(pattern).ok());
+        *self.group_regex.write().unwrap_or_else(|e| e.into_inner()) = group_regex;
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+        if let Some(processed) = &self.processed_charts {
+            processed.write().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+
+    /// Cache scope of the ratings fetched from `cloud` with the current settings
+    fn cache_scope(&self, cloud: Option<&str>) -> String {
+        let config = self.config();
+        format!("{}@{}#{}", cloud.unwrap_or_default(), config.openstack_command, rating_fingerprint(&config))
+    }
+
+    /// Get the cache backing this service
+    pub fn cache(&self) -> &OpenStackCache {
+        &self.cache
+    }
+
+    /// Get the metrics recorded by this service
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Whether the last CLI output had the expected fields
+    pub fn schema_ok(&self) -> bool {
+        self.schema_ok.load(Ordering::Relaxed)
+    }
+
+    /// Whether any fetch has succeeded with fresh data, whoever asked for it
+    pub fn ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Get the last raw CLI output, if capturing is enabled and a command has run
+    pub fn last_output(&self) -> Option<CapturedOutput> {
+        self.last_output.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Keep a bounded, redacted copy of a command's output
+    fn capture_output(&self, output: &std::process::Output) {
+        let stdout = self.redact_output(&String::from_utf8_lossy(&output.stdout));
+        let truncated = stdout.len() > DEBUG_CAPTURE_MAX_BYTES;
+        let mut end = stdout.len().min(DEBUG_CAPTURE_MAX_BYTES);
+        while !stdout.is_char_boundary(end) {
+            end -= 1;
+        }
+        let captured = CapturedOutput {
+            captured_at: Utc::now().to_rfc3339(),
+            status: output.status.to_string(),
+            stdout: stdout[..end].to_string(),
+            truncated,
         };
-        let cache = Arc::new(OpenStackCache::new(std::time::Duration::from_secs(300)));
-        let service = DataService::new(config, cache.clone());
-        let date_string = service.get_date_string(None);
-        
-        // Test that the date matches the expected format: YYYY-MM-01T00:00:00+00:00
-        let date_regex = Regex::new(r"^\d{4}-\d{2}-01T00:00:00\+00:00$").unwrap();
-        assert!(date_regex.is_match(&date_string), "Date format should match YYYY-MM-01T00:00:00+00:00, got: {}", date_string);
+        *self.last_output.write().unwrap_or_else(|e| e.into_inner()) = Some(captured);
+    }
+
+    /// Mask the configured secrets and values of secret-looking keys
+    fn redact_output(&self, text: &str) -> String {
+        let mut redacted = SECRET_KEYS.replace_all(text, r#"${1}"[REDACTED]""#).into_owned();
+        let config = self.config();
+        let secrets = std::iter::once(&config.os_password)
+            .chain(config.api_token.as_ref())
+            .chain(config.token_scopes.iter().map(|(token, _)| token));
+        for secret in secrets.filter(|secret| !secret.is_empty()) {
+            redacted = redacted.replace(secret.as_str(), "[REDACTED]");
+        }
+        redacted
+    }
+
+    /// Get the named clouds that can be selected
+    pub fn clouds(&self) -> Vec<String> {
+        self.config().os_clouds.clone()
+    }
+
+    /// Get the cloud subsequent fetches run against, if clouds are configured
+    pub fn active_cloud(&self) -> Option<String> {
+        self.active_cloud.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Switch subsequent fetches to another configured cloud
+    ///
+    /// Returns `false` when the name is not one of the configured clouds.
+    pub fn set_active_cloud(&self, name: &str) -> bool {
+        if !self.config().os_clouds.iter().any(|cloud| cloud == name) {
+            return false;
+        }
+        *self.active_cloud.write().unwrap_or_else(|e| e.into_inner()) = Some(name.to_string());
+        info!("Active cloud switched to {}", name);
+        true
+    }
+
+    /// Fetch rating data (totals and time series) from OpenStack CLI with caching
+    ///
+    /// Failed commands are retried up to `fetch_retries` times. When every attempt
+    /// fails and an expired cache entry exists for the query, it is returned
+    /// tagged as stale instead of an empty result.
+    pub async fn fetch_data(&self, begin_at: Option<String>, end_at: Option<String>) -> FetchOutcome {
+        let (begin_at_date_string, end_at_date_string) = match self.resolve_date_range(begin_at, end_at) {
+            Ok(range) => range,
+            Err(reason) => return FetchOutcome::failed(format!("Invalid date range: {}", reason)),
+        };
+        
+        let range = (begin_at_date_string.clone(), end_at_date_string.clone());
+        let mut outcome = if self.config().rating_endpoints.is_empty() {
+            self.fetch_from(self.active_cloud(), begin_at_date_string, end_at_date_string).await
+        } else {
+            self.fetch_endpoints(begin_at_date_string, end_at_date_string).await
+        };
+        outcome.range = Some(range);
+        if outcome.error.is_none() && !outcome.stale {
+            self.ready.store(true, Ordering::Relaxed);
+        }
+        if let Some(rolling) = &self.rolling
+            && outcome.error.is_none()
+            && !outcome.stale
+        {
+            rolling.write().unwrap_or_else(|e| e.into_inner()).record(&outcome.rating.timeseries, Utc::now());
+        }
+        outcome
+    }
+
+    /// Sum the recorded costs over a trailing window, in display names and units
+    ///
+    /// Returns `None` when `ROLLING_RETENTION_HOURS` is unset.
+    pub fn rolling_sum(&self, window: std::time::Duration) -> Option<RollingSum> {
+        let rolling = self.rolling.as_ref()?;
+        let mut sum = rolling.read().unwrap_or_else(|e| e.into_inner()).sum(window, Utc::now());
+        sum.services = sum.services
+            .into_iter()
+            .map(|(service, cost)| (self.display_name(&service), self.round_cost(self.scale_cost(cost))))
+            .collect();
+        sum.total_cost = self.round_cost(self.scale_cost(sum.total_cost));
+        Some(sum)
+    }
+
+    /// Longest window `rolling_sum` can cover, if rolling totals are enabled
+    pub fn rolling_retention(&self) -> Option<std::time::Duration> {
+        self.rolling.as_ref().map(|rolling| rolling.read().unwrap_or_else(|e| e.into_inner()).retention())
+    }
+
+    /// Fetch every `RATING_ENDPOINTS` endpoint concurrently and sum their costs
+    ///
+    /// Endpoints that fail are left out and the outcome is marked partial; the
+    /// fetch only fails when no endpoint returned data.
+    async fn fetch_endpoints(&self, begin_at: String, end_at: String) -> FetchOutcome {
+        let mut tasks = tokio::task::JoinSet::new();
+        for endpoint in &self.config().rating_endpoints {
+            let service = self.clone();
+            let (endpoint, begin_at, end_at) = (endpoint.clone(), begin_at.clone(), end_at.clone());
+            tasks.spawn(async move {
+                let outcome = service.fetch_from(Some(endpoint.clone()), begin_at, end_at).await;
+                (endpoint, outcome)
+            });
+        }
+
+        let mut merged = FetchOutcome::default();
+        let mut all_cached = true;
+        let mut cache_keys = Some(Vec::new());
+        while let Some(joined) = tasks.join_next().await {
+            let (endpoint, outcome) = match joined {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!("Rating endpoint task failed: {}", e);
+                    continue;
+                }
+            };
+            if let Some(error) = outcome.error {
+                warn!("Rating endpoint {} failed: {}", endpoint, error);
+                merged.overloaded |= outcome.overloaded;
+                merged.endpoint_errors.insert(endpoint, error);
+                continue;
+            }
+            all_cached &= outcome.from_cache;
+            cache_keys = cache_keys.zip(outcome.cache_key).map(|(mut keys, key)| {
+                keys.push(key);
+                keys
+            });
+            merged.stale |= outcome.stale;
+            merge_rating(&mut merged.rating, &outcome.rating);
+            merged.rating.by_endpoint.insert(endpoint, outcome.rating);
+        }
+
+        if merged.rating.by_endpoint.is_empty() {
+            let errors: Vec<String> = merged.endpoint_errors
+                .iter()
+                .map(|(endpoint, error)| format!("{}: {}", endpoint, error))
+                .collect();
+            return FetchOutcome {
+                error: Some(format!("All rating endpoints failed ({})", errors.join("; "))),
+                endpoint_errors: merged.endpoint_errors,
+                overloaded: merged.overloaded,
+                ..FetchOutcome::default()
+            };
+        }
+
+        merged.from_cache = all_cached;
+        merged.cache_key = cache_keys.map(|mut keys| {
+            keys.sort();
+            keys.join("+")
+        });
+        merged.partial = !merged.endpoint_errors.is_empty();
+        merged
+    }
+
+    /// Fetch rating data from a single cloud, or with the `OS_*` credentials when `None`
+    ///
+    /// With `DAILY_AGGREGATES` the range is assembled day by day. Otherwise, with
+    /// `INCREMENTAL_FETCH`, a range ending today is split at today's midnight: the
+    /// days before come from the cache like any other query and only today is fetched
+    /// live, then both are merged.
+    async fn fetch_from(&self, cloud: Option<String>, begin_at_date_string: String, end_at_date_string: String) -> FetchOutcome {
+        if let Some(daily) = &self.daily {
+            return self.fetch_daily(daily, cloud, &begin_at_date_string, &end_at_date_string).await;
+        }
+        let Some(today) = self.incremental_split(&begin_at_date_string, &end_at_date_string) else {
+            return self.fetch_range(cloud, begin_at_date_string, end_at_date_string, true).await;
+        };
+        let history = self.fetch_range(cloud.clone(), begin_at_date_string, today.clone(), true).await;
+        if history.error.is_some() {
+            return history;
+        }
+        let live = self.fetch_range(cloud, today, end_at_date_string, false).await;
+        if live.error.is_some() {
+            return live;
+        }
+        debug!("Merged the costs before today with today's live costs");
+        let mut rating = history.rating;
+        merge_rating(&mut rating, &live.rating);
+        FetchOutcome {
+            rating,
+            // Today's part is never reused, so the merged data has no cache entry of its own
+            stale: history.stale || live.stale,
+            ..FetchOutcome::default()
+        }
+    }
+
+    /// Assemble a range from the totals of each of its days
+    ///
+    /// Days before today no longer change, so once fetched their totals are kept and
+    /// they are never fetched again; today and later days go through the cache like
+    /// any other query. Missing days are fetched concurrently, as many at once as
+    /// `MAX_CONCURRENT_FETCHES` allows.
+    async fn fetch_daily(&self, daily: &RwLock<BoundedMap<RatingData>>, cloud: Option<String>, begin_at_date_string: &str, end_at_date_string: &str) -> FetchOutcome {
+        // The day ranges are UTC midnights, so a day is complete once it has ended in UTC
+        let today = Utc::now().date_naive();
+        let scope = self.cache_scope(cloud.as_deref());
+        let mut totals = Vec::new();
+        let mut missing = Vec::new();
+        for (index, (day, begin_at, end_at)) in day_ranges(begin_at_date_string, end_at_date_string).into_iter().enumerate() {
+            let key = format!("{}:{}", scope, day);
+            let kept = daily.read().unwrap_or_else(|e| e.into_inner()).get(&key).cloned();
+            if kept.is_none() {
+                missing.push((index, key, day, begin_at, end_at));
+            }
+            totals.push(kept);
+        }
+        let fetched_days = missing.len();
+        let mut from_cache = true;
+        let mut stale = false;
+        let mut missing = missing.into_iter();
+        let mut fetches = JoinSet::new();
+        loop {
+            // Queued days would wait on the fetch slots and could hit FETCH_QUEUE_TIMEOUT
+            while fetches.len() < self.config().max_concurrent_fetches
+                && let Some((index, key, day, begin_at, end_at)) = missing.next()
+            {
+                let service = self.clone();
+                let cloud = cloud.clone();
+                fetches.spawn(async move { (index, key, day, service.fetch_range(cloud, begin_at, end_at, true).await) });
+            }
+            let Some(joined) = fetches.join_next().await else {
+                break;
+            };
+            let (index, key, day, outcome) = joined.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+            if outcome.error.is_some() {
+                return outcome;
+            }
+            from_cache &= outcome.from_cache;
+            stale |= outcome.stale;
+            if day < today && !outcome.stale {
+                daily.write().unwrap_or_else(|e| e.into_inner()).insert(key, outcome.rating.clone());
+            }
+            totals[index] = Some(outcome.rating);
+        }
+        debug!("Assembled the range from daily totals, {} day(s) fetched", fetched_days);
+        let mut rating = RatingData::default();
+        for day in totals.iter().flatten() {
+            merge_rating(&mut rating, day);
+        }
+        FetchOutcome {
+            rating,
+            // The days are cached separately, so the assembled data has no cache entry of its own
+            from_cache,
+            stale,
+            ..FetchOutcome::default()
+        }
+    }
+
+    /// Start of today as a command date string, when `INCREMENTAL_FETCH` should split the range there
+    ///
+    /// Ranges that end before today, or begin today, are fetched in one piece.
+    fn incremental_split(&self, begin_at_date_string: &str, end_at_date_string: &str) -> Option<String> {
+        if !self.config().incremental_fetch {
+            return None;
+        }
+        let today = Local::now().format("%Y-%m-%dT00:00:00+00:00").to_string();
+        (end_at_date_string[..10] == today[..10] && begin_at_date_string < today.as_str()).then_some(today)
+    }
+
+    /// Fetch rating data for one range, looking it up in the cache first when `use_cache` is set
+    ///
+    /// Fetched data is cached either way, so an expired entry can stand in during an outage.
+    async fn fetch_range(&self, cloud: Option<String>, begin_at_date_string: String, end_at_date_string: String, use_cache: bool) -> FetchOutcome {
+        let config = self.config();
+        let args = self.build_args(cloud.as_deref(), &begin_at_date_string, &end_at_date_string);
+        
+        // Generate a cache key from the cloud, command, rating settings and args
+        let cache_scope = self.cache_scope(cloud.as_deref());
+        let cache_key = self.cache.generate_key(&cache_scope, &args);
+        
+        // A replica only serves what the primary persisted, fresh or not
+        if config.read_only_replica {
+            return self.replica_outcome(cache_key).await;
+        }
+        
+        // Check cache first
+        if use_cache
+            && let Some(cached_data) = self.cache.get(&cache_key).await
+        {
+            info!("Using cached data for OpenStack query");
+            self.metrics.record_fetch(FetchResult::CacheHit);
+            return FetchOutcome {
+                rating: cached_data,
+                from_cache: true,
+                cache_key: Some(cache_key),
+                ..FetchOutcome::default()
+            };
+        }
+        
+        // Create a redacted version of args for logging
+        let redacted_args = self.redact_sensitive_args(&args);
+        let attempts = config.fetch_retries + 1;
+        let mut last_error = None;
+        let mut overloaded = false;
+        for attempt in 1..=attempts {
+            let (program, shown_args) = self.command_line(&redacted_args);
+            info!("Executing command (attempt {}/{}): {} {}", attempt, attempts, program, shown_args.join(" "));
+            let started = Instant::now();
+            let mut result = self.run_command(&args).await;
+            // Some CLI versions break the JSON on columns with embedded newlines
+            if config.parse_fallback
+                && let Err(FetchError::Parse(e)) = &result
+            {
+                warn!("Could not parse the OpenStack output ({}), retrying with only the Resources column (PARSE_FALLBACK)", e);
+                result = self.run_command(&resources_column_only(&args)).await;
+                if result.is_ok() {
+                    warn!("Fetched with only the Resources column; rows have no time window");
+                }
+            }
+            let elapsed = started.elapsed();
+            self.metrics.observe_fetch_duration(elapsed);
+            match result {
+                Ok(mut rating) => {
+                    self.metrics.observe_range_duration(&format!("{}/{}", begin_at_date_string, end_at_date_string), elapsed);
+                    info!("Successfully fetched data for {} services", rating.costs.len());
+                    if config.transform_command.is_some() {
+                        rating.costs = self.transform_costs(rating.costs).await;
+                        // Repricing works from the raw rating, so it has to carry the transform too
+                        let rate = if config.rating_is_currency { 1.0 } else { config.currency_rate };
+                        rating.raw_costs = rating.costs.iter().map(|(service, cost)| (service.clone(), cost * rate)).collect();
+                    }
+                    self.metrics.record_fetch(FetchResult::Success);
+                    
+                    // Cache the result with configured TTL
+                    self.cache.set(
+                        cache_key.clone(),
+                        rating.clone()
+                    ).await;
+                    self.forget_processed(&cache_key);
+                    
+                    return FetchOutcome {
+                        rating,
+                        cache_key: Some(cache_key),
+                        ..FetchOutcome::default()
+                    };
+                }
+                Err(e @ FetchError::QueueTimeout(_)) => {
+                    // Retrying would only queue again behind the same busy fetches
+                    warn!("OpenStack fetch not started: {}", e);
+                    overloaded = true;
+                    last_error = Some(e.to_string());
+                    break;
+                }
+                Err(e) => {
+                    warn!("OpenStack fetch attempt {}/{} failed: {}", attempt, attempts, e);
+                    last_error = Some(e.to_string());
+                    if attempt < attempts {
+                        tokio::time::sleep(config.fetch_retry_delay).await;
+                    }
+                }
+            }
+        }
+
+        self.metrics.record_fetch(FetchResult::Error);
+
+        // Last resort: keep showing the most recent data during an outage
+        if config.serve_expired_on_exhaustion
+            && let Some(expired) = self.cache.get_expired(&cache_key).await
+        {
+            warn!("All fetch attempts failed, serving expired cached data");
+            return FetchOutcome {
+                rating: expired,
+                from_cache: true,
+                stale: true,
+                ..FetchOutcome::default()
+            };
+        }
+
+        FetchOutcome {
+            overloaded,
+            ..FetchOutcome::failed(last_error.unwrap_or_else(|| "OpenStack fetch failed".to_string()))
+        }
+    }
+
+    /// Serve a range from the cache without running the CLI, for `READ_ONLY_REPLICA`
+    ///
+    /// An entry past its TTL is still the last good data the primary persisted,
+    /// so it is served tagged as stale.
+    async fn replica_outcome(&self, cache_key: String) -> FetchOutcome {
+        if let Some(cached) = self.cache.get(&cache_key).await {
+            self.metrics.record_fetch(FetchResult::CacheHit);
+            return FetchOutcome {
+                rating: cached,
+                from_cache: true,
+                cache_key: Some(cache_key),
+                ..FetchOutcome::default()
+            };
+        }
+        match self.cache.get_expired(&cache_key).await {
+            Some(expired) => FetchOutcome {
+                rating: expired,
+                from_cache: true,
+                stale: true,
+                ..FetchOutcome::default()
+            },
+            None => FetchOutcome::failed("The primary has not cached this range yet (READ_ONLY_REPLICA)".to_string()),
+        }
+    }
+
+    /// Arguments of the CLI command fetching one range, for a cloud or with the `OS_*` credentials
+    ///
+    /// Pure: nothing is run, so every auth mode and date combination can be checked directly.
+    fn build_args(&self, cloud: Option<&str>, begin_at_date_string: &str, end_at_date_string: &str) -> Vec<String> {
+        // Build arguments with authentication parameters
+        let mut args = Vec::new();
+        
+        // A selected cloud takes its credentials from clouds.yaml
+        if let Some(cloud) = cloud {
+            args.push("--os-cloud".to_string());
+            args.push(cloud.to_string());
+        } else {
+            self.push_auth_args(&mut args);
+        }
+        
+        // Add operator-supplied global options (validated at startup)
+        args.extend(self.config().openstack_extra_args.iter().cloned());
+        
+        // Add the main command arguments
+        args.extend([
+            "rating".to_string(),
+            "dataframes".to_string(),
+            "get".to_string(),
+            "-b".to_string(),
+            begin_at_date_string.to_string(),
+            "-e".to_string(),
+            end_at_date_string.to_string(),
+            "-c".to_string(),
+            "Begin".to_string(),
+            "-c".to_string(),
+            "End".to_string(),
+            "-c".to_string(),
+            "Resources".to_string(),
+            "-f".to_string(),
+            "json".to_string(),
+        ]);
+        args
+    }
+
+    /// Redacted commands `fetch_data` would run for a range, one per cloud queried, without running them
+    ///
+    /// Shows the whole range as one command; `DAILY_AGGREGATES` and `INCREMENTAL_FETCH`
+    /// split it into several ranges with otherwise identical arguments.
+    pub fn preview_commands(&self, begin_at: Option<String>, end_at: Option<String>) -> Result<Vec<CommandPreview>, String> {
+        let config = self.config();
+        let (begin_at_date_string, end_at_date_string) = self.resolve_date_range(begin_at, end_at)?;
+        let clouds = if config.rating_endpoints.is_empty() {
+            vec![self.active_cloud()]
+        } else {
+            config.rating_endpoints.iter().cloned().map(Some).collect()
+        };
+        Ok(clouds
+            .into_iter()
+            .map(|cloud| {
+                let args = self.build_args(cloud.as_deref(), &begin_at_date_string, &end_at_date_string);
+                let (program, args) = self.command_line(&self.redact_sensitive_args(&args));
+                CommandPreview { cloud, program, args }
+            })
+            .collect())
+    }
+
+    /// Add the `OS_*` authentication parameters that are set
+    fn push_auth_args(&self, args: &mut Vec<String>) {
+        let config = self.config();
+        if !config.os_auth_url.is_empty() {
+            args.push("--os-auth-url".to_string());
+            args.push(config.os_auth_url.clone());
+        }
+        
+        if !config.os_username.is_empty() {
+            args.push("--os-username".to_string());
+            args.push(config.os_username.clone());
+        }
+        
+        // Over SSH the password travels in the environment instead (see `cli_command`)
+        if !config.os_password.is_empty() && config.ssh_host.is_none() {
+            args.push("--os-password".to_string());
+            args.push(config.os_password.clone());
+        }
+        
+        // System scope replaces the project scope; the differing args also keep the cache keys apart
+        if let Some(scope) = &config.os_system_scope {
+            args.push("--os-system-scope".to_string());
+            args.push(scope.clone());
+        } else if !config.os_project_id.is_empty() {
+            args.push("--os-project-id".to_string());
+            args.push(config.os_project_id.clone());
+        }
+        
+        if !config.os_region_name.is_empty() {
+            args.push("--os-region-name".to_string());
+            args.push(config.os_region_name.clone());
+        }
+        
+        if !config.os_user_domain_name.is_empty() {
+            args.push("--os-user-domain-name".to_string());
+            args.push(config.os_user_domain_name.clone());
+        }
+    }
+
+    /// Ask the OpenStack CLI for its version, or report "unavailable"
+    pub async fn cli_version(&self) -> String {
+        let config = self.config();
+        if config.read_only_replica {
+            return "unavailable (read-only replica)".to_string();
+        }
+        let output = tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            {
+                self.cli_command(&config.openstack_command, &["--version".to_string()]).output()
+            },
+        )
+        .await;
+        match output {
+            Ok(Ok(output)) if output.status.success() => {
+                // Older clients print the version on stderr
+                let text = if output.stdout.is_empty() { &output.stderr } else { &output.stdout };
+                let text = String::from_utf8_lossy(text);
+                match text.lines().next().map(str::trim) {
+                    Some(version) if !version.is_empty() => version.to_string(),
+                    _ => "unavailable".to_string(),
+                }
+            }
+            Ok(Ok(output)) => {
+                warn!("OpenStack CLI version check failed with status {}", output.status);
+                "unavailable".to_string()
+            }
+            Ok(Err(e)) => {
+                warn!("Could not run the OpenStack CLI to read its version: {}", e);
+                "unavailable".to_string()
+            }
+            Err(_) => {
+                warn!("OpenStack CLI version check timed out");
+                "unavailable".to_string()
+            }
+        }
+    }
+
+    /// Run the OpenStack command once and parse its output
+    async fn run_command(&self, args: &[String]) -> Result<RatingData, FetchError> {
+        let config = self.config();
+        let timeout = config.fetch_queue_timeout;
+        let _permit = match tokio::time::timeout(timeout, self.fetch_permits.acquire()).await {
+            Ok(Ok(permit)) => permit,
+            // The semaphore is never closed, so only the timeout can fail here
+            _ => return Err(FetchError::QueueTimeout(timeout)),
+        };
+        // Captured output needs the whole of stdout, so it always takes the buffered path
+        if config.streaming_parse && !config.debug_capture_output {
+            return self.run_command_streaming(args).await;
+        }
+        let output = self.spawn_with_fallback(|command| async move { self.spawn_command(&command, args).await }).await?;
+
+        if config.debug_capture_output {
+            self.capture_output(&output);
+        }
+
+        if !output.status.success() {
+            return Err(self.command_failed(output.status, &output.stderr, &String::from_utf8_lossy(&output.stdout)));
+        }
+        self.log_stderr(&output.stderr);
+
+        let json_str = self.decode_output(&output.stdout)?;
+        match serde_json::from_str::<Vec<ResourceWrapper>>(&json_str) {
+            Ok(resources) => {
+                let totals = self.total_resources(resources);
+                self.check_schema(&totals.schema);
+                Ok(totals.finish())
+            }
+            Err(e) => {
+                warn!("Raw output: {}", json_str);
+                Err(FetchError::Parse(e))
+            }
+        }
+    }
+
+    /// Run the OpenStack command once, parsing its output as it is read (`STREAMING_PARSE`)
+    ///
+    /// The dataframes are deserialized straight from the pipe and added to the totals
+    /// one at a time, so neither the output text nor the whole list of rows is held in
+    /// memory; output that fails to parse isn't logged.
+    async fn run_command_streaming(&self, args: &[String]) -> Result<RatingData, FetchError> {
+        let config = self.config();
+        let mut child = self.spawn_with_fallback(|command| async move {
+            self.cli_command(&command, args).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+        }).await?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let handle = tokio::runtime::Handle::current();
+        let mut totals = ResourceTotals::new(config.clone());
+        let strict_utf8 = config.strict_utf8;
+        // Warnings from the parse go wherever this task's go
+        let dispatch = tracing::dispatcher::get_default(|dispatch| dispatch.clone());
+        // serde_json reads synchronously, so the parse runs on the blocking pool
+        let parse = tokio::task::spawn_blocking(move || tracing::dispatcher::with_default(&dispatch, || {
+            let raw = BlockingRead { inner: stdout, handle };
+            let stream: Box<dyn std::io::Read + Send> = if strict_utf8 { Box::new(raw) } else { Box::new(LossyUtf8::new(raw)) };
+            let mut reader = std::io::BufReader::new(stream);
+            let parsed = parse_dataframes(&mut reader, &mut totals);
+            if parsed.is_err() {
+                // Read the rest, or the command blocks on a full pipe and never exits
+                let _ = std::io::copy(&mut reader, &mut std::io::sink());
+            }
+            parsed.map(|()| totals)
+        }));
+        let mut error_output = Vec::new();
+        // Reading stderr meanwhile keeps the command from blocking on a full pipe
+        let (_, parsed) = tokio::join!(stderr.read_to_end(&mut error_output), parse);
+        let status = child.wait().await.map_err(FetchError::Spawn)?;
+
+        if !status.success() {
+            return Err(self.command_failed(status, &error_output, ""));
+        }
+        self.log_stderr(&error_output);
+
+        let totals = parsed.expect("the streaming parse does not panic").map_err(FetchError::Parse)?;
+        self.check_schema(&totals.schema);
+        Ok(totals.finish())
+    }
+
+    /// Start the CLI with `spawn`, retrying with `OPENSTACK_COMMAND_FALLBACK` if it cannot start
+    async fn spawn_with_fallback<T, F>(&self, spawn: impl Fn(String) -> F) -> Result<T, FetchError>
+    where
+        F: Future<Output = std::io::Result<T>>,
+    {
+        let config = self.config();
+        let mut spawned = spawn(config.openstack_command.clone()).await;
+        // Only a command that cannot start falls back; a failing query would fail the same way
+        if let (Err(e), Some(fallback)) = (&spawned, &config.openstack_command_fallback) {
+            warn!(
+                "Failed to run {}: {}; switching to OPENSTACK_COMMAND_FALLBACK {}",
+                config.openstack_command, e, fallback
+            );
+            spawned = spawn(fallback.clone()).await;
+        }
+        spawned.map_err(|e| {
+            warn!("Make sure the OpenStack CLI is installed and in your PATH");
+            FetchError::Spawn(e)
+        })
+    }
+
+    /// Log the output of a command that exited with `status` and describe the failure
+    fn command_failed(&self, status: std::process::ExitStatus, stderr: &[u8], stdout: &str) -> FetchError {
+        let stderr = String::from_utf8_lossy(stderr);
+        if !stderr.is_empty() {
+            warn!("Error output: {}", stderr.trim());
+        }
+        if !stdout.is_empty() {
+            warn!("Standard output: {}", stdout.trim());
+        }
+        
+        // Check for common authentication errors
+        if stderr.contains("auth-url") || stderr.contains("Missing value") {
+            warn!("OpenStack authentication not configured. Please set up your OpenStack credentials.");
+            warn!("You can do this by sourcing an OpenStack RC file or setting environment variables.");
+            warn!("Example: source ~/openstack-rc.sh");
+        }
+        
+        FetchError::CommandFailed(status.to_string())
+    }
+
+    /// Log the error output of a command that succeeded
+    fn log_stderr(&self, stderr: &[u8]) {
+        // Wrappers often print deprecation notices on success; only alarm when asked to
+        let stderr = String::from_utf8_lossy(stderr);
+        if !stderr.trim().is_empty() {
+            if self.config().warn_on_stderr {
+                warn!("OpenStack command succeeded with error output: {}", stderr.trim());
+            } else {
+                debug!("OpenStack command succeeded with error output: {}", stderr.trim());
+            }
+        }
+    }
+
+    /// Run the CLI `command` with `args` and wait for its output
+    async fn spawn_command(&self, command: &str, args: &[String]) -> std::io::Result<std::process::Output> {
+        self.cli_command(command, args).output().await
+    }
+
+    /// Record whether parsed rows have a service and rating, warning about schema drift
+    ///
+    /// The output drifted when more than `SCHEMA_DRIFT_THRESHOLD` of the rows lack either,
+    /// which usually means a CloudKitty upgrade renamed the fields.
+    fn check_schema(&self, tally: &SchemaTally) {
+        let ok = tally.rows == 0 || tally.incomplete as f64 / tally.rows as f64 <= self.config().schema_drift_threshold;
+        if !ok {
+            warn!(
+                schema_drift = true,
+                rows = tally.rows,
+                missing_service = tally.missing_service,
+                missing_rating = tally.missing_rating,
+                sample = ?tally.sample,
+                "CLI output schema drift: {} of {} rows lack a service or rating",
+                tally.incomplete,
+                tally.rows
+            );
+        }
+        self.schema_ok.store(ok, Ordering::Relaxed);
+    }
+
+    /// Decode CLI output as UTF-8, lossily unless `STRICT_UTF8` is set
+    ///
+    /// Replaced bytes can corrupt a service name and split it into two entries,
+    /// so lossy decoding is logged with the offset of the first invalid byte.
+    fn decode_output<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, str>, FetchError> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Ok(Cow::Borrowed(text)),
+            Err(e) if self.config().strict_utf8 => Err(FetchError::InvalidOutput(e)),
+            Err(e) => {
+                warn!("OpenStack command output has invalid UTF-8 at byte offset {}, replacing invalid bytes", e.valid_up_to());
+                Ok(String::from_utf8_lossy(bytes))
+            }
+        }
+    }
+
+    /// Pipe the cost map through the configured transform command
+    ///
+    /// The command line is run by `sh -c`, so it may quote its arguments. The map
+    /// is written as JSON to the command's stdin and a JSON cost map is read back
+    /// from its stdout. Any failure or timeout keeps the original map.
+    ///
+    /// Only the per-service totals are transformed; the timeseries and the
+    /// `SPLIT_BY=domain` totals keep the costs as fetched.
+    async fn transform_costs(&self, costs: HashMap<String, f64>) -> HashMap<String, f64> {
+        let config = self.config();
+        let Some(command) = &config.transform_command else {
+            return costs;
+        };
+        if command.trim().is_empty() {
+            return costs;
+        }
+
+        let transform = async {
+            let mut child = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| format!("failed to start: {}", e))?;
+            let input = serde_json::to_vec(&costs).map_err(|e| e.to_string())?;
+            // The input is written while the output is drained, or a large map fills both pipes
+            let stdin = child.stdin.take();
+            let writer = tokio::spawn(async move {
+                match stdin {
+                    Some(mut stdin) => stdin.write_all(&input).await,
+                    None => Ok(()),
+                }
+            });
+            let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+            // A command that does not read its input closes the pipe early, which is fine
+            match writer.await.map_err(|e| e.to_string())? {
+                Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(format!("failed to write input: {}", e)),
+                _ => {}
+            }
+            if !output.status.success() {
+                return Err(format!(
+                    "exited with status {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            serde_json::from_slice::<HashMap<String, f64>>(&output.stdout).map_err(|e| format!("invalid output: {}", e))
+        };
+
+        match tokio::time::timeout(config.transform_timeout, transform).await {
+            Ok(Ok(transformed)) => {
+                info!("Transform command produced costs for {} services", transformed.len());
+                transformed
+            }
+            Ok(Err(e)) => {
+                warn!("Transform command {} failed, using untransformed costs: {}", command, e);
+                costs
+            }
+            Err(_) => {
+                warn!("Transform command {} timed out, using untransformed costs", command);
+                costs
+            }
+        }
+    }
+
+    /// Load exported dataframe files from the backfill directory as monthly snapshots
+    ///
+    /// Every `*.json` file is parsed as a dataframes payload and the costs are
+    /// aggregated per calendar month of each dataframe's `Begin` timestamp.
+    /// This is a one-time bootstrap and never queries OpenStack.
+    pub fn load_backfill(&self) -> Vec<HistoryPoint> {
+        match &self.config().backfill_dir {
+            Some(dir) => self.load_backfill_dir(Path::new(dir)),
+            None => Vec::new(),
+        }
+    }
+
+    fn load_backfill_dir(&self, dir: &Path) -> Vec<HistoryPoint> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Failed to read backfill directory {}: {}", dir.display(), e);
+                return Vec::new();
+            }
+        };
+
+        let mut months: BTreeMap<String, Vec<ResourceWrapper>> = BTreeMap::new();
+        for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let parsed = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|content| serde_json::from_str::<Vec<ResourceWrapper>>(&content).map_err(|e| e.to_string()));
+            match parsed {
+                Ok(wrappers) => {
+                    info!("Loaded {} dataframes from backfill file {}", wrappers.len(), path.display());
+                    for wrapper in wrappers {
+                        match wrapper.begin.as_deref().and_then(|begin| begin.get(..7)) {
+                            Some(month) => months.entry(month.to_string()).or_default().push(wrapper),
+                            None => warn!("Skipping backfill dataframe without a Begin timestamp in {}", path.display()),
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to parse backfill file {}: {}", path.display(), e),
+            }
+        }
+
+        months
+            .into_iter()
+            .map(|(month, wrappers)| {
+                let chart = self.process_data(&self.process_resources(wrappers));
+                HistoryPoint::from_chart(format!("{}-01T00:00:00+00:00", month), &chart)
+            })
+            .collect()
+    }
+
+    /// Process fetched resources into per-service totals and a time series (see `ResourceTotals`)
+    fn process_resources(&self, resources: Vec<ResourceWrapper>) -> RatingData {
+        self.total_resources(resources).finish()
+    }
+
+    fn total_resources(&self, resources: Vec<ResourceWrapper>) -> ResourceTotals {
+        let mut totals = ResourceTotals::new(self.config());
+        for wrapped in resources {
+            totals.add(wrapped);
+        }
+        totals
+    }
+
+    /// Process a fetch outcome into chart-ready format, keeping its freshness and error status
+    pub fn process_outcome(&self, outcome: FetchOutcome) -> ChartData {
+        let config = self.config();
+        let mut chart = self.processed_chart(&outcome);
+        if config.include_run_rate
+            && outcome.error.is_none()
+            && let Some((begin_at, end_at)) = &outcome.range
+        {
+            self.apply_run_rate(&mut chart, begin_at, end_at);
+        }
+        if config.include_row_counts && outcome.error.is_none() {
+            chart.dataframe_count = Some(outcome.rating.dataframe_count);
+            chart.row_count = Some(outcome.rating.row_count);
+        }
+        if !chart.anomalies.is_empty() {
+            warn!("Clamped implausible costs to SERVICE_COST_CAP for: {}", chart.anomalies.join(", "));
+        }
+        chart.stale = outcome.stale;
+        chart.partial = outcome.partial;
+        chart.range = outcome.range;
+        if outcome.error.is_some() {
+            // An empty result from a failed fetch is not a genuine zero bill
+            chart.zero_cost = false;
+            chart.error = outcome.error;
+        }
+        chart
+    }
+
+    /// Chart the ratings of an outcome, reusing the chart built from the same cache entry
+    ///
+    /// Only fresh charts are remembered and only cache hits reuse them, so a refetch
+    /// always reprocesses. Without `CACHE_PROCESSED_CHART` this is `process_data`.
+    fn processed_chart(&self, outcome: &FetchOutcome) -> ChartData {
+        let key = outcome
+            .cache_key
+            .as_ref()
+            .filter(|_| outcome.error.is_none() && !outcome.stale)
+            .map(|cache_key| processed_key(cache_key, &processing_fingerprint(&self.config())));
+        let (Some(processed), Some(key)) = (&self.processed_charts, key) else {
+            return self.process_data(&outcome.rating);
+        };
+        if outcome.from_cache
+            && let Some(chart) = processed.read().unwrap_or_else(|e| e.into_inner()).get(&key)
+        {
+            debug!("Reusing the chart built from the cached ratings");
+            return ChartData {
+                last_updated: self.format_timestamp(Utc::now()),
+                ..chart.clone()
+            };
+        }
+        let chart = self.process_data(&outcome.rating);
+        processed.write().unwrap_or_else(|e| e.into_inner()).insert(key, chart.clone());
+        chart
+    }
+
+    /// Drop the charts built from a cache entry that was just replaced
+    fn forget_processed(&self, cache_key: &str) {
+        if let Some(processed) = &self.processed_charts {
+            processed
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .retain(|key| key.rsplit_once('/').is_none_or(|(entry, _)| entry != cache_key));
+        }
+    }
+
+    /// Drop the kept totals of completed days, so they are fetched again
+    pub fn clear_daily(&self) {
+        if let Some(daily) = &self.daily {
+            daily.write().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+
+    /// Set the daily run-rate of the range `begin_at`..`end_at` and its monthly projection
+    ///
+    /// Partial days count as whole ones, so an inclusive single-day range divides by 1.
+    pub fn apply_run_rate(&self, chart: &mut ChartData, begin_at: &str, end_at: &str) {
+        let (Ok(begin), Ok(end)) = (DateTime::parse_from_rfc3339(begin_at), DateTime::parse_from_rfc3339(end_at)) else {
+            return;
+        };
+        let seconds = (end - begin).num_seconds().max(0);
+        let days = ((seconds + 86_399) / 86_400).max(1);
+        let daily = chart.total_cost / days as f64;
+        let month_start = begin.date_naive().with_day(1).unwrap_or(begin.date_naive());
+        let month_days = month_start
+            .checked_add_months(chrono::Months::new(1))
+            .map_or(30, |next_month| (next_month - month_start).num_days());
+        chart.normalized_daily_total = Some(self.round_cost(daily));
+        chart.projected_monthly_total = Some(self.round_cost(daily * month_days as f64));
+    }
+
+    /// Record in `chart` how each service moved since the `previous` chart, if enabled
+    ///
+    /// Failed refreshes have nothing to compare, a failed previous chart would make
+    /// every service look new, and a chart of another date range isn't comparable,
+    /// so all three leave the deltas empty.
+    pub fn apply_deltas(&self, chart: &mut ChartData, previous: &ChartData) {
+        if !self.config().include_deltas {
+            return;
+        }
+        let deltas = if chart.error.is_none() && previous.error.is_none() && chart.range == previous.range {
+            chart.deltas_since(previous)
+        } else {
+            BTreeMap::new()
+        };
+        chart.deltas = Some(deltas);
+    }
+
+    /// Report services incurring cost for the first time, if enabled
+    ///
+    /// Failed and stale fetches are skipped so an outage can't make services
+    /// look new once they come back.
+    pub async fn alert_new_services(&self, outcome: &FetchOutcome) -> Vec<String> {
+        if !self.config().alert_on_new_service || outcome.error.is_some() || outcome.stale {
+            return Vec::new();
+        }
+        let billed = outcome.rating.costs.iter().filter(|(_, cost)| **cost > 0.0).map(|(service, _)| service);
+        let mut new_services = self.cache.observe_services(billed).await;
+        new_services.sort();
+        if !new_services.is_empty() {
+            warn!("New services in the bill: {}", new_services.join(", "));
+        }
+        new_services
+    }
+
+    /// Whether `chart` differs enough from `previous` to replace it (see `MIN_CHANGE_PERCENT`)
+    ///
+    /// Changes in the set of services or in the error, stale or partial state always
+    /// count; costs count when the total or any service moved by more than the
+    /// threshold, relative to its previous value.
+    pub fn changed_significantly(&self, chart: &ChartData, previous: &ChartData) -> bool {
+        let threshold = self.config().min_change_percent;
+        if threshold <= 0.0
+            || chart.error != previous.error
+            || chart.stale != previous.stale
+            || chart.partial != previous.partial
+            || chart.keys().len() != previous.keys().len()
+        {
+            return true;
+        }
+        let exceeds = |current: f64, before: f64| {
+            if before == 0.0 {
+                current != 0.0
+            } else {
+                ((current - before) / before).abs() * 100.0 > threshold
+            }
+        };
+        let before: HashMap<&String, f64> = previous.keys().iter().zip(previous.values.iter().copied()).collect();
+        exceeds(chart.total_cost, previous.total_cost)
+            || chart.keys().iter().zip(&chart.values).any(|(service, cost)| match before.get(service) {
+                Some(before) => exceeds(*cost, *before),
+                None => true,
+            })
+    }
+
+    /// Process data into chart-ready format
+    pub fn process_data(&self, rating: &RatingData) -> ChartData {
+        self.metrics.record_chart_build();
+        self.build_chart(rating, |service| self.display_name(service))
+    }
+
+    /// Roll service costs up into the configured categories and chart them
+    ///
+    /// Services without a category are grouped under "Other".
+    pub fn process_categories(&self, rating: &RatingData) -> ChartData {
+        let mut rolled_up = RatingData::default();
+        for (service, cost) in &rating.costs {
+            let category = self.category_of(service);
+            *rolled_up.costs.entry(category.clone()).or_insert(0.0) += cost;
+            *rolled_up.raw_costs.entry(category).or_insert(0.0) +=
+                rating.raw_costs.get(service).copied().unwrap_or(0.0);
+        }
+        self.build_chart(&rolled_up, str::to_string)
+    }
+
+    /// Total costs per group extracted with `GROUP_REGEX`
+    ///
+    /// Services the regex doesn't match, or whose first capture group is empty,
+    /// are totalled under "ungrouped" (all of them when `GROUP_REGEX` is unset).
+    /// Only the `allowed` services are totalled when limited, and group names are
+    /// anonymized like service names since they are taken from them.
+    pub fn process_groups(&self, rating: &RatingData, allowed: Option<&[String]>) -> ChartData {
+        let group_regex = self.group_regex.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut grouped = RatingData::default();
+        let visible = rating.costs.iter().filter(|(service, _)| allowed.is_none_or(|allowed| allowed.contains(service)));
+        for (service, cost) in visible {
+            let group = group_regex
+                .as_ref()
+                .and_then(|regex| regex.captures(service))
+                .and_then(|captures| captures.get(1))
+                .map(|group| group.as_str())
+                .filter(|group| !group.is_empty())
+                .unwrap_or("ungrouped");
+            *grouped.costs.entry(group.to_string()).or_insert(0.0) += cost;
+            *grouped.raw_costs.entry(group.to_string()).or_insert(0.0) +=
+                rating.raw_costs.get(service).copied().unwrap_or(0.0);
+        }
+        self.build_chart(&grouped, |group| match group {
+            "ungrouped" => group.to_string(),
+            group => self.display_name(group),
+        })
+    }
+
+    /// Chart the per-domain costs collected with `SPLIT_BY=domain`
+    pub fn process_domains(&self, rating: &RatingData) -> ChartData {
+        let by_domain = RatingData {
+            costs: rating.domain_costs.clone(),
+            raw_costs: rating.domain_raw_costs.clone(),
+            ..RatingData::default()
+        };
+        self.build_chart(&by_domain, str::to_string)
+    }
+
+    /// Get the configured category of a service
+    fn category_of(&self, service: &str) -> String {
+        self.config()
+            .service_categories
+            .iter()
+            .find(|(_, services)| services.iter().any(|s| s == service))
+            .map_or("Other", |(category, _)| category.as_str())
+            .to_string()
+    }
+
+    /// Sort, scale and round costs into chart data, labelling each key with `label`
+    fn build_chart(&self, rating: &RatingData, label: impl Fn(&str) -> String) -> ChartData {
+        let config = self.config();
+        let mut sorted_data: Vec<(String, f64)> = rating.costs.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        sorted_data.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let full_labels: Vec<String> = sorted_data.iter().map(|(service, _)| label(service)).collect();
+        let (labels, full_labels) = match config.max_label_length {
+            Some(max) => (full_labels.iter().map(|label| truncate_label(label, max)).collect(), full_labels),
+            None => (full_labels, Vec::new()),
+        };
+        let values: Vec<f64> = sorted_data.iter().map(|(_, cost)| self.round_cost(self.scale_cost(*cost))).collect();
+        let raw_costs: Vec<f64> = sorted_data
+            .iter()
+            .map(|(service, _)| rating.raw_costs.get(service).copied().unwrap_or(0.0))
+            .collect();
+        let raw_values = if config.include_raw_values { raw_costs.clone() } else { Vec::new() };
+        let currencies = config
+            .currency_rates
+            .iter()
+            .map(|(code, rate)| {
+                // Scaled like `values`, so every currency is in the same display unit
+                let values: Vec<f64> = raw_costs.iter().map(|raw| self.round_cost(self.scale_cost(raw / rate))).collect();
+                let total_cost = values.iter().sum();
+                (code.clone(), CurrencyCosts { total_cost, values })
+            })
+            .collect();
+        let mut chart = ChartData {
+            labels,
+            full_labels,
+            values,
+            raw_values,
+            last_updated: self.format_timestamp(Utc::now()),
+            sampled: config.sample_rate < 1.0,
+            currencies,
+            raw_costs,
+            ..ChartData::empty()
+        };
+        if let Some(cap) = config.service_cost_cap {
+            // Clamped costs keep their sort position at the top; `raw_values` still carry the originals
+            chart.anomalies = chart
+                .values
+                .iter()
+                .zip(chart.keys())
+                .filter(|(value, _)| **value > cap)
+                .map(|(_, key)| key.clone())
+                .collect();
+            for value in &mut chart.values {
+                *value = value.min(cap);
+            }
+        }
+        self.summarize(&mut chart);
+        chart
+    }
+
+    /// Recompute the total and the statistics of a chart from its values
+    fn summarize(&self, chart: &mut ChartData) {
+        chart.total_cost = chart.values.iter().sum();
+        chart.service_count = chart.labels.len();
+        chart.average_cost = if chart.service_count > 0 {
+            self.round_cost(chart.total_cost / chart.service_count as f64)
+        } else {
+            0.0
+        };
+        // Values are sorted by descending cost
+        let ascending: Vec<f64> = chart.values.iter().rev().copied().collect();
+        chart.p50 = self.round_cost(percentile(&ascending, 0.5));
+        chart.p90 = self.round_cost(percentile(&ascending, 0.9));
+        chart.max_service_cost = ascending.last().copied().unwrap_or(0.0);
+        chart.zero_cost = chart.total_cost == 0.0;
+        let config = self.config();
+        chart.categories = chart
+            .values
+            .iter()
+            .map(|&cost| share_bucket(cost, chart.total_cost, config.major_share_threshold, config.moderate_share_threshold).to_string())
+            .collect();
+        chart.formatted_total = format_amount(config.currency_format.as_ref(), chart.total_cost, &config.cost_unit_label);
+        chart.formatted_average_cost = format_amount(config.currency_format.as_ref(), chart.average_cost, &config.cost_unit_label);
+        chart.formatted_values = chart
+            .values
+            .iter()
+            .map(|&cost| format_amount(config.currency_format.as_ref(), cost, &config.cost_unit_label))
+            .collect();
+        if config.include_concentration {
+            chart.concentration = Some(herfindahl(&chart.values));
+            chart.top_service_share = Some(if chart.zero_cost { 0.0 } else { chart.max_service_cost / chart.total_cost });
+        }
+    }
+
+    /// Narrow a chart to the `allowed` services, recomputing its total and statistics
+    ///
+    /// Used for tokens scoped by `TOKEN_SCOPES`; the chart passed in is left untouched.
+    pub fn restrict(&self, chart: &ChartData, allowed: &[String]) -> ChartData {
+        let keep: Vec<bool> = chart.keys().iter().map(|key| allowed.contains(key)).collect();
+        let mut restricted = ChartData {
+            labels: retain(&chart.labels, &keep),
+            full_labels: retain(&chart.full_labels, &keep),
+            values: retain(&chart.values, &keep),
+            raw_values: retain(&chart.raw_values, &keep),
+            raw_costs: retain(&chart.raw_costs, &keep),
+            anomalies: chart.anomalies.iter().filter(|service| allowed.contains(service)).cloned().collect(),
+            currencies: chart
+                .currencies
+                .iter()
+                .map(|(code, costs)| {
+                    let values = retain(&costs.values, &keep);
+                    (code.clone(), CurrencyCosts { total_cost: values.iter().sum(), values })
+                })
+                .collect(),
+            deltas: chart.deltas.as_ref().map(|deltas| {
+                deltas.iter().filter(|(service, _)| allowed.contains(service)).map(|(k, v)| (k.clone(), *v)).collect()
+            }),
+            ..chart.clone()
+        };
+        self.summarize(&mut restricted);
+        // The run-rate scales with the total, as when repricing
+        if chart.total_cost > 0.0 {
+            let ratio = restricted.total_cost / chart.total_cost;
+            restricted.normalized_daily_total = chart.normalized_daily_total.map(|daily| self.round_cost(daily * ratio));
+            restricted.projected_monthly_total = chart.projected_monthly_total.map(|monthly| self.round_cost(monthly * ratio));
+        }
+        restricted
+    }
+
+    /// Reprice a chart with another currency rate, recomputing its total and statistics
+    ///
+    /// Used for one-off `?currency_rate=` overrides; the chart passed in is left untouched.
+    /// Every value is repriced from its raw rating, then scaled, rounded and clamped to
+    /// `SERVICE_COST_CAP` again, so rounding is never applied twice. Labels carry over
+    /// and deltas are scaled by the ratio of the rates.
+    pub fn reprice(&self, chart: &ChartData, currency_rate: f64) -> ChartData {
+        let config = self.config();
+        let configured_rate = if config.rating_is_currency { 1.0 } else { config.currency_rate };
+        let ratio = configured_rate / currency_rate;
+        let mut repriced = ChartData {
+            deltas: chart.deltas.as_ref().map(|deltas| {
+                deltas.iter().map(|(service, delta)| (service.clone(), self.round_cost(delta * ratio))).collect()
+            }),
+            ..chart.clone()
+        };
+        let keys = chart.keys();
+        let unclamped: Vec<f64> = chart
+            .values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| match chart.raw_costs.get(index) {
+                Some(raw) => self.round_cost(self.scale_cost(raw / currency_rate)),
+                None => self.round_cost(value * ratio),
+            })
+            .collect();
+        repriced.values = unclamped.clone();
+        if let Some(cap) = config.service_cost_cap {
+            repriced.anomalies = keys.iter().zip(&unclamped).filter(|(_, value)| **value > cap).map(|(key, _)| key.clone()).collect();
+            for value in &mut repriced.values {
+                *value = value.min(cap);
+            }
+        }
+        self.summarize(&mut repriced);
+        // The run-rate scales with the total, whatever the range was
+        if chart.total_cost > 0.0 {
+            let ratio = repriced.total_cost / chart.total_cost;
+            repriced.normalized_daily_total = chart.normalized_daily_total.map(|daily| self.round_cost(daily * ratio));
+            repriced.projected_monthly_total = chart.projected_monthly_total.map(|monthly| self.round_cost(monthly * ratio));
+        }
+        repriced
+    }
+
+    /// Express a cost in the configured display unit
+    pub fn scale_cost(&self, cost: f64) -> f64 {
+        cost / self.config().cost_scale
+    }
+
+    /// Round a cost to the configured precision, if any
+    ///
+    /// Totals are summed from the already-rounded service values so they always
+    /// match the sum of the displayed rows.
+    pub fn round_cost(&self, cost: f64) -> f64 {
+        let config = self.config();
+        match config.cost_precision {
+            Some(precision) => config.rounding_mode.round(cost, precision),
+            None => cost,
+        }
+    }
+
+    /// Format an instant for display in the configured time zone and format
+    pub fn format_timestamp(&self, instant: DateTime<Utc>) -> String {
+        let config = self.config();
+        let format = config.date_display_format.as_str();
+        match config.display_timezone {
+            Some(tz) => instant.with_timezone(&tz).format(format).to_string(),
+            None => instant.with_timezone(&Local).format(format).to_string(),
+        }
+    }
+
+    /// Name shown for a service, anonymized when configured
+    ///
+    /// Names that already are pseudonyms are kept, so names taken from a chart or
+    /// from history recorded while anonymizing are not hashed twice.
+    pub fn display_name(&self, service: &str) -> String {
+        if self.config().anonymize_services && !is_pseudonym(service) {
+            anonymize_service(service)
+        } else {
+            service.to_string()
+        }
+    }
+
+    /// Apply display names to the services of a time series
+    pub fn display_timeseries(&self, timeseries: Vec<TimeBucket>) -> Vec<TimeBucket> {
+        timeseries
+            .into_iter()
+            .map(|bucket| TimeBucket {
+                services: bucket
+                    .services
+                    .into_iter()
+                    .map(|(service, cost)| (self.display_name(&service), self.scale_cost(cost)))
+                    .collect(),
+                total_cost: self.scale_cost(bucket.total_cost),
+                ..bucket
+            })
+            .collect()
+    }
+
+    /// Resolve a requested range to the command's date strings, or why it is invalid
+    ///
+    /// The range defaults to the start of the current month up to today. The end
+    /// day's charges are included unless `END_INCLUSIVE` is disabled.
+    pub fn resolve_date_range(&self, begin_at: Option<String>, end_at: Option<String>) -> Result<(String, String), String> {
+        // Generate the date string in the same format as the shell command
+        let begin_at_date_string = self.get_date_string(begin_at);
+        let end_at_date_string = self.get_end_date_string(
+            Some(end_at.unwrap_or_else(|| Local::now().format("%Y-%m-%d").to_string()))
+        );
+
+        self.check_date_validity(Some(begin_at_date_string.clone()), Some(end_at_date_string.clone()))?;
+        Ok((begin_at_date_string, end_at_date_string))
+    }
+
+    pub fn check_date_validity(&self, start: Option<String>, end: Option<String>) -> Result<(), String> {
+        let config = self.config();
+        if let (Some(start), Some(end)) = (start, end) {
+            let start_date = chrono::NaiveDate::parse_from_str(&start, "%Y-%m-%dT%H:%M:%S%z");
+            let end_date = chrono::NaiveDate::parse_from_str(&end, "%Y-%m-%dT%H:%M:%S%z");
+
+            match (start_date, end_date) {
+                (Ok(start_date), Ok(end_date)) => {
+                    let current_date = Local::now().date_naive();
+                    let span_days = (end_date - start_date).num_days();
+
+                    if start_date > end_date {
+                        warn!("Start date {} is after end date {}", start, end);
+                        Err("it must begin before it ends".to_string())
+                    } else if start_date >= current_date || end_date > current_date {
+                        warn!("Date range cannot be in the future: start {}, end {}", start, end);
+                        Err("it must be in the past".to_string())
+                    } else if span_days > i64::from(config.max_range_days) {
+                        warn!("Date range of {} days exceeds MAX_RANGE_DAYS: start {}, end {}", span_days, start, end);
+                        Err(format!("it spans {} days, more than the limit of {} (MAX_RANGE_DAYS)", span_days, config.max_range_days))
+                    } else {
+                        Ok(())
+                    }
+                }
+                (Err(e), _) => {
+                    warn!("Invalid start date format '{}': {}", start, e);
+                    Err(format!("invalid start date {}", start))
+                }
+                (_, Err(e)) => {
+                    warn!("Invalid end date format '{}': {}", end, e);
+                    Err(format!("invalid end date {}", end))
+                }
+            }
+        } else {
+            Ok(()) // If no dates provided, assume valid
+        }
+    }
+
+    /// Get the formatted date string that would be used in the OpenStack command
+    pub fn get_date_string(&self, date: Option<String>) -> String {
+        match date {
+            Some(d) => {
+                // Parse the provided date string
+                match chrono::NaiveDate::parse_from_str(&d, "%Y-%m-%d") {
+                    Ok(date) => date.format("%Y-%m-%dT00:00:00+00:00").to_string(),
+                    Err(e) => {
+                        warn!("Invalid date format '{}', using current date: {}", d, e);
+                        Local::now().format("%Y-%m-01T00:00:00+00:00").to_string()
+                    }
+                }
+            }
+            None => Local::now().format("%Y-%m-01T00:00:00+00:00").to_string(),
+        }
+    }
+    
+    /// Get the formatted end date string used in the OpenStack command
+    ///
+    /// With `END_INCLUSIVE` the end is the last second of the day so that day's
+    /// charges are part of the range, otherwise it is midnight like the begin date.
+    pub fn get_end_date_string(&self, date: Option<String>) -> String {
+        let date_string = self.get_date_string(date);
+        if self.config().end_inclusive {
+            date_string.replacen("T00:00:00", "T23:59:59", 1)
+        } else {
+            date_string
+        }
+    }
+
+    /// Program and arguments running the OpenStack CLI with `args`
+    fn command_line(&self, args: &[String]) -> (String, Vec<String>) {
+        self.command_line_for(&self.config().openstack_command, args)
+    }
+
+    /// Process running the CLI `command` with `args`, locally or on `SSH_HOST`
+    ///
+    /// Over SSH the password is set in the environment of `ssh`, which forwards it
+    /// with `SendEnv`, so it never appears in the remote command line.
+    fn cli_command(&self, command: &str, args: &[String]) -> Command {
+        let (program, args) = self.command_line_for(command, args);
+        let mut cli = Command::new(program);
+        cli.args(args);
+        let config = self.config();
+        if config.ssh_host.is_some() && !config.os_password.is_empty() {
+            cli.env("OS_PASSWORD", &config.os_password);
+        }
+        cli
+    }
+
+    /// Program and arguments running the CLI `command` with `args`
+    ///
+    /// With `SSH_HOST` the CLI runs on that host: `ssh` gets the CLI and its arguments
+    /// as one shell-quoted remote command, since the remote shell splits it again.
+    fn command_line_for(&self, command: &str, args: &[String]) -> (String, Vec<String>) {
+        let config = self.config();
+        let Some(host) = &config.ssh_host else {
+            return (command.to_string(), args.to_vec());
+        };
+        // Never wait for a password or host key prompt nobody can answer
+        let mut ssh_args = vec!["-o".to_string(), "BatchMode=yes".to_string()];
+        if !config.os_password.is_empty() {
+            ssh_args.extend(["-o".to_string(), "SendEnv=OS_PASSWORD".to_string()]);
+        }
+        if let Some(key) = &config.ssh_key {
+            ssh_args.extend(["-i".to_string(), key.clone()]);
+        }
+        ssh_args.push(match &config.ssh_user {
+            Some(user) => format!("{}@{}", user, host),
+            None => host.clone(),
+        });
+        ssh_args.push("--".to_string());
+        let remote: Vec<Cow<str>> = std::iter::once(command)
+            .chain(args.iter().map(String::as_str))
+            .map(shell_quote)
+            .collect();
+        ssh_args.push(remote.join(" "));
+        ("ssh".to_string(), ssh_args)
+    }
+
+    /// Create a redacted version of command arguments for safe logging
+    fn redact_sensitive_args(&self, args: &[String]) -> Vec<String> {
+        let mut redacted_args = Vec::new();
+        let mut i = 0;
+        
+        while i < args.len() {
+            let arg = &args[i];
+            
+            // Check if this is a sensitive parameter flag
+            if arg == "--os-password" || arg == "--os-project-id" {
+                redacted_args.push(arg.clone());
+                // If there's a next argument (the value), replace it with [REDACTED]
+                if i + 1 < args.len() {
+                    redacted_args.push("[REDACTED]".to_string());
+                    i += 1; // Skip the next argument since we've processed it
+                }
+            } else {
+                redacted_args.push(arg.clone());
+            }
+            
+            i += 1;
+        }
+        
+        redacted_args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use crate::test_support::{mock_command, CapturedLogs};
+
+    /// Build a data service whose configuration only contains the given variables
+    fn service_with(vars: &[(&str, &str)]) -> DataService {
+        let config = Config::from_pairs(vars);
+        let cache = Arc::new(OpenStackCache::new(std::time::Duration::from_secs(300)));
+        DataService::new(config, cache)
+    }
+
+    /// Build a payload of dataframes from `(service, rating)` rows
+    fn wrappers(rows: &[(&str, &str)]) -> Vec<ResourceWrapper> {
+        let resources: Vec<serde_json::Value> = rows
+            .iter()
+            .map(|(service, rating)| serde_json::json!({ "service": service, "rating": rating }))
+            .collect();
+        serde_json::from_value(serde_json::json!([{ "Resources": resources }])).unwrap()
+    }
+
+    #[test]
+    fn test_date_formatting() {
+        let service = service_with(&[("OS_REGION_NAME", "rc3-a")]);
+        let date_string = service.get_date_string(None);
+        
+        // Test that the date matches the expected format: YYYY-MM-01T00:00:00+00:00
+        let date_regex = Regex::new(r"^\d{4}-\d{2}-01T00:00:00\+00:00$").unwrap();
+        assert!(date_regex.is_match(&date_string), "Date format should match YYYY-MM-01T00:00:00+00:00, got: {}", date_string);
+    }
+
+    #[test]
+    fn test_end_date_is_end_of_day_only_when_inclusive() {
+        let inclusive = service_with(&[]);
+        assert_eq!(inclusive.get_end_date_string(Some("2025-01-31".to_string())), "2025-01-31T23:59:59+00:00");
+
+        let exclusive = service_with(&[("END_INCLUSIVE", "false")]);
+        assert_eq!(exclusive.get_end_date_string(Some("2025-01-31".to_string())), "2025-01-31T00:00:00+00:00");
+        assert_eq!(exclusive.get_date_string(Some("2025-01-31".to_string())), "2025-01-31T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_run_rate_over_a_ten_day_range() {
+        let service = service_with(&[("CURRENCY_RATE", "1")]);
+        let outcome = FetchOutcome {
+            rating: service.process_resources(wrappers(&[("compute", "40"), ("storage", "10")])),
+            range: Some(("2025-04-01T00:00:00+00:00".to_string(), "2025-04-10T23:59:59+00:00".to_string())),
+            ..FetchOutcome::default()
+        };
+        let chart = service.process_outcome(outcome);
+        assert_eq!(chart.normalized_daily_total, Some(5.0));
+        // April has 30 days
+        assert_eq!(chart.projected_monthly_total, Some(150.0));
+
+        let mut single_day = ChartData::empty();
+        single_day.total_cost = 7.0;
+        service.apply_run_rate(&mut single_day, "2025-04-01T00:00:00+00:00", "2025-04-01T00:00:00+00:00");
+        assert_eq!(single_day.normalized_daily_total, Some(7.0));
+    }
+
+    #[test]
+    fn test_service_cost_percentiles() {
+        let service = service_with(&[("CURRENCY_RATE", "1")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[
+            ("compute", "10"), ("storage", "1"), ("network", "4"), ("dns", "2"), ("image", "3"),
+        ])));
+        // Ascending 1, 2, 3, 4, 10: p50 is rank 2, p90 is rank 3.6 = 4 + 0.6 * (10 - 4)
+        assert_eq!(chart.p50, 3.0);
+        assert!((chart.p90 - 7.6).abs() < 1e-9, "{}", chart.p90);
+        assert_eq!(chart.max_service_cost, 10.0);
+
+        let empty = service.process_data(&RatingData::default());
+        assert_eq!((empty.p50, empty.p90, empty.max_service_cost), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_totals_are_formatted_with_the_currency_pattern() {
+        let rating = |service: &DataService| service.process_resources(wrappers(&[("compute", "1200"), ("storage", "34.5")]));
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("CURRENCY_FORMAT_PATTERN", "# ##0,00 ¤")]);
+        let chart = service.process_data(&rating(&service));
+        assert_eq!(chart.formatted_total, "1 234,50 €");
+        assert_eq!(chart.formatted_average_cost, "617,25 €");
+        assert_eq!(chart.formatted_values, vec!["1 200,00 €", "34,50 €"]);
+
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("COST_UNIT_LABEL", "$")]);
+        assert_eq!(service.process_data(&rating(&service)).formatted_total, "$1234.50");
+    }
+
+    #[test]
+    fn test_services_are_bucketed_by_share_of_total() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("MODERATE_SHARE_THRESHOLD", "0.05")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[
+            ("compute", "70"), ("storage", "10"), ("network", "12"), ("dns", "5"), ("image", "3"),
+        ])));
+        // 70%, 12% and 10% (not above the default 10%), 5% (not above 5%), 3%
+        assert_eq!(chart.labels, ["compute", "network", "storage", "dns", "image"]);
+        assert_eq!(chart.categories, ["major", "major", "moderate", "minor", "minor"]);
+
+        let free = service.process_data(&service.process_resources(wrappers(&[("compute", "0"), ("storage", "0")])));
+        assert_eq!(free.categories, ["minor", "minor"]);
+
+        // Scoped charts are bucketed by their own total
+        let restricted = service.restrict(&chart, &["storage".to_string(), "dns".to_string()]);
+        assert_eq!(restricted.categories, ["major", "major"]);
+    }
+
+    #[test]
+    fn test_concentration_is_the_herfindahl_index_of_service_shares() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("INCLUDE_CONCENTRATION", "true")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[
+            ("compute", "50"), ("storage", "30"), ("network", "20"),
+        ])));
+        // 0.5² + 0.3² + 0.2² = 0.25 + 0.09 + 0.04
+        assert!((chart.concentration.unwrap() - 0.38).abs() < 1e-9, "{:?}", chart.concentration);
+        assert_eq!(chart.top_service_share, Some(0.5));
+
+        let single = service.process_data(&service.process_resources(wrappers(&[("compute", "7")])));
+        assert_eq!((single.concentration, single.top_service_share), (Some(1.0), Some(1.0)));
+        let empty = service.process_data(&RatingData::default());
+        assert_eq!((empty.concentration, empty.top_service_share), (Some(0.0), Some(0.0)));
+
+        let without = service_with(&[("CURRENCY_RATE", "1")]);
+        let chart = without.process_data(&RatingData::default());
+        assert!(serde_json::to_value(&chart).unwrap().get("concentration").is_none());
+    }
+
+    #[test]
+    fn test_min_change_percent_ignores_small_fluctuations() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("MIN_CHANGE_PERCENT", "1")]);
+        let chart = |rows: &[(&str, &str)]| service.process_data(&service.process_resources(wrappers(rows)));
+        let previous = chart(&[("compute", "100"), ("storage", "10")]);
+
+        assert!(!service.changed_significantly(&chart(&[("compute", "100.5"), ("storage", "10.05")]), &previous));
+        assert!(service.changed_significantly(&chart(&[("compute", "100"), ("storage", "10.2")]), &previous));
+        assert!(service.changed_significantly(&chart(&[("compute", "100"), ("storage", "10"), ("dns", "0.01")]), &previous));
+        assert!(service_with(&[]).changed_significantly(&previous, &previous));
+    }
+
+    #[test]
+    fn test_resource_state_filter_keeps_matching_states() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("RESOURCE_STATE_FILTER", "active, Paused")]);
+        let payload: Vec<ResourceWrapper> = serde_json::from_str(r#"[{"Resources": [
+            {"service": "compute", "rating": "5", "state": "ACTIVE"},
+            {"service": "compute", "rating": "3", "state": "terminated"},
+            {"service": "compute", "rating": "1", "status": "paused"},
+            {"service": "storage", "rating": "2"}
+        ]}]"#).unwrap();
+        let rating = service.process_resources(payload);
+        assert_eq!(rating.costs.get("compute"), Some(&6.0));
+        assert_eq!(rating.costs.get("storage"), None);
+
+        let unfiltered = service_with(&[("CURRENCY_RATE", "1")])
+            .process_resources(wrappers(&[("compute", "5"), ("storage", "2")]));
+        assert_eq!(unfiltered.costs.values().sum::<f64>(), 7.0);
+    }
+
+    #[test]
+    fn test_costs_above_cap_are_clamped_and_flagged() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("SERVICE_COST_CAP", "1000"), ("INCLUDE_RAW_VALUES", "true")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[("compute", "1000000000"), ("storage", "20")])));
+        assert_eq!(chart.labels, vec!["compute", "storage"]);
+        assert_eq!(chart.values, vec![1000.0, 20.0]);
+        assert_eq!(chart.total_cost, 1020.0);
+        assert_eq!(chart.anomalies, vec!["compute"]);
+        assert_eq!(chart.raw_values[0], 1_000_000_000.0);
+    }
+
+    #[test]
+    fn test_full_sample_rate_is_exact() {
+        let service = service_with(&[("CURRENCY_RATE", "2")]);
+        let data = service.process_resources(wrappers(&[("compute", "3"), ("compute", "1"), ("storage", "5")]));
+        assert_eq!(data.costs["compute"], 2.0);
+        assert_eq!(data.costs["storage"], 2.5);
+        assert!(!service.process_data(&data).sampled);
+    }
+
+    #[test]
+    fn test_half_sample_rate_preserves_totals() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("SAMPLE_RATE", "0.5")]);
+        let rows: Vec<(&str, &str)> = (0..20_000)
+            .map(|i| (if i % 2 == 0 { "compute" } else { "storage" }, "1"))
+            .collect();
+        let chart = service.process_data(&service.process_resources(wrappers(&rows)));
+        assert!(chart.sampled);
+        assert!((chart.total_cost - 20_000.0).abs() / 20_000.0 < 0.05, "estimated total {}", chart.total_cost);
+    }
+
+    #[test]
+    fn test_dataframes_are_bucketed_by_window() {
+        let service = service_with(&[("CURRENCY_RATE", "1")]);
+        let payload = r#"[
+            {"Begin": "2025-01-02T00:00:00+00:00", "End": "2025-01-03T00:00:00+00:00",
+             "Resources": [{"service": "compute", "rating": "4"}, {"service": "storage", "rating": "1"}]},
+            {"Begin": "2025-01-01T00:00:00+00:00", "End": "2025-01-02T00:00:00+00:00",
+             "Resources": [{"service": "compute", "rating": "2"}]},
+            {"Begin": "2025-01-02T00:00:00+00:00", "End": "2025-01-03T00:00:00+00:00",
+             "Resources": [{"service": "compute", "rating": "3"}]}
+        ]"#;
+        let rating = service.process_resources(serde_json::from_str(payload).unwrap());
+
+        assert_eq!(rating.costs["compute"], 9.0);
+        assert_eq!(rating.costs["storage"], 1.0);
+        assert_eq!(rating.timeseries.len(), 2);
+        assert_eq!(rating.timeseries[0].begin, "2025-01-01T00:00:00+00:00");
+        assert_eq!(rating.timeseries[0].total_cost, 2.0);
+        assert_eq!(rating.timeseries[1].end, "2025-01-03T00:00:00+00:00");
+        assert_eq!(rating.timeseries[1].services["compute"], 7.0);
+        assert_eq!(rating.timeseries[1].total_cost, 8.0);
+    }
+
+    #[test]
+    fn test_anonymized_labels_are_stable_and_keep_costs() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("ANONYMIZE_SERVICES", "true")]);
+        let data = service.process_resources(wrappers(&[("compute", "3"), ("storage", "1")]));
+        let first = service.process_data(&data);
+        let second = service.process_data(&data);
+
+        assert_eq!(anonymize_service("compute"), anonymize_service("compute"));
+        assert_ne!(anonymize_service("compute"), anonymize_service("storage"));
+        assert_eq!(first.labels, vec![anonymize_service("compute"), anonymize_service("storage")]);
+        assert_eq!(first.labels, second.labels);
+        assert_eq!(first.values, vec![3.0, 1.0]);
+        assert!(first.labels.iter().all(|label| label.starts_with("service-")));
+    }
+
+    #[tokio::test]
+    async fn test_second_identical_fetch_is_served_from_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo '[{"Resources": [{"service": "compute", "rating": "5"}]}]'"#);
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        let range = || (Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+
+        let (begin, end) = range();
+        let first = service.fetch_data(begin, end).await;
+        assert!(!first.from_cache);
+        assert_eq!(first.rating.costs["compute"], 5.0);
+
+        let (begin, end) = range();
+        let second = service.fetch_data(begin, end).await;
+        assert!(second.from_cache);
+        assert_eq!(second.rating.costs["compute"], 5.0);
+    }
+
+    #[tokio::test]
+    async fn test_incremental_fetch_only_refetches_today() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls.log");
+        let today = Local::now().date_naive();
+        let command = mock_command(&dir, &format!(
+            r#"echo "$*" >> {}
+case "$*" in
+  *"-b {}T00:00:00"*) echo '[{{"Resources": [{{"service": "compute", "rating": "1"}}]}}]' ;;
+  *) echo '[{{"Resources": [{{"service": "compute", "rating": "10"}}, {{"service": "storage", "rating": "2"}}]}}]' ;;
+esac"#,
+            calls.display(),
+            today.format("%Y-%m-%d"),
+        ));
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1"), ("INCREMENTAL_FETCH", "true")]);
+        let refresh = || service.fetch_data(
+            Some((today - chrono::Days::new(3)).format("%Y-%m-%d").to_string()),
+            Some(today.format("%Y-%m-%d").to_string()),
+        );
+        let logged = || std::fs::read_to_string(&calls).unwrap().lines().map(str::to_string).collect::<Vec<_>>();
+
+        let first = refresh().await;
+        assert_eq!(logged().len(), 2);
+        assert_eq!(first.rating.costs["compute"], 11.0);
+
+        let second = refresh().await;
+        let calls = logged();
+        assert_eq!(calls.len(), 3, "only today is fetched again");
+        assert!(calls[2].contains(&format!("-b {}T00:00:00", today.format("%Y-%m-%d"))), "{}", calls[2]);
+        assert_eq!(second.rating.costs["compute"], 11.0);
+        assert_eq!(second.rating.costs["storage"], 2.0);
+        assert!(!second.from_cache);
+    }
+
+    #[tokio::test]
+    async fn test_daily_aggregates_fetch_each_day_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls.log");
+        let command = mock_command(&dir, &format!(
+            r#"echo "$*" >> {}
+echo '[{{"Resources": [{{"service": "compute", "rating": "2"}}]}}]'"#,
+            calls.display(),
+        ));
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1"), ("DAILY_AGGREGATES", "true")]);
+        let query = || service.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-03".to_string()));
+        let logged = || std::fs::read_to_string(&calls).unwrap().lines().map(str::to_string).collect::<Vec<_>>();
+
+        let first = query().await;
+        // Days are fetched concurrently, so the calls may land in any order
+        let mut fetched = logged();
+        fetched.sort();
+        assert_eq!(fetched.len(), 3, "one fetch per day");
+        assert!(fetched[0].contains("-b 2025-01-01T00:00:00+00:00 -e 2025-01-02T00:00:00+00:00"), "{}", fetched[0]);
+        assert!(fetched[2].contains("-b 2025-01-03T00:00:00+00:00 -e 2025-01-04T00:00:00+00:00"), "{}", fetched[2]);
+        assert_eq!(first.rating.costs["compute"], 6.0);
+
+        // Completed days are kept even once the query cache no longer has them
+        service.cache().clear().await;
+        let second = query().await;
+        assert_eq!(logged().len(), 3, "no day is fetched again");
+        assert_eq!(second.rating.costs["compute"], 6.0);
+        assert!(second.from_cache);
+    }
+
+    #[tokio::test]
+    async fn test_daily_aggregates_fetch_the_current_utc_day_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls.log");
+        let command = mock_command(&dir, &format!(
+            r#"echo "$*" >> {}
+echo '[{{"Resources": [{{"service": "compute", "rating": "2"}}]}}]'"#,
+            calls.display(),
+        ));
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1"), ("DAILY_AGGREGATES", "true")]);
+        let today = Utc::now().date_naive();
+        let yesterday = today.pred_opt().unwrap();
+        let query = || service.fetch_data(Some(yesterday.format("%Y-%m-%d").to_string()), Some(today.format("%Y-%m-%d").to_string()));
+        let logged = || std::fs::read_to_string(&calls).unwrap().lines().map(str::to_string).collect::<Vec<_>>();
+
+        query().await;
+        assert_eq!(logged().len(), 2, "one fetch per day");
+
+        service.cache().clear().await;
+        let second = query().await;
+        let calls = logged();
+        assert_eq!(calls.len(), 3, "only the current UTC day is fetched again");
+        assert!(calls[2].contains(&format!("-b {}T00:00:00+00:00", today.format("%Y-%m-%d"))), "{}", calls[2]);
+        assert_eq!(second.rating.costs["compute"], 4.0);
+    }
+
+    #[tokio::test]
+    async fn test_dataframe_and_row_counts_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo '[
+            {"Resources": [{"service": "compute", "rating": "1"}, {"service": "storage", "rating": "2"}]},
+            {"Resources": [{"service": "compute", "rating": "3", "state": "deleted"}]},
+            {"Resources": [{"service": "compute", "rating": "4"}, {"service": "network", "rating": "5"}]}
+        ]'"#);
+        let service = service_with(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("CURRENCY_RATE", "1"),
+            ("INCLUDE_ROW_COUNTS", "true"),
+            ("RESOURCE_STATE_FILTER", "active"),
+        ]);
+
+        let outcome = service.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string())).await;
+        assert_eq!((outcome.rating.dataframe_count, outcome.rating.row_count), (3, 5));
+        let chart = service.process_outcome(outcome);
+        assert_eq!(chart.dataframe_count, Some(3));
+        assert_eq!(chart.row_count, Some(5));
+        let json = serde_json::to_value(&chart).unwrap();
+        assert_eq!(json["dataframe_count"], 3);
+        assert_eq!(json["row_count"], 5);
+
+        let without = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        let chart = without.process_outcome(without.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string())).await);
+        assert!(serde_json::to_value(&chart).unwrap().get("row_count").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_warm_cache_refresh_reuses_the_processed_chart() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo '[{"Resources": [{"service": "compute", "rating": "5"}]}]'"#);
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1"), ("CACHE_PROCESSED_CHART", "true")]);
+        let refresh = || service.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+
+        let first = service.process_outcome(refresh().await);
+        assert_eq!(service.metrics().chart_builds(), 1);
+        let warm = refresh().await;
+        assert!(warm.from_cache);
+        let second = service.process_outcome(warm);
+        assert_eq!(service.metrics().chart_builds(), 1, "a cache hit must not reprocess");
+        assert_eq!(second.labels, first.labels);
+        assert_eq!(second.total_cost, 5.0);
+
+        // Replacing the cache entry invalidates the processed chart
+        service.cache().clear().await;
+        service.process_outcome(refresh().await);
+        assert_eq!(service.metrics().chart_builds(), 2);
+
+        let uncached = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        for _ in 0..2 {
+            uncached.process_outcome(uncached.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string())).await);
+        }
+        assert_eq!(uncached.metrics().chart_builds(), 2);
+    }
+
+    #[test]
+    fn test_processed_charts_are_bounded_and_forgotten_by_exact_key() {
+        let service = service_with(&[("CACHE_PROCESSED_CHART", "true")]);
+        let processed = service.processed_charts.as_ref().unwrap();
+        let chart = service.process_data(&RatingData::default());
+        for index in 0..PROCESSED_CHARTS_MAX + 10 {
+            processed.write().unwrap().insert(processed_key(&format!("entry-{}", index), "settings"), chart.clone());
+        }
+        assert_eq!(processed.read().unwrap().len(), PROCESSED_CHARTS_MAX);
+        assert!(processed.read().unwrap().get(&processed_key("entry-0", "settings")).is_none());
+
+        // Forgetting one entry keeps those whose key merely contains it
+        service.forget_processed("entry-7");
+        assert!(processed.read().unwrap().get(&processed_key("entry-70", "settings")).is_some());
+        service.forget_processed("entry-70");
+        assert!(processed.read().unwrap().get(&processed_key("entry-70", "settings")).is_none());
+        assert_eq!(processed.read().unwrap().len(), PROCESSED_CHARTS_MAX - 1);
+    }
+
+    #[test]
+    fn test_backfill_files_are_aggregated_per_month() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("2025-01.json"), r#"[
+            {"Begin": "2025-01-01T00:00:00+00:00", "End": "2025-01-02T00:00:00+00:00",
+             "Resources": [{"service": "compute", "rating": "2"}]},
+            {"Begin": "2025-01-15T00:00:00+00:00", "End": "2025-01-16T00:00:00+00:00",
+             "Resources": [{"service": "compute", "rating": "3"}]}
+        ]"#).unwrap();
+        std::fs::write(dir.path().join("2025-02.json"), r#"[
+            {"Begin": "2025-02-01T00:00:00+00:00", "End": "2025-02-02T00:00:00+00:00",
+             "Resources": [{"service": "storage", "rating": "4"}]}
+        ]"#).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "ignored").unwrap();
+
+        let backfill_dir = dir.path().to_string_lossy().into_owned();
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("BACKFILL_DIR", &backfill_dir)]);
+        let mut history = crate::history::HistoryBuffer::new(10);
+        for point in service.load_backfill() {
+            history.push(point);
+        }
+
+        let points = history.points();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].timestamp, "2025-01-01T00:00:00+00:00");
+        assert_eq!(points[0].services["compute"], 5.0);
+        assert_eq!(points[1].timestamp, "2025-02-01T00:00:00+00:00");
+        assert_eq!(points[1].total_cost, 4.0);
+    }
+
+    #[tokio::test]
+    async fn test_expired_cache_is_served_when_retries_are_exhausted() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("healthy");
+        let command = mock_command(&dir, &format!(
+            r#"[ -f "{}" ] || exit 1
+echo '[{{"Resources": [{{"service": "compute", "rating": "5"}}]}}]'"#,
+            marker.display()
+        ));
+        let config = Config::from_pairs(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("CURRENCY_RATE", "1"),
+            ("FETCH_RETRIES", "2"),
+            ("FETCH_RETRY_DELAY_MS", "1"),
+        ]);
+        let cache = Arc::new(OpenStackCache::new(std::time::Duration::from_millis(20)));
+        let service = DataService::new(config, cache);
+        let range = || (Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+
+        std::fs::write(&marker, "").unwrap();
+        let (begin, end) = range();
+        assert!(!service.fetch_data(begin, end).await.stale);
+
+        std::fs::remove_file(&marker).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        let (begin, end) = range();
+        let outcome = service.fetch_data(begin, end).await;
+        assert!(outcome.stale);
+        assert!(outcome.from_cache);
+        assert_eq!(outcome.rating.costs["compute"], 5.0);
+        assert!(service.process_outcome(outcome).stale);
+    }
+
+    #[tokio::test]
+    async fn test_expired_cache_is_not_served_when_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, "exit 1");
+        let config = Config::from_pairs(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("SERVE_EXPIRED_ON_EXHAUSTION", "false"),
+        ]);
+        let cache = Arc::new(OpenStackCache::new(std::time::Duration::ZERO));
+        let service = DataService::new(config, cache);
+        let outcome = service.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string())).await;
+        assert!(!outcome.stale);
+        assert!(outcome.rating.costs.is_empty());
+    }
+
+    #[test]
+    fn test_raw_values_are_parallel_to_converted_values() {
+        let service = service_with(&[("CURRENCY_RATE", "55.5"), ("INCLUDE_RAW_VALUES", "true")]);
+        let data = service.process_resources(wrappers(&[("compute", "100"), ("compute", "11"), ("storage", "5.55")]));
+        let chart = service.process_data(&data);
+
+        assert_eq!(chart.raw_values.len(), chart.values.len());
+        assert_eq!(chart.raw_values[0], 111.0);
+        for (value, raw) in chart.values.iter().zip(&chart.raw_values) {
+            assert!((value - raw / 55.5).abs() < 1e-9);
+        }
+
+        let service = service_with(&[("CURRENCY_RATE", "55.5")]);
+        assert!(service.process_data(&data).raw_values.is_empty());
+    }
+
+    #[test]
+    fn test_timestamps_render_in_configured_timezone() {
+        let instant = DateTime::parse_from_rfc3339("2025-01-15T12:30:00Z").unwrap().with_timezone(&Utc);
+
+        let paris = service_with(&[("DISPLAY_TIMEZONE", "Europe/Paris")]);
+        assert_eq!(paris.format_timestamp(instant), "2025-01-15 13:30:00 +01:00");
+
+        let new_york = service_with(&[("DISPLAY_TIMEZONE", "America/New_York")]);
+        assert_eq!(new_york.format_timestamp(instant), "2025-01-15 07:30:00 -05:00");
+
+        let custom = service_with(&[("DISPLAY_TIMEZONE", "Asia/Tokyo"), ("DATE_DISPLAY_FORMAT", "%d/%m/%Y %H:%M %Z")]);
+        assert_eq!(custom.format_timestamp(instant), "15/01/2025 21:30 JST");
+    }
+
+    #[test]
+    fn test_zero_cost_only_for_successful_all_zero_data() {
+        let service = service_with(&[]);
+        let zero = service.process_resources(wrappers(&[("compute", "0"), ("storage", "0")]));
+        assert!(service.process_data(&zero).zero_cost);
+
+        let charged = service.process_resources(wrappers(&[("compute", "0"), ("storage", "1")]));
+        assert!(!service.process_data(&charged).zero_cost);
+
+        let chart = service.process_outcome(FetchOutcome::failed("OpenStack command failed"));
+        assert!(!chart.zero_cost);
+        assert_eq!(chart.error.as_deref(), Some("OpenStack command failed"));
+    }
+
+    #[tokio::test]
+    async fn test_only_services_new_since_the_first_refresh_are_alerted() {
+        let service = service_with(&[("ALERT_ON_NEW_SERVICE", "true")]);
+        let outcome = |rows: &[(&str, &str)]| FetchOutcome {
+            rating: service.process_resources(wrappers(rows)),
+            ..FetchOutcome::default()
+        };
+
+        let first = outcome(&[("compute", "3"), ("storage", "1")]);
+        assert!(service.alert_new_services(&first).await.is_empty());
+
+        let second = outcome(&[("compute", "4"), ("storage", "1"), ("dns", "2"), ("network", "0")]);
+        assert_eq!(service.alert_new_services(&second).await, vec!["dns".to_string()]);
+        assert!(service.alert_new_services(&second).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_fetches_are_limited() {
+        let dir = tempfile::tempdir().unwrap();
+        let slots = dir.path().display();
+        let command = mock_command(&dir, &format!(
+            "touch {slots}/running.$$\nls {slots}/running.* | wc -l >> {slots}/counts\nsleep 0.2\nrm {slots}/running.$$\n\
+             echo '[{{\"Resources\": [{{\"service\": \"compute\", \"rating\": \"1\"}}]}}]'"
+        ));
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("MAX_CONCURRENT_FETCHES", "2")]);
+
+        let mut fetches = tokio::task::JoinSet::new();
+        for day in 1..=6 {
+            let service = service.clone();
+            // Distinct ranges so no fetch is served from the cache
+            fetches.spawn(async move { service.fetch_data(Some(format!("2025-01-0{}", day)), Some("2025-01-31".to_string())).await });
+        }
+        while let Some(outcome) = fetches.join_next().await {
+            assert!(outcome.unwrap().error.is_none());
+        }
+        let counts: Vec<usize> = std::fs::read_to_string(dir.path().join("counts"))
+            .unwrap()
+            .lines()
+            .map(|line| line.trim().parse().unwrap())
+            .collect();
+        assert_eq!(counts.len(), 6);
+        assert!(counts.iter().all(|running| *running <= 2), "{:?}", counts);
+
+        // A fetch still queued after the timeout gives up without running
+        let service = service_with(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("MAX_CONCURRENT_FETCHES", "1"),
+            ("FETCH_QUEUE_TIMEOUT_MS", "50"),
+        ]);
+        let (first, second) = tokio::join!(
+            service.fetch_data(Some("2025-02-01".to_string()), Some("2025-02-28".to_string())),
+            service.fetch_data(Some("2025-02-02".to_string()), Some("2025-02-28".to_string())),
+        );
+        assert_eq!([first.overloaded, second.overloaded].iter().filter(|overloaded| **overloaded).count(), 1);
+        assert!(first.error.is_some() == first.overloaded && second.error.is_some() == second.overloaded);
+    }
+
+    #[tokio::test]
+    async fn test_transform_command_rewrites_costs() {
+        let costs = HashMap::from([("compute".to_string(), 3.0), ("storage".to_string(), 1.0)]);
+
+        let identity = service_with(&[("TRANSFORM_COMMAND", "cat")]);
+        assert_eq!(identity.transform_costs(costs.clone()).await, costs);
+
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, "sed 's/compute/nova/'");
+        let rename = service_with(&[("TRANSFORM_COMMAND", &command)]);
+        let transformed = rename.transform_costs(costs.clone()).await;
+        assert_eq!(transformed["nova"], 3.0);
+        assert!(!transformed.contains_key("compute"));
+
+        let broken = service_with(&[("TRANSFORM_COMMAND", "false")]);
+        assert_eq!(broken.transform_costs(costs.clone()).await, costs);
+
+        let quoted = service_with(&[("TRANSFORM_COMMAND", "sed 's/compute/block storage/'")]);
+        assert_eq!(quoted.transform_costs(costs.clone()).await["block storage"], 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_transform_command_handles_large_cost_maps() {
+        let costs: HashMap<String, f64> = (0..20_000).map(|i| (format!("service-{}", i), i as f64)).collect();
+        let service = service_with(&[("TRANSFORM_COMMAND", "sed s/service-/svc-/g"), ("TRANSFORM_TIMEOUT_SECONDS", "5")]);
+        let transformed = service.transform_costs(costs).await;
+        assert_eq!(transformed.len(), 20_000);
+        assert_eq!(transformed["svc-19999"], 19_999.0);
+    }
+
+    #[tokio::test]
+    async fn test_transform_command_applies_to_raw_costs() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo '[{"Resources": [{"service": "compute", "rating": "100"}]}]'"#);
+        let transform_dir = tempfile::tempdir().unwrap();
+        let transform = mock_command(&transform_dir, r#"echo '{"compute": 4}'"#);
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "50"), ("TRANSFORM_COMMAND", &transform)]);
+
+        let outcome = service.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string())).await;
+        assert_eq!(outcome.rating.costs["compute"], 4.0);
+        assert_eq!(outcome.rating.raw_costs["compute"], 200.0);
+        let chart = service.process_outcome(outcome);
+        assert_eq!(service.reprice(&chart, 100.0).values, vec![2.0]);
+    }
+
+    #[test]
+    fn test_cost_precision_uses_rounding_mode() {
+        let rows = [("compute", "1.005"), ("storage", "2.001")];
+        let floor = service_with(&[("CURRENCY_RATE", "1"), ("COST_PRECISION", "2"), ("ROUNDING_MODE", "floor")]);
+        let chart = floor.process_data(&floor.process_resources(wrappers(&rows)));
+        assert_eq!(chart.values, vec![2.0, 1.0]);
+        assert_eq!(chart.total_cost, 3.0);
+
+        let ceil = service_with(&[("CURRENCY_RATE", "1"), ("COST_PRECISION", "2"), ("ROUNDING_MODE", "ceil")]);
+        let chart = ceil.process_data(&ceil.process_resources(wrappers(&rows)));
+        assert_eq!(chart.values, vec![2.01, 1.01]);
+        assert!((chart.total_cost - 3.02).abs() < 1e-9);
+    }
+
+    /// The subcommand and output flags following the auth arguments for a range
+    fn range_args(begin_at: &str, end_at: &str) -> Vec<String> {
+        ["rating", "dataframes", "get", "-b", begin_at, "-e", end_at, "-c", "Begin", "-c", "End", "-c", "Resources", "-f", "json"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    #[test]
+    fn test_build_args_with_os_credentials() {
+        let service = service_with(&[
+            ("OS_AUTH_URL", "https://keystone.example.com:5000/v3"),
+            ("OS_USERNAME", "alice"),
+            ("OS_PASSWORD", "hunter2"),
+            ("OS_PROJECT_ID", "1234"),
+            ("OS_REGION_NAME", "RegionOne"),
+            ("OS_USER_DOMAIN_NAME", "Users"),
+            ("OPENSTACK_EXTRA_ARGS", "--insecure"),
+        ]);
+        let (begin_at, end_at) = ("2025-01-01T00:00:00+00:00", "2025-01-31T23:59:59+00:00");
+        let expected: Vec<String> = [
+            "--os-auth-url", "https://keystone.example.com:5000/v3",
+            "--os-username", "alice",
+            "--os-password", "hunter2",
+            "--os-project-id", "1234",
+            "--os-region-name", "RegionOne",
+            "--os-user-domain-name", "Users",
+            "--insecure",
+        ]
+            .map(String::from)
+            .into_iter()
+            .chain(range_args(begin_at, end_at))
+            .collect();
+        assert_eq!(service.build_args(None, begin_at, end_at), expected);
+
+        // A cloud from clouds.yaml replaces every OS_* credential
+        let mut cloud_args = vec!["--os-cloud".to_string(), "prod".to_string(), "--insecure".to_string()];
+        cloud_args.extend(range_args(begin_at, end_at));
+        assert_eq!(service.build_args(Some("prod"), begin_at, end_at), cloud_args);
+    }
+
+    #[test]
+    fn test_build_args_without_credentials() {
+        let (begin_at, end_at) = ("2025-02-01T00:00:00+00:00", "2025-02-02T00:00:00+00:00");
+        let mut expected = vec!["--os-user-domain-name".to_string(), "Default".to_string()];
+        expected.extend(range_args(begin_at, end_at));
+        assert_eq!(service_with(&[]).build_args(None, begin_at, end_at), expected);
+    }
+
+    #[test]
+    fn test_system_scope_replaces_project_scope() {
+        let vars = [("OS_USERNAME", "admin"), ("OS_PROJECT_ID", "1234")];
+        let mut project_args = Vec::new();
+        service_with(&vars).push_auth_args(&mut project_args);
+        assert!(project_args.windows(2).any(|pair| pair == ["--os-project-id", "1234"]));
+
+        let service = service_with(&[vars[0], vars[1], ("OS_SYSTEM_SCOPE", "all")]);
+        let mut system_args = Vec::new();
+        service.push_auth_args(&mut system_args);
+        assert!(system_args.windows(2).any(|pair| pair == ["--os-system-scope", "all"]));
+        assert!(!system_args.iter().any(|arg| arg == "--os-project-id" || arg == "1234"));
+        assert_ne!(
+            service.cache().generate_key("openstack", &project_args),
+            service.cache().generate_key("openstack", &system_args)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_switching_clouds_changes_fetch_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("args.log");
+        let command = mock_command(&dir, &format!(
+            r#"echo "$@" >> {}
+echo '[{{"Resources": [{{"service": "compute", "rating": "5"}}]}}]'"#,
+            log.display()
+        ));
+        let service = service_with(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("OS_CLOUDS", "east, west"),
+            ("OS_USERNAME", "admin"),
+        ]);
+        let range = || (Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+
+        assert_eq!(service.active_cloud().as_deref(), Some("east"));
+        let (begin, end) = range();
+        service.fetch_data(begin, end).await;
+
+        assert!(!service.set_active_cloud("north"));
+        assert!(service.set_active_cloud("west"));
+        let (begin, end) = range();
+        let outcome = service.fetch_data(begin, end).await;
+        assert!(!outcome.from_cache, "clouds must not share cache entries");
+
+        let calls = std::fs::read_to_string(&log).unwrap();
+        let calls: Vec<&str> = calls.lines().collect();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[0].starts_with("--os-cloud east "));
+        assert!(calls[1].starts_with("--os-cloud west "));
+        assert!(!calls[1].contains("--os-username"));
+    }
+
+    #[tokio::test]
+    async fn test_rating_endpoints_are_merged() {
+        let dir = tempfile::tempdir().unwrap();
+        let flag = dir.path().join("west-down");
+        let command = mock_command(&dir, &format!(
+            r#"case "$2" in
+  east) echo '[{{"Resources": [{{"service": "compute", "rating": "5"}}, {{"service": "storage", "rating": "1"}}]}}]' ;;
+  west) [ -e {} ] && exit 1; echo '[{{"Resources": [{{"service": "compute", "rating": "3"}}]}}]' ;;
+esac"#,
+            flag.display()
+        ));
+        let service = service_with(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("RATING_ENDPOINTS", "east,west"),
+            ("CURRENCY_RATE", "1"),
+        ]);
+        let range = || (Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+
+        let (begin, end) = range();
+        let outcome = service.fetch_data(begin, end).await;
+        assert!(outcome.error.is_none());
+        assert!(!outcome.partial);
+        assert_eq!(outcome.rating.costs["compute"], 8.0);
+        assert_eq!(outcome.rating.costs["storage"], 1.0);
+        assert_eq!(outcome.rating.by_endpoint["west"].costs["compute"], 3.0);
+
+        std::fs::write(&flag, "").unwrap();
+        service.cache().clear().await;
+        let (begin, end) = range();
+        let outcome = service.fetch_data(begin, end).await;
+        assert!(outcome.error.is_none());
+        assert!(outcome.partial);
+        assert!(outcome.endpoint_errors.contains_key("west"));
+        let chart = service.process_outcome(outcome);
+        assert!(chart.partial);
+        assert_eq!(chart.total_cost, 6.0);
+    }
+
+    #[tokio::test]
+    async fn test_replica_serves_the_persisted_cache_without_running_the_cli() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls.log");
+        let cache_file = dir.path().join("cache.json");
+        let command = mock_command(&dir, &format!(
+            r#"echo "$*" >> {}
+echo '[{{"Resources": [{{"service": "compute", "rating": "7"}}]}}]'"#,
+            calls.display(),
+        ));
+        let vars = [("OPENSTACK_COMMAND", command.as_str()), ("CURRENCY_RATE", "1"), ("CACHE_FILE", cache_file.to_str().unwrap())];
+        let range = |end: &str| (Some("2025-01-01".to_string()), Some(end.to_string()));
+        let ttl = std::time::Duration::from_secs(300);
+
+        // The primary fetches and persists
+        let primary = DataService::new(Config::from_pairs(&vars), Arc::new(OpenStackCache::new(ttl).with_file(&cache_file)));
+        let (begin_at, end_at) = range("2025-01-31");
+        primary.fetch_data(begin_at, end_at).await;
+        std::fs::remove_file(&calls).unwrap();
+
+        let config = Config::from_pairs(&[vars.as_slice(), &[("READ_ONLY_REPLICA", "true")]].concat());
+        assert!(config.validate().is_ok());
+        let cache = OpenStackCache::new(ttl).with_file(&cache_file).read_only();
+        assert_eq!(cache.load().await, 1);
+        let replica = DataService::new(config, Arc::new(cache));
+
+        let (begin_at, end_at) = range("2025-01-31");
+        let served = replica.fetch_data(begin_at, end_at).await;
+        assert!(served.error.is_none());
+        assert!(served.from_cache);
+        assert_eq!(served.rating.costs["compute"], 7.0);
+
+        let (begin_at, end_at) = range("2025-02-28");
+        let missing = replica.fetch_data(begin_at, end_at).await;
+        assert!(missing.error.unwrap().contains("READ_ONLY_REPLICA"));
+        assert_eq!(replica.cli_version().await, "unavailable (read-only replica)");
+        assert!(!calls.exists(), "the replica never runs the CLI");
+
+        let without_file = Config::from_pairs(&[("READ_ONLY_REPLICA", "true")]);
+        assert!(without_file.validate().unwrap_err().to_string().contains("READ_ONLY_REPLICA requires CACHE_FILE"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_timings_are_averaged_per_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo '[{"Resources": [{"service": "compute", "rating": "1"}]}]'"#);
+        let service = service_with(&[("OPENSTACK_COMMAND", &command)]);
+        let query = || service.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+
+        query().await;
+        let first = service.metrics().range_timings();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].range, "2025-01-01T00:00:00+00:00/2025-01-31T23:59:59+00:00");
+        assert_eq!(first[0].count, 1);
+        assert_eq!(first[0].avg_ms, first[0].last_ms);
+
+        // Cache hits run no command, so they are not timed
+        query().await;
+        assert_eq!(service.metrics().range_timings()[0].count, 1);
+
+        service.cache().clear().await;
+        query().await;
+        let second = &service.metrics().range_timings()[0];
+        assert_eq!(second.count, 2);
+        assert!((second.avg_ms - (first[0].last_ms + second.last_ms) / 2.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_parse_matches_buffered_parse() {
+        let dir = tempfile::tempdir().unwrap();
+        let dataframes: Vec<serde_json::Value> = (0..50)
+            .map(|frame| serde_json::json!({
+                "Begin": format!("2025-01-01T{:02}:00:00", frame % 24),
+                "End": format!("2025-01-01T{:02}:59:59", frame % 24),
+                "Resources": (0..1000)
+                    .map(|row| serde_json::json!({
+                        "service": format!("service-{}", row % 37),
+                        "rating": format!("{}.{:03}", row % 11, (frame * row) % 1000),
+                    }))
+                    .collect::<Vec<_>>(),
+            }))
+            .collect();
+        let payload = dir.path().join("payload.json");
+        std::fs::write(&payload, serde_json::to_vec(&dataframes).unwrap()).unwrap();
+        let command = mock_command(&dir, &format!("cat '{}'\necho 'deprecated option' >&2", payload.display()));
+        let vars = [("OPENSTACK_COMMAND", command.as_str()), ("CURRENCY_RATE", "1")];
+        let range = || (Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+
+        let (begin_at, end_at) = range();
+        let buffered = service_with(&vars).fetch_data(begin_at, end_at).await;
+        let (begin_at, end_at) = range();
+        let streamed = service_with(&[vars.as_slice(), &[("STREAMING_PARSE", "true")]].concat())
+            .fetch_data(begin_at, end_at)
+            .await;
+        assert!(streamed.error.is_none(), "{:?}", streamed.error);
+        assert_eq!(streamed.rating.costs.len(), 37);
+        assert_eq!(streamed.rating.costs, buffered.rating.costs);
+        assert_eq!(streamed.rating.row_count, 50_000);
+
+        let broken = mock_command(&dir, r#"echo '[{"Resources": [{"service": "compute",'"#);
+        let (begin_at, end_at) = range();
+        let outcome = service_with(&[("OPENSTACK_COMMAND", &broken), ("STREAMING_PARSE", "true"), ("PARSE_FALLBACK", "false")])
+            .fetch_data(begin_at, end_at)
+            .await;
+        assert!(outcome.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_streaming_parse_error_drains_the_rest_of_the_output() {
+        let dir = tempfile::tempdir().unwrap();
+        // Far more than a pipe holds after the point the parse gives up
+        let command = mock_command(&dir, "echo 'not json'\nhead -c 1000000 /dev/zero | tr '\\0' x");
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("STREAMING_PARSE", "true"), ("PARSE_FALLBACK", "false")]);
+        let outcome = tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            service.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string())),
+        )
+        .await
+        .expect("the command must not block on a full pipe");
+        assert!(outcome.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_streaming_parse_replaces_invalid_utf8_unless_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"printf '[{"Resources": [{"service": "comp\377ute", "rating": "5"}]}]'"#);
+        let range = || (Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+        let vars = [("OPENSTACK_COMMAND", command.as_str()), ("CURRENCY_RATE", "1"), ("STREAMING_PARSE", "true"), ("PARSE_FALLBACK", "false")];
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+
+        let (begin_at, end_at) = range();
+        let outcome = service_with(&vars).fetch_data(begin_at, end_at).await;
+        assert!(outcome.error.is_none(), "{:?}", outcome.error);
+        assert_eq!(outcome.rating.costs["comp\u{FFFD}ute"], 5.0);
+        assert!(logs.text().contains("invalid UTF-8 at byte offset 33"), "{}", logs.text());
+
+        let (begin_at, end_at) = range();
+        let strict = service_with(&[vars.as_slice(), &[("STRICT_UTF8", "true")]].concat()).fetch_data(begin_at, end_at).await;
+        assert!(strict.error.is_some());
+    }
+
+    #[test]
+    fn test_lossy_utf8_completes_characters_split_across_reads() {
+        /// Hands out one byte per read
+        struct Trickle<'a>(&'a [u8]);
+        impl std::io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let Some((first, rest)) = self.0.split_first() else {
+                    return Ok(0);
+                };
+                buf[0] = *first;
+                self.0 = rest;
+                Ok(1)
+            }
+        }
+
+        let input = [b"caf\xc3\xa9 ".as_slice(), b"\xff ", b"\xe2\x82"].concat();
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut LossyUtf8::new(Trickle(&input)), &mut text).unwrap();
+        assert_eq!(text, "caf\u{e9} \u{FFFD} \u{FFFD}");
+    }
+
+    #[tokio::test]
+    async fn test_unparseable_output_is_retried_with_only_the_resources_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"case "$*" in
+  *"-c Begin"*) echo '[{"Begin": "broken' ;;
+  *) echo '[{"Resources": [{"service": "compute", "rating": "6"}]}]' ;;
+esac"#);
+        let vars = [("OPENSTACK_COMMAND", command.as_str()), ("CURRENCY_RATE", "1")];
+        let range = || (Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+
+        let (begin_at, end_at) = range();
+        let outcome = service_with(&vars).fetch_data(begin_at, end_at).await;
+        assert!(outcome.error.is_none(), "{:?}", outcome.error);
+        assert_eq!(outcome.rating.costs["compute"], 6.0);
+        assert!(logs.text().contains("retrying with only the Resources column"));
+
+        let (begin_at, end_at) = range();
+        let outcome = service_with(&[vars.as_slice(), &[("PARSE_FALLBACK", "false")]].concat())
+            .fetch_data(begin_at, end_at)
+            .await;
+        assert!(outcome.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_command_runs_when_primary_cannot_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let fallback = mock_command(&dir, r#"echo '[{"Resources": [{"service": "compute", "rating": "4"}]}]'"#);
+        let vars = [("OPENSTACK_COMMAND", "/nonexistent/openstack"), ("CURRENCY_RATE", "1")];
+        let range = || (Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+
+        let (begin_at, end_at) = range();
+        let outcome = service_with(&[vars.as_slice(), &[("OPENSTACK_COMMAND_FALLBACK", &fallback)]].concat())
+            .fetch_data(begin_at, end_at)
+            .await;
+        assert!(outcome.error.is_none(), "{:?}", outcome.error);
+        assert_eq!(outcome.rating.costs["compute"], 4.0);
+        assert!(logs.text().contains("switching to OPENSTACK_COMMAND_FALLBACK"));
+
+        let (begin_at, end_at) = range();
+        let outcome = service_with(&vars).fetch_data(begin_at, end_at).await;
+        assert!(outcome.error.unwrap().contains("failed to execute OpenStack command"));
+    }
+
+    #[test]
+    fn test_invalid_utf8_output_is_flagged() {
+        let output = b"[{\"Resources\": [{\"service\": \"comp\xffute\", \"rating\": 1}]}]";
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+
+        let lossy = service_with(&[]);
+        let decoded = lossy.decode_output(output).unwrap();
+        assert!(decoded.contains("comp\u{fffd}ute"));
+        let logged = logs.text();
+        assert!(logged.contains("invalid UTF-8 at byte offset 33"), "{}", logged);
+
+        let strict = service_with(&[("STRICT_UTF8", "true")]);
+        assert!(matches!(strict.decode_output(output), Err(FetchError::InvalidOutput(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rows_without_service_flag_schema_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo '[{"Resources": [
+            {"service": "compute", "rating": "5"},
+            {"type": "compute", "rating": "1"},
+            {"type": "storage", "rating": "2"},
+            {"type": "network", "rating": "3"}
+        ]}]'"#);
+        let args = ["rating".to_string()];
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        assert!(service.schema_ok());
+        let rating = service.run_command(&args).await.unwrap();
+        assert!(!service.schema_ok());
+        assert_eq!(rating.costs.len(), 1, "rows without a service are skipped");
+        let line = logs.text().lines().find(|line| line.contains("schema drift")).unwrap().to_string();
+        assert!(line.contains("WARN") && line.contains("schema_drift=true"), "{}", line);
+        assert!(line.contains("missing_service=3") && line.contains("3 of 4 rows"), "{}", line);
+
+        let tolerant = service_with(&[("OPENSTACK_COMMAND", &command), ("SCHEMA_DRIFT_THRESHOLD", "0.8")]);
+        tolerant.run_command(&args).await.unwrap();
+        assert!(tolerant.schema_ok());
+    }
+
+    #[test]
+    fn test_ssh_command_wraps_quoted_openstack_args() {
+        let args: Vec<String> = ["--os-password", "it's secret", "rating", "dataframes", "get", "-b", "2025-01-01T00:00:00+00:00"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+
+        let local = service_with(&[]);
+        assert_eq!(local.command_line(&args), ("openstack".to_string(), args.clone()));
+
+        let remote = service_with(&[("SSH_HOST", "bastion.example.com"), ("SSH_USER", "ops"), ("SSH_KEY", "/etc/dashboard/id_ed25519")]);
+        let (program, ssh_args) = remote.command_line(&args);
+        assert_eq!(program, "ssh");
+        assert_eq!(ssh_args[..6], ["-o", "BatchMode=yes", "-i", "/etc/dashboard/id_ed25519", "ops@bastion.example.com", "--"]);
+        assert_eq!(
+            ssh_args[6],
+            r"openstack --os-password 'it'\''s secret' rating dataframes get -b 2025-01-01T00:00:00+00:00"
+        );
+        assert_eq!(ssh_args.len(), 7);
+
+        let (_, shown) = remote.command_line(&remote.redact_sensitive_args(&args));
+        assert!(shown[6].contains("--os-password '[REDACTED]'"), "{}", shown[6]);
+        assert!(!shown.join(" ").contains("secret"));
+    }
+
+    #[test]
+    fn test_ssh_password_is_sent_in_the_environment() {
+        use std::ffi::OsStr;
+
+        let remote = service_with(&[("SSH_HOST", "bastion.example.com"), ("OS_USERNAME", "ops"), ("OS_PASSWORD", "hunter2")]);
+        let args = remote.build_args(None, "2025-01-01T00:00:00+00:00", "2025-02-01T00:00:00+00:00");
+        assert!(!args.contains(&"--os-password".to_string()));
+
+        let cli = remote.cli_command("openstack", &args);
+        let cli = cli.as_std();
+        assert_eq!(cli.get_program(), "ssh");
+        let ssh_args: Vec<_> = cli.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        assert_eq!(ssh_args[..4], ["-o", "BatchMode=yes", "-o", "SendEnv=OS_PASSWORD"]);
+        assert!(!ssh_args.join(" ").contains("hunter2"));
+        assert!(cli.get_envs().any(|env| env == (OsStr::new("OS_PASSWORD"), Some(OsStr::new("hunter2")))));
+
+        let local = service_with(&[("OS_PASSWORD", "hunter2")]);
+        assert!(local.build_args(None, "2025-01-01T00:00:00+00:00", "2025-02-01T00:00:00+00:00").contains(&"hunter2".to_string()));
+        assert_eq!(local.cli_command("openstack", &[]).as_std().get_envs().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_stderr_on_success_is_logged_at_debug() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo 'DeprecationWarning: old plugin' >&2
+echo '[{"Resources": [{"service": "compute", "rating": "5"}]}]'"#);
+        let args = ["rating".to_string()];
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+
+        let quiet = service_with(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        let rating = quiet.run_command(&args).await.unwrap();
+        assert_eq!(rating.costs["compute"], 5.0);
+        let line = logs.text().lines().find(|line| line.contains("DeprecationWarning")).unwrap().to_string();
+        assert!(line.contains("DEBUG"), "{}", line);
+
+        let loud = service_with(&[("OPENSTACK_COMMAND", &command), ("WARN_ON_STDERR", "true")]);
+        loud.run_command(&args).await.unwrap();
+        assert!(logs.text().lines().any(|line| line.contains("WARN") && line.contains("DeprecationWarning")));
+    }
+
+    #[test]
+    fn test_cost_scale_divides_displayed_costs() {
+        let service = service_with(&[
+            ("CURRENCY_RATE", "1"),
+            ("COST_SCALE", "1000"),
+            ("INCLUDE_RAW_VALUES", "true"),
+        ]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[("compute", "250000"), ("storage", "50000")])));
+        assert_eq!(chart.values, vec![250.0, 50.0]);
+        assert_eq!(chart.total_cost, 300.0);
+        assert_eq!(chart.average_cost, 150.0);
+        assert_eq!(chart.raw_values, vec![250_000.0, 50_000.0]);
+    }
+
+    #[test]
+    fn test_services_roll_up_into_categories() {
+        let service = service_with(&[
+            ("CURRENCY_RATE", "1"),
+            ("SERVICE_CATEGORIES", "Compute=instance,gpu; Storage=volume"),
+        ]);
+        let rating = service.process_resources(wrappers(&[
+            ("instance", "4"),
+            ("gpu", "6"),
+            ("volume", "3"),
+            ("floating_ip", "1"),
+        ]));
+        let chart = service.process_categories(&rating);
+
+        assert_eq!(chart.labels, vec!["Compute", "Storage", "Other"]);
+        assert_eq!(chart.values, vec![10.0, 3.0, 1.0]);
+        assert_eq!(chart.total_cost, 14.0);
+        assert_eq!(chart.service_count, 3);
+    }
+
+    #[test]
+    fn test_services_are_grouped_by_regex_prefix() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("GROUP_REGEX", "^(team[A-Z])-")]);
+        let rating = service.process_resources(wrappers(&[
+            ("teamA-compute", "5"), ("teamA-storage", "1"), ("teamB-storage", "2"), ("shared-dns", "0.5"),
+        ]));
+        let chart = service.process_groups(&rating, None);
+        assert_eq!(chart.labels, vec!["teamA", "teamB", "ungrouped"]);
+        assert_eq!(chart.values, vec![6.0, 2.0, 0.5]);
+
+        let allowed = ["teamB-storage".to_string(), "shared-dns".to_string()];
+        let chart = service.process_groups(&rating, Some(&allowed));
+        assert_eq!(chart.labels, vec!["teamB", "ungrouped"]);
+        assert_eq!(chart.total_cost, 2.5);
+    }
+
+    #[test]
+    fn test_group_names_are_anonymized() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("GROUP_REGEX", "^(team[A-Z])-"), ("ANONYMIZE_SERVICES", "true")]);
+        let rating = service.process_resources(wrappers(&[("teamA-compute", "5"), ("shared-dns", "0.5")]));
+        let chart = service.process_groups(&rating, None);
+        assert_eq!(chart.labels, vec![anonymize_service("teamA"), "ungrouped".to_string()]);
+    }
+
+    #[test]
+    fn test_long_labels_are_truncated_but_kept_in_full() {
+        let long_name = "object-storage-replicated-across-three-availability-zones";
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("MAX_LABEL_LENGTH", "20")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[
+            (long_name, "5"),
+            (long_name, "2"),
+            ("compute", "1"),
+        ])));
+
+        assert_eq!(chart.labels[0].chars().count(), 20);
+        assert!(chart.labels[0].ends_with('…'));
+        assert_eq!(chart.labels[1], "compute");
+        assert_eq!(chart.full_labels, vec![long_name, "compute"]);
+        assert_eq!(chart.values, vec![7.0, 1.0]);
+        assert_eq!(chart.keys()[0], long_name);
+    }
+
+    #[tokio::test]
+    async fn test_raw_output_is_captured_and_redacted_when_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo '[{"Resources": [], "token": "abc123"}] hunter2 s3cret'"#);
+        let range = || (Some("2025-01-01".to_string()), Some("2025-01-31".to_string()));
+
+        let service = service_with(&[("OPENSTACK_COMMAND", &command), ("OS_PASSWORD", "hunter2")]);
+        let (begin, end) = range();
+        service.fetch_data(begin, end).await;
+        assert!(service.last_output().is_none());
+
+        let service = service_with(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("OS_PASSWORD", "hunter2"),
+            ("DEBUG_CAPTURE_OUTPUT", "true"),
+            ("API_TOKEN", "s3cret"),
+        ]);
+        let (begin, end) = range();
+        service.fetch_data(begin, end).await;
+        let captured = service.last_output().expect("output should be captured");
+        assert!(captured.stdout.starts_with(r#"[{"Resources": [], "token": "[REDACTED]"}]"#));
+        assert!(!captured.stdout.contains("abc123"));
+        assert!(!captured.stdout.contains("hunter2"));
+        assert!(!captured.stdout.contains("s3cret"));
+        assert!(!captured.truncated);
+    }
+
+    #[test]
+    fn test_additional_currencies_convert_raw_rating() {
+        let service = service_with(&[("CURRENCY_RATE", "50"), ("CURRENCY_RATES", "usd=40, EUR=80")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[("compute", "400"), ("storage", "160")])));
+
+        assert_eq!(chart.total_cost, 11.2);
+        assert_eq!(chart.currencies["USD"].values, vec![10.0, 4.0]);
+        assert_eq!(chart.currencies["USD"].total_cost, 560.0 / 40.0);
+        assert_eq!(chart.currencies["EUR"].values, vec![5.0, 2.0]);
+        assert_eq!(chart.currencies["EUR"].total_cost, 560.0 / 80.0);
+    }
+
+    #[test]
+    fn test_reprice_keeps_transformed_costs_and_deltas() {
+        let service = service_with(&[("CURRENCY_RATE", "50"), ("SERVICE_COST_CAP", "10")]);
+        // A TRANSFORM_COMMAND marked compute up, which its raw rating carries too
+        let rating = RatingData {
+            costs: HashMap::from([("compute".to_string(), 3.0), ("storage".to_string(), 1.0), ("gpu".to_string(), 40.0)]),
+            raw_costs: HashMap::from([("compute".to_string(), 150.0), ("storage".to_string(), 50.0), ("gpu".to_string(), 2000.0)]),
+            ..RatingData::default()
+        };
+        let mut chart = service.process_data(&rating);
+        chart.deltas = Some(BTreeMap::from([("compute".to_string(), 1.5)]));
+        assert_eq!(chart.anomalies, vec!["gpu"]);
+
+        let repriced = service.reprice(&chart, 100.0);
+        assert_eq!(repriced.labels, vec!["gpu", "compute", "storage"]);
+        assert_eq!(repriced.values, vec![10.0, 1.5, 0.5]);
+        assert_eq!(repriced.total_cost, 12.0);
+        assert_eq!(repriced.deltas, Some(BTreeMap::from([("compute".to_string(), 0.75)])));
+        assert_eq!(repriced.anomalies, vec!["gpu"]);
+
+        // At a rate where the raw rating fits under the cap it is no longer clamped
+        let cheaper = service.reprice(&chart, 400.0);
+        assert_eq!(cheaper.values, vec![5.0, 0.375, 0.125]);
+        assert!(cheaper.anomalies.is_empty());
+    }
+
+    #[test]
+    fn test_reprice_rounds_from_the_raw_rating() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("COST_PRECISION", "0")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[("compute", "0.6")])));
+        assert_eq!(chart.values, vec![1.0]);
+
+        let repriced = service.reprice(&chart, 0.5);
+        assert_eq!(repriced.values, vec![1.0]);
+        assert_eq!(repriced.total_cost, 1.0);
+    }
+
+    #[test]
+    fn test_additional_currencies_use_the_cost_scale() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("CURRENCY_RATES", "USD=2"), ("COST_SCALE", "1000")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[("compute", "4000"), ("storage", "2000")])));
+
+        assert_eq!(chart.total_cost, 6.0);
+        assert_eq!(chart.currencies["USD"].values, vec![2.0, 1.0]);
+        assert_eq!(chart.currencies["USD"].total_cost, 3.0);
+    }
+
+    #[test]
+    fn test_costs_split_by_domain() {
+        let service = service_with(&[("CURRENCY_RATE", "1"), ("SPLIT_BY", "domain")]);
+        let payload = r#"[{"Resources": [
+            {"service": "compute", "rating": "4", "domain_id": "research"},
+            {"service": "storage", "rating": "1", "domain_id": "research"},
+            {"service": "compute", "rating": "7", "domain_id": "teaching"},
+            {"service": "network", "rating": "2"}
+        ]}]"#;
+        let rating = service.process_resources(serde_json::from_str(payload).unwrap());
+        let chart = service.process_domains(&rating);
+
+        assert_eq!(chart.labels, vec!["teaching", "research", "unknown"]);
+        assert_eq!(chart.values, vec![7.0, 5.0, 2.0]);
+        assert_eq!(chart.total_cost, 14.0);
+        assert_eq!(rating.costs["compute"], 11.0);
+    }
+
+    #[test]
+    fn test_costs_serialize_without_float_noise() {
+        // No COST_PRECISION, so only the serializer trims the sum
+        let service = service_with(&[("CURRENCY_RATE", "1")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[("compute", "0.2"), ("storage", "0.1")])));
+        assert_ne!(chart.total_cost, 0.3, "internal sum keeps full precision");
+
+        let json = serde_json::to_string(&chart).unwrap();
+        assert!(json.contains(r#""values":[0.2,0.1]"#), "{}", json);
+        assert!(json.contains(r#""total_cost":0.3,"#), "{}", json);
+        assert!(json.contains(r#""average_cost":0.15,"#), "{}", json);
+        assert!(!json.contains("raw_costs"));
+
+        // Only the digits a float can't carry reliably are dropped
+        let service = service_with(&[("CURRENCY_RATE", "3")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[("compute", "1"), ("storage", "1234")])));
+        let json = serde_json::to_string(&chart).unwrap();
+        assert!(json.contains(r#""values":[411.333333333,0.333333333333]"#), "{}", json);
+
+        // COST_PRECISION rounds when costs are computed, and serializing keeps those decimals
+        let service = service_with(&[("CURRENCY_RATE", "3"), ("COST_PRECISION", "2")]);
+        let chart = service.process_data(&service.process_resources(wrappers(&[("compute", "1"), ("storage", "1234")])));
+        assert_eq!(chart.values, vec![411.33, 0.33]);
+        let json = serde_json::to_string(&chart).unwrap();
+        assert!(json.contains(r#""values":[411.33,0.33]"#), "{}", json);
+        assert!(json.contains(r#""total_cost":411.66,"#), "{}", json);
+    }
+
+    #[test]
+    fn test_rating_is_currency_skips_conversion() {
+        let service = service_with(&[("CURRENCY_RATE", "55.5"), ("RATING_IS_CURRENCY", "true")]);
+        let data = service.process_resources(wrappers(&[("compute", "12.5"), ("storage", "3")]));
+        assert_eq!(data.costs["compute"], 12.5);
+        assert_eq!(data.costs["storage"], 3.0);
+        assert_eq!(data.raw_costs["compute"], 12.5);
+    }
+
+    #[test]
+    fn test_numeric_and_string_ratings_both_parse() {
+        let service = service_with(&[("CURRENCY_RATE", "1")]);
+        let numeric = r#"[{"Resources": [{"service": "compute", "rating": 2.5}, {"service": "compute", "rating": 1}]}]"#;
+        let rating = service.process_resources(serde_json::from_str(numeric).unwrap());
+        assert_eq!(rating.costs["compute"], 3.5);
+
+        let string = r#"[{"Resources": [{"service": "compute", "rating": "2.5"}, {"service": "storage", "rating": "n/a"}]}]"#;
+        let rating = service.process_resources(serde_json::from_str(string).unwrap());
+        assert_eq!(rating.costs["compute"], 2.5);
+        assert!(!rating.costs.contains_key("storage"), "unparsable ratings are skipped");
     }
 }
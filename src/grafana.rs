@@ -0,0 +1,117 @@
+//! Grafana SimpleJSON datasource API over the history buffer
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::history::HistoryPoint;
+
+/// Target naming the total cost rather than a single service
+pub const TOTAL_TARGET: &str = "total";
+
+/// Body of `POST /search`
+#[derive(Debug, Deserialize, Default)]
+pub struct SearchRequest {
+    /// Text typed in the query editor; only targets containing it are listed
+    #[serde(default)]
+    pub target: String,
+}
+
+/// Time range of a `POST /query`
+#[derive(Debug, Deserialize)]
+pub struct QueryRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+/// A requested series of a `POST /query`
+#[derive(Debug, Deserialize)]
+pub struct QueryTarget {
+    pub target: String,
+}
+
+/// Body of `POST /query`
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    pub range: QueryRange,
+    pub targets: Vec<QueryTarget>,
+}
+
+/// One series of a `POST /query` response
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TimeSeries {
+    pub target: String,
+    /// `[value, unix milliseconds]` pairs, oldest first
+    pub datapoints: Vec<(f64, i64)>,
+}
+
+/// Build the series of each target from the history snapshots inside the range
+///
+/// Snapshots where a service was absent are left out of its series.
+pub fn query(points: &[HistoryPoint], request: &QueryRequest) -> Vec<TimeSeries> {
+    let in_range: Vec<(i64, &HistoryPoint)> = points
+        .iter()
+        .filter_map(|point| {
+            let timestamp = DateTime::parse_from_rfc3339(&point.timestamp).ok()?.with_timezone(&Utc);
+            (request.range.from..=request.range.to)
+                .contains(&timestamp)
+                .then(|| (timestamp.timestamp_millis(), point))
+        })
+        .collect();
+    request
+        .targets
+        .iter()
+        .map(|target| TimeSeries {
+            target: target.target.clone(),
+            datapoints: in_range
+                .iter()
+                .filter_map(|(millis, point)| {
+                    let value = if target.target == TOTAL_TARGET {
+                        Some(point.total_cost)
+                    } else {
+                        point.services.get(&target.target).copied()
+                    };
+                    value.map(|value| (value, *millis))
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn point(timestamp: &str, compute: f64) -> HistoryPoint {
+        HistoryPoint {
+            timestamp: timestamp.to_string(),
+            total_cost: compute + 1.0,
+            cost_per_service: (compute + 1.0) / 2.0,
+            services: HashMap::from([("compute".to_string(), compute), ("storage".to_string(), 1.0)]),
+            cache_hits: 0,
+            cache_misses: 0,
+        }
+    }
+
+    #[test]
+    fn test_query_returns_datapoints_in_range() {
+        let points = [
+            point("2025-01-01T00:00:00+00:00", 1.0),
+            point("2025-01-02T00:00:00+00:00", 2.0),
+            point("2025-01-03T00:00:00+00:00", 3.0),
+        ];
+        let request: QueryRequest = serde_json::from_value(serde_json::json!({
+            "range": { "from": "2025-01-01T12:00:00.000Z", "to": "2025-01-03T00:00:00.000Z" },
+            "targets": [{ "target": "compute", "refId": "A", "type": "timeserie" }, { "target": "total" }],
+            "maxDataPoints": 500
+        }))
+        .unwrap();
+
+        let series = query(&points, &request);
+        assert_eq!(serde_json::to_value(&series[0]).unwrap(), serde_json::json!({
+            "target": "compute",
+            "datapoints": [[2.0, 1_735_776_000_000_i64], [3.0, 1_735_862_400_000_i64]]
+        }));
+        assert_eq!(series[1].datapoints, vec![(3.0, 1_735_776_000_000), (4.0, 1_735_862_400_000)]);
+    }
+}
@@ -2,52 +2,1127 @@
 
 use axum::{
     extract::State,
-    http::StatusCode,
-    response::{Html, Json},
+    Extension,
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{Html, IntoResponse, Json, Response},
 };
 use axum::extract::Query;
-use serde::Deserialize;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use chrono::{Local, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::info;
-use crate::models::ChartData;
+use crate::models::{CapturedOutput, ChartData, CommandPreview, CurrencyBreakdown, RefreshResponse, TimeBucket};
+use crate::baseline::{compute_variance, Variance};
+use crate::burndown::{self, Burndown};
+use crate::cache::CacheStats;
+use crate::config::{Config, SplitBy};
+use crate::error::ApiError;
+use crate::grafana::{self, QueryRequest, SearchRequest, TimeSeries};
+use crate::history::{Efficiency, HistoryPoint, Sparklines};
+use crate::metrics::{ExpositionFormat, RangeTiming};
+use crate::middleware::{allowed_services, ServerTimings};
+use crate::render::{render_png, ChartKind};
+use crate::rolling::{parse_window, RollingSum};
+use crate::sink;
 use crate::AppState;
 
+/// Dashboard HTML template served at `/`
+pub const INDEX_TEMPLATE: &str = include_str!("../templates/index.html");
+
 #[derive(Deserialize)]
 pub struct DateRange {
     begin_at: Option<String>,
     end_at: Option<String>,
+    /// Any other query parameters, refused with `STRICT_QUERY_PARAMS`
+    #[serde(flatten)]
+    unknown: BTreeMap<String, String>,
+}
+
+impl DateRange {
+    /// Reject ranges that are reversed, reach into the future or exceed `MAX_RANGE_DAYS`,
+    /// and unknown parameters in strict mode
+    fn validate(&self, state: &AppState) -> Result<(), ApiError> {
+        if state.config().strict_query_params && !self.unknown.is_empty() {
+            let unknown: Vec<&str> = self.unknown.keys().map(String::as_str).collect();
+            return Err(ApiError::bad_request(format!(
+                "Unknown query parameters: {} (expected begin_at, end_at)",
+                unknown.join(", ")
+            )));
+        }
+        state
+            .data_service
+            .resolve_date_range(self.begin_at.clone(), self.end_at.clone())
+            .map(|_| ())
+            .map_err(|reason| ApiError::bad_request(format!("Invalid date range: {}", reason)))
+    }
+}
+
+/// Body of `POST /api/clouds/active`
+#[derive(Deserialize)]
+pub struct CloudSelection {
+    name: String,
+}
+
+/// Configured clouds and the one currently in use
+#[derive(Serialize)]
+pub struct CloudList {
+    clouds: Vec<String>,
+    active: Option<String>,
+}
+
+/// Dashboard page rendered once from the embedded template, with its validators
+#[derive(Debug, Clone)]
+pub struct IndexPage {
+    html: Arc<str>,
+    etag: String,
+    last_modified: String,
+}
+
+impl IndexPage {
+    /// Render the page for a configuration
+    ///
+    /// The template is compiled into the binary, so the page only changes when the
+    /// process restarts with a new build or new branding.
+    pub fn new(config: &Config) -> Self {
+        let html = render_index(config);
+        let digest = Sha256::digest(html.as_bytes());
+        let hash: String = digest.iter().take(16).map(|byte| format!("{:02x}", byte)).collect();
+        Self {
+            html: html.into(),
+            etag: format!("\"{}\"", hash),
+            last_modified: Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        }
+    }
+
+    /// Whether an `If-None-Match` header value matches this page
+    fn matches(&self, if_none_match: &str) -> bool {
+        if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == self.etag)
+    }
+}
+
+/// Serve the main HTML page, answering revalidation with `304 Not Modified`
+pub async fn serve_index(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let page = &state.index_page;
+    let validators = [
+        (header::CACHE_CONTROL, "no-cache".to_string()),
+        (header::ETAG, page.etag.clone()),
+        (header::LAST_MODIFIED, page.last_modified.clone()),
+    ];
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| page.matches(value));
+    if not_modified {
+        (StatusCode::NOT_MODIFIED, validators).into_response()
+    } else {
+        (validators, Html(page.html.to_string())).into_response()
+    }
+}
+
+/// Fill the template placeholders with the configured branding
+pub fn render_index(config: &Config) -> String {
+    let logo = config
+        .dashboard_logo_url
+        .as_deref()
+        .map(|url| {
+            // Only assets served by the dashboard itself are versioned; external URLs may be signed
+            let url = if url.starts_with('/') { versioned_asset(url, &config.asset_version) } else { url.to_string() };
+            format!("<img class=\"logo\" src=\"{}\" alt=\"\">", escape_html(&url))
+        })
+        .unwrap_or_default();
+
+    INDEX_TEMPLATE
+        .replace("{{DASHBOARD_TITLE}}", &escape_html(&config.dashboard_title))
+        .replace("{{DASHBOARD_LOGO}}", &logo)
+        .replace("{{CHART_PALETTE}}", &serde_json::to_string(&config.chart_palette).unwrap_or_default())
+        .replace("{{ASSET_VERSION}}", &escape_html(&config.asset_version))
+}
+
+/// Append the asset version to a URL as the `v` query parameter
+fn versioned_asset(url: &str, version: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}v={}", url, separator, version)
 }
 
-/// Serve the main HTML page
-pub async fn serve_index() -> Html<String> {
-    Html(include_str!("../templates/index.html").to_string())
+/// Escape text for safe inclusion in HTML content and attributes
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
 }
 
-/// Get current chart data
-pub async fn get_chart_data(State(state): State<AppState>) -> Json<ChartData> {
-    let data = state.chart_data.read().await;
-    Json(data.clone())
+/// List the configured clouds
+pub async fn list_clouds(State(state): State<AppState>) -> Json<CloudList> {
+    Json(CloudList {
+        clouds: state.data_service.clouds(),
+        active: state.data_service.active_cloud(),
+    })
+}
+
+/// Switch the active cloud and refresh the chart from it
+pub async fn set_active_cloud(
+    State(state): State<AppState>,
+    selection: Result<Json<CloudSelection>, JsonRejection>,
+) -> Result<Json<RefreshResponse>, ApiError> {
+    let Json(selection) = selection.map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+    if !state.data_service.set_active_cloud(&selection.name) {
+        return Err(ApiError::not_found(format!("Unknown cloud: {}", selection.name)));
+    }
+
+    let outcome = state.data_service.fetch_data(None, None).await;
+    let from_cache = outcome.from_cache;
+    let chart = state.data_service.process_outcome(outcome);
+    *state.chart_data.write().await = chart.clone();
+    Ok(Json(RefreshResponse {
+        chart,
+        from_cache,
+        replica: state.config().read_only_replica,
+    }))
+}
+
+/// Get current chart data, optionally repriced with a one-off `?currency_rate=`
+pub async fn get_chart_data(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    query: Result<Query<DataQuery>, QueryRejection>,
+) -> Result<Json<ChartData>, ApiError> {
+    let Query(query) = query.map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+    let allowed = allowed_services(&state.config(), &headers)?;
+    let Some(currency_rate) = query.currency_rate else {
+        return Ok(Json(visible_chart(&state, allowed.as_deref()).await));
+    };
+    if !currency_rate.is_finite() || currency_rate <= 0.0 {
+        return Err(ApiError::bad_request("currency_rate must be a positive number"));
+    }
+
+    // Reprice a copy so the shared chart keeps the configured rate
+    let chart = state.data_service.reprice(&*state.chart_data.read().await, currency_rate);
+    Ok(Json(match allowed {
+        Some(allowed) => state.data_service.restrict(&chart, &allowed),
+        None => chart,
+    }))
+}
+
+/// Copy of the current chart narrowed to the `allowed` services, if limited
+async fn visible_chart(state: &AppState, allowed: Option<&[String]>) -> ChartData {
+    let chart = state.chart_data.read().await;
+    match allowed {
+        Some(allowed) => state.data_service.restrict(&chart, allowed),
+        None => chart.clone(),
+    }
+}
+
+/// Get the current chart as a Chart.js configuration object
+///
+/// Embeds can pass the response straight to `new Chart(ctx, config)`.
+pub async fn get_chartjs(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<serde_json::Value>, ApiError> {
+    let config = state.config();
+    let chart = visible_chart(&state, allowed_services(&config, &headers)?.as_deref()).await;
+    // Reuse the `/api/data` serialization so values get the same rounding
+    let data = serde_json::to_value(&chart).unwrap_or_default();
+    let palette = &config.chart_palette;
+    let colors: Vec<&String> = palette.iter().cycle().take(chart.values.len()).collect();
+    Ok(Json(serde_json::json!({
+        "type": "bar",
+        "data": {
+            "labels": data["labels"],
+            "datasets": [{
+                "label": format!("Cost ({})", config.cost_unit_label),
+                "data": data["values"],
+                "backgroundColor": colors,
+                "borderWidth": 1,
+            }],
+        },
+        "options": {
+            "responsive": true,
+            "plugins": {
+                "title": { "display": true, "text": config.dashboard_title },
+                "legend": { "display": false },
+            },
+            "scales": { "y": { "beginAtZero": true } },
+        },
+    })))
+}
+
+/// Query parameters of `GET /api/data`
+#[derive(Deserialize)]
+pub struct DataQuery {
+    /// One-off currency rate to price the current data with
+    currency_rate: Option<f64>,
+}
+
+/// Query parameters of `GET /api/rolling`
+#[derive(Deserialize)]
+pub struct RollingQuery {
+    /// Trailing window such as `90m`, `24h` or `7d`
+    window: Option<String>,
+}
+
+/// Get costs summed over a trailing window such as the last 24 hours
+pub async fn get_rolling(
+    State(state): State<AppState>,
+    query: Result<Query<RollingQuery>, QueryRejection>,
+) -> Result<Json<RollingSum>, ApiError> {
+    let Query(query) = query.map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+    let Some(retention) = state.data_service.rolling_retention() else {
+        return Err(ApiError::not_found("Rolling totals are disabled (set ROLLING_RETENTION_HOURS)"));
+    };
+    let window = query.window.as_deref().unwrap_or("24h");
+    let Some(window) = parse_window(window) else {
+        return Err(ApiError::bad_request(format!("Invalid window '{}': use minutes, hours or days such as 90m, 24h or 7d", window)));
+    };
+    if window > retention {
+        return Err(ApiError::bad_request(format!(
+            "Window exceeds the {}h retention (ROLLING_RETENTION_HOURS)",
+            retention.as_secs() / 3600
+        )));
+    }
+    state
+        .data_service
+        .rolling_sum(window)
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("Rolling totals are disabled (set ROLLING_RETENTION_HOURS)"))
+}
+
+/// Query parameters of `GET /api/chart.png`
+#[derive(Deserialize)]
+pub struct ChartImageQuery {
+    #[serde(rename = "type", default)]
+    kind: ChartKind,
+    #[serde(default = "default_image_width")]
+    width: u32,
+    #[serde(default = "default_image_height")]
+    height: u32,
+}
+
+fn default_image_width() -> u32 {
+    800
+}
+
+fn default_image_height() -> u32 {
+    500
+}
+
+/// Get the current chart rendered as a PNG image
+pub async fn get_chart_png(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    query: Result<Query<ChartImageQuery>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let Query(query) = query.map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+    let config = state.config();
+    let allowed = allowed_services(&config, &headers)?;
+    for (name, value) in [("width", query.width), ("height", query.height)] {
+        if !(100..=4000).contains(&value) {
+            return Err(ApiError::bad_request(format!("{} must be between 100 and 4000 pixels", name)));
+        }
+    }
+    let chart = visible_chart(&state, allowed.as_deref()).await;
+    let png = tokio::task::spawn_blocking(move || {
+        render_png(&chart, query.kind, (query.width, query.height), &config.chart_palette, &config.dashboard_title, &config.chart_font)
+    })
+    .await
+    .map_err(|e| ApiError::internal(e.to_string()))?
+    .map_err(|e| ApiError::internal(format!("Failed to render chart: {}", e)))?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+/// Get the current costs in each additional currency from `CURRENCY_RATES`
+pub async fn get_currencies(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<CurrencyBreakdown>, ApiError> {
+    let chart = visible_chart(&state, allowed_services(&state.config(), &headers)?.as_deref()).await;
+    Ok(Json(CurrencyBreakdown {
+        labels: chart.labels,
+        currencies: chart.currencies,
+    }))
+}
+
+/// Error for a failed fetch: unavailable when it never got a fetch slot, upstream otherwise
+fn fetch_error(error: String, overloaded: bool) -> ApiError {
+    if overloaded {
+        ApiError::unavailable(error)
+    } else {
+        ApiError::upstream(error)
+    }
+}
+
+/// Get the current costs split by Keystone domain
+pub async fn get_data_by_domain(State(state): State<AppState>) -> Result<Json<ChartData>, ApiError> {
+    if state.config().split_by != Some(SplitBy::Domain) {
+        return Err(ApiError::not_found("Splitting by domain is disabled (set SPLIT_BY=domain)"));
+    }
+    let outcome = state.data_service.fetch_data(None, None).await;
+    if let Some(error) = outcome.error {
+        return Err(fetch_error(error, outcome.overloaded));
+    }
+    let mut chart = state.data_service.process_domains(&outcome.rating);
+    chart.stale = outcome.stale;
+    Ok(Json(chart))
+}
+
+/// Get the current costs of each `RATING_ENDPOINTS` endpoint
+///
+/// Endpoints that could not be fetched are listed with their error.
+pub async fn get_data_by_endpoint(State(state): State<AppState>) -> Result<Json<BTreeMap<String, ChartData>>, ApiError> {
+    if state.config().rating_endpoints.is_empty() {
+        return Err(ApiError::not_found("No rating endpoints are configured (set RATING_ENDPOINTS)"));
+    }
+    let outcome = state.data_service.fetch_data(None, None).await;
+    if let Some(error) = outcome.error {
+        return Err(fetch_error(error, outcome.overloaded));
+    }
+    let mut charts: BTreeMap<String, ChartData> = outcome.rating.by_endpoint
+        .iter()
+        .map(|(endpoint, rating)| {
+            let mut chart = state.data_service.process_data(rating);
+            chart.stale = outcome.stale;
+            (endpoint.clone(), chart)
+        })
+        .collect();
+    for (endpoint, error) in outcome.endpoint_errors {
+        charts.insert(endpoint, ChartData { error: Some(error), ..ChartData::empty() });
+    }
+    Ok(Json(charts))
+}
+
+/// Get the current costs rolled up into the configured service categories
+pub async fn get_data_by_category(State(state): State<AppState>) -> Result<Json<ChartData>, ApiError> {
+    let outcome = state.data_service.fetch_data(None, None).await;
+    if let Some(error) = outcome.error {
+        return Err(fetch_error(error, outcome.overloaded));
+    }
+    let mut chart = state.data_service.process_categories(&outcome.rating);
+    chart.stale = outcome.stale;
+    Ok(Json(chart))
+}
+
+/// Get the current costs totalled per `GROUP_REGEX` group, over the services the token may view
+pub async fn get_data_grouped(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<ChartData>, ApiError> {
+    let config = state.config();
+    if config.group_regex.is_none() {
+        return Err(ApiError::not_found("Grouping is disabled (set GROUP_REGEX)"));
+    }
+    let allowed = allowed_services(&config, &headers)?;
+    let outcome = state.data_service.fetch_data(None, None).await;
+    if let Some(error) = outcome.error {
+        return Err(fetch_error(error, outcome.overloaded));
+    }
+    let mut chart = state.data_service.process_groups(&outcome.rating, allowed.as_deref());
+    chart.stale = outcome.stale;
+    Ok(Json(chart))
 }
 
 /// Refresh data manually
-pub async fn refresh_data(State(state): State<AppState>, Query(date_range): Query<DateRange>) -> Json<ChartData> {
+///
+/// A failed fetch is still stored so the dashboard shows the error, and is
+/// reported to the caller as an upstream error. A refresh that never got a
+/// fetch slot leaves the stored data alone and is reported as unavailable.
+/// A `READ_ONLY_REPLICA` refreshes from the cached data only.
+pub async fn refresh_data(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(date_range): Query<DateRange>,
+) -> Result<(Extension<ServerTimings>, Json<RefreshResponse>), ApiError> {
+    let config = state.config();
     info!("Manual refresh requested");
+    date_range.validate(&state)?;
+    let allowed = allowed_services(&config, &headers)?;
+    state.refresh_count.fetch_add(1, Ordering::Relaxed);
     
-    let new_data = state.data_service.fetch_data(date_range.begin_at, date_range.end_at).await;
-    let new_chart_data = state.data_service.process_data(new_data);
-    *state.chart_data.write().await = new_chart_data.clone();
-    Json(new_chart_data)
+    let started = Instant::now();
+    let outcome = state.data_service.fetch_data(date_range.begin_at, date_range.end_at).await;
+    let fetched = started.elapsed();
+    if outcome.overloaded
+        && let Some(error) = outcome.error
+    {
+        return Err(fetch_error(error, true));
+    }
+    let from_cache = outcome.from_cache;
+    let published = outcome.error.is_none() && !outcome.stale;
+    let range = outcome.range.clone();
+    state.data_service.alert_new_services(&outcome).await;
+    let started = Instant::now();
+    let mut new_chart_data = state.data_service.process_outcome(outcome);
+    let timings = ServerTimings(vec![("fetch", fetched), ("process", started.elapsed())]);
+    if published && let Some(sink) = &state.sink {
+        sink::publish(sink, range.as_ref(), &new_chart_data).await;
+    }
+    {
+        let mut chart_data = state.chart_data.write().await;
+        state.data_service.apply_deltas(&mut new_chart_data, &chart_data);
+        *chart_data = new_chart_data.clone();
+    }
+    if let Some(error) = &new_chart_data.error {
+        return Err(ApiError::upstream(error.clone()));
+    }
+    let chart = match allowed {
+        Some(allowed) => state.data_service.restrict(&new_chart_data, &allowed),
+        None => new_chart_data,
+    };
+    Ok((Extension(timings), Json(RefreshResponse {
+        chart,
+        from_cache,
+        replica: config.read_only_replica,
+    })))
+}
+
+/// Get chart data for the requested period without replacing the shared chart
+///
+/// The fetch goes through the cache like any other, so querying a range warms it
+/// for later refreshes of the same range.
+pub async fn query_data(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(date_range): Query<DateRange>,
+) -> Result<(Extension<ServerTimings>, Json<ChartData>), ApiError> {
+    date_range.validate(&state)?;
+    let allowed = allowed_services(&state.config(), &headers)?;
+    let started = Instant::now();
+    let outcome = state.data_service.fetch_data(date_range.begin_at, date_range.end_at).await;
+    let fetched = started.elapsed();
+    if let Some(error) = outcome.error {
+        return Err(fetch_error(error, outcome.overloaded));
+    }
+    let started = Instant::now();
+    let chart = state.data_service.process_outcome(outcome);
+    let chart = match allowed {
+        Some(allowed) => state.data_service.restrict(&chart, &allowed),
+        None => chart,
+    };
+    let timings = ServerTimings(vec![("fetch", fetched), ("process", started.elapsed())]);
+    Ok((Extension(timings), Json(chart)))
+}
+
+/// Get costs bucketed by dataframe window for the requested period
+pub async fn get_timeseries(
+    State(state): State<AppState>,
+    Query(date_range): Query<DateRange>,
+) -> Result<(Extension<ServerTimings>, Json<Vec<TimeBucket>>), ApiError> {
+    date_range.validate(&state)?;
+    let started = Instant::now();
+    let outcome = state.data_service.fetch_data(date_range.begin_at, date_range.end_at).await;
+    let fetched = started.elapsed();
+    if let Some(error) = outcome.error {
+        return Err(fetch_error(error, outcome.overloaded));
+    }
+    let started = Instant::now();
+    let timeseries = state.data_service.display_timeseries(outcome.rating.timeseries);
+    let timings = ServerTimings(vec![("fetch", fetched), ("process", started.elapsed())]);
+    Ok((Extension(timings), Json(timeseries)))
+}
+
+/// Query of `/api/history`
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// Downsample to at most this many points
+    points: Option<usize>,
+}
+
+/// Get the stored history of cost snapshots, optionally downsampled
+pub async fn get_history(
+    State(state): State<AppState>,
+    query: Result<Query<HistoryQuery>, QueryRejection>,
+) -> Result<Json<Vec<HistoryPoint>>, ApiError> {
+    let Query(query) = query.map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+    let history = state.history.read().await;
+    match query.points {
+        None => Ok(Json(history.points())),
+        Some(points) if points < 3 => Err(ApiError::bad_request("points must be at least 3")),
+        Some(points) => Ok(Json(history.downsampled(points.min(history.len())))),
+    }
+}
+
+/// Get recent per-service cost series from the history
+pub async fn get_sparklines(State(state): State<AppState>) -> Json<Sparklines> {
+    Json(state.history.read().await.sparklines(state.config().sparkline_points))
+}
+
+/// Get the cost-per-service trend from the history
+pub async fn get_efficiency(State(state): State<AppState>) -> Json<Efficiency> {
+    Json(state.history.read().await.efficiency())
+}
+
+/// Answer requests for unknown paths with the error envelope
+pub async fn route_not_found(uri: Uri) -> ApiError {
+    ApiError::not_found(format!("No route for {}", uri.path()))
 }
 
-/// Health check endpoint
-pub async fn health_check() -> StatusCode {
+/// Answer requests with a method the route does not accept with the error envelope
+pub async fn method_not_allowed(method: Method, uri: Uri) -> ApiError {
+    ApiError::method_not_allowed(format!("{} is not allowed on {}", method, uri.path()))
+}
+
+/// Grafana SimpleJSON connection test
+pub async fn grafana_test() -> StatusCode {
     StatusCode::OK
 }
 
+/// Grafana SimpleJSON target list: the services of the current chart containing the typed text
+pub async fn grafana_search(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request: Result<Json<SearchRequest>, JsonRejection>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let Json(request) = request.map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+    let chart = visible_chart(&state, allowed_services(&state.config(), &headers)?.as_deref()).await;
+    let mut services: Vec<String> = chart
+        .keys()
+        .iter()
+        .map(|service| state.data_service.display_name(service))
+        .filter(|service| service.contains(&request.target))
+        .collect();
+    services.sort();
+    Ok(Json(services))
+}
+
+/// Grafana SimpleJSON series of the requested services (or `total`) from the history
+pub async fn grafana_query(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request: Result<Json<QueryRequest>, JsonRejection>,
+) -> Result<Json<Vec<TimeSeries>>, ApiError> {
+    let Json(request) = request.map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+    let allowed = allowed_services(&state.config(), &headers)?;
+    let points: Vec<HistoryPoint> = state
+        .history
+        .read()
+        .await
+        .points()
+        .into_iter()
+        .map(|mut point| {
+            // A scoped total only covers the services the token may view
+            if let Some(allowed) = &allowed {
+                point.services.retain(|service, _| allowed.contains(service));
+                point.total_cost = point.services.values().sum();
+            }
+            point.services = point
+                .services
+                .into_iter()
+                .map(|(service, cost)| (state.data_service.display_name(&service), cost))
+                .collect();
+            point
+        })
+        .collect();
+    Ok(Json(grafana::query(&points, &request)))
+}
+/// Expose metrics in the Prometheus text format, or OpenMetrics when the client asks for it
+pub async fn metrics(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let format = ExpositionFormat::from_accept(headers.get(header::ACCEPT).and_then(|value| value.to_str().ok()));
+    let cache = state.data_service.cache();
+    let cache_bytes = cache.stats().await.approx_bytes;
+    let chart = state.chart_data.read().await;
+    (
+        [(header::CONTENT_TYPE, format.content_type())],
+        state.data_service.metrics().render(format, &chart, cache.lookups(), cache_bytes),
+    )
+}
+
+/// Compare the current chart against the configured budget baseline
+pub async fn get_variance(State(state): State<AppState>) -> Result<Json<Vec<Variance>>, ApiError> {
+    let baseline = state
+        .baseline
+        .as_ref()
+        .ok_or_else(|| ApiError::not_found("No BASELINE_FILE is configured"))?;
+    let planned = baseline.planned().await;
+    let chart = state.chart_data.read().await;
+    Ok(Json(compute_variance(&chart, &planned)))
+}
+
+/// Spend of the current fiscal period (`FISCAL_MONTH_START_DAY`) projected to its end
+///
+/// Only whole days before today are fetched; the budget is the `BASELINE_FILE` total.
+pub async fn get_burndown(State(state): State<AppState>) -> Result<Json<Burndown>, ApiError> {
+    let config = state.config();
+    let today = Local::now().date_naive();
+    let start_day = config.fiscal_month_start_day;
+    let (period_start, _) = burndown::fiscal_period(today, start_day);
+    let current_spend = if period_start < today {
+        // An inclusive end date already covers the whole day
+        let last_day = if config.end_inclusive { today.pred_opt().unwrap_or(today) } else { today };
+        let outcome = state
+            .data_service
+            .fetch_data(Some(period_start.to_string()), Some(last_day.to_string()))
+            .await;
+        if let Some(error) = outcome.error {
+            return Err(fetch_error(error, outcome.overloaded));
+        }
+        state.data_service.process_data(&outcome.rating).total_cost
+    } else {
+        0.0
+    };
+    let budget = match &state.baseline {
+        Some(baseline) => Some(baseline.planned().await.values().sum()),
+        None => None,
+    };
+    let mut burndown = burndown::burndown(today, start_day, current_spend, budget);
+    burndown.projected_spend = state.data_service.round_cost(burndown.projected_spend);
+    burndown.projected_over_budget = burndown.projected_over_budget.map(|over| state.data_service.round_cost(over));
+    Ok(Json(burndown))
+}
+
+/// Get the last raw CLI output for bug reports
+pub async fn last_output(State(state): State<AppState>) -> Result<Json<CapturedOutput>, ApiError> {
+    if !state.config().debug_capture_output {
+        return Err(ApiError::not_found("DEBUG_CAPTURE_OUTPUT is disabled"));
+    }
+    state
+        .data_service
+        .last_output()
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("No OpenStack command has run yet"))
+}
+
+/// Get the redacted command a fetch of the range would run, without running it
+pub async fn debug_command(
+    State(state): State<AppState>,
+    Query(date_range): Query<DateRange>,
+) -> Result<Json<Vec<CommandPreview>>, ApiError> {
+    if !state.config().debug_command_enabled {
+        return Err(ApiError::not_found("DEBUG_COMMAND_ENABLED is disabled"));
+    }
+    date_range.validate(&state)?;
+    state
+        .data_service
+        .preview_commands(date_range.begin_at, date_range.end_at)
+        .map(Json)
+        .map_err(|reason| ApiError::bad_request(format!("Invalid date range: {}", reason)))
+}
+
+/// Get cache statistics
+pub async fn cache_stats(State(state): State<AppState>) -> Json<CacheStats> {
+    Json(state.data_service.cache().stats().await)
+}
+
+/// Get the fetch timings of recently fetched ranges, slowest first
+pub async fn range_stats(State(state): State<AppState>) -> Json<Vec<RangeTiming>> {
+    Json(state.data_service.metrics().range_timings())
+}
+
+/// Drop every cached entry so the next fetch queries OpenStack
+pub async fn clear_cache(State(state): State<AppState>) -> StatusCode {
+    info!("Cache clear requested");
+    state.data_service.cache().clear().await;
+    state.data_service.clear_daily();
+    StatusCode::NO_CONTENT
+}
+
+/// Health check endpoint, reporting uptime and the current background refresh pacing
+pub async fn health_check(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "status": "ok",
+        "uptime_seconds": state.started.elapsed().as_secs_f64(),
+        "started_at": state.started_at.to_rfc3339(),
+        "refresh_count": state.refresh_count.load(Ordering::Relaxed),
+        "refresh_interval_seconds": state.refresh_backoff.interval().as_secs(),
+        "consecutive_refresh_failures": state.refresh_backoff.consecutive_failures(),
+        "last_checked": *state.last_checked.read().await,
+        "schema_ok": state.data_service.schema_ok(),
+    }))
+}
+
+/// Readiness check: `503` until a fetch has succeeded, unlike the liveness check
+pub async fn readiness_check(State(state): State<AppState>) -> Result<Json<serde_json::Value>, ApiError> {
+    if state.data_service.ready() {
+        Ok(Json(serde_json::json!({ "status": "ready" })))
+    } else {
+        Err(ApiError::unavailable("Waiting for the first successful OpenStack fetch"))
+    }
+}
+
 /// Get application information
-pub async fn app_info() -> Json<serde_json::Value> {
+pub async fn app_info(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let config = state.config();
+    let cli_version = state
+        .cli_version
+        .get_or_init(|| state.data_service.cli_version())
+        .await;
     Json(serde_json::json!({
         "name": "OpenStack Cost Dashboard",
         "version": env!("CARGO_PKG_VERSION"),
-        "description": "A web dashboard for OpenStack cost visualization"
+        "asset_version": config.asset_version,
+        "openstack_cli_version": cli_version,
+        "description": "A web dashboard for OpenStack cost visualization",
+        "features": config.features(),
+        "cost_unit": {
+            "scale": config.cost_scale,
+            "label": config.cost_unit_label
+        }
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::data::anonymize_service;
+    use crate::models::RatingData;
+    use crate::test_support::mock_command;
+
+    #[tokio::test]
+    async fn test_app_info_reports_features() {
+        let Json(info) = app_info(State(AppState::from_pairs(&[]))).await;
+        assert_eq!(info["features"]["data_backend"], "cli");
+        assert_eq!(info["features"]["cache_backend"], "memory");
+        assert_eq!(info["features"]["anonymize_services"], false);
+        assert_eq!(info["features"]["sampling"], false);
+
+        let state = AppState::from_pairs(&[("ANONYMIZE_SERVICES", "1"), ("SAMPLE_RATE", "0.25")]);
+        let Json(info) = app_info(State(state)).await;
+        assert_eq!(info["features"]["anonymize_services"], true);
+        assert_eq!(info["features"]["sampling"], true);
+
+        let state = AppState::from_pairs(&[("CACHE_FILE", "/tmp/cache.json"), ("SSH_HOST", "bastion.example.com")]);
+        let Json(info) = app_info(State(state)).await;
+        assert_eq!(info["features"]["cache_backend"], "file");
+        assert_eq!(info["features"]["data_backend"], "ssh");
+        assert_eq!(info["features"]["tls"], false);
+    }
+
+    #[tokio::test]
+    async fn test_app_info_reports_cost_unit() {
+        let Json(info) = app_info(State(AppState::from_pairs(&[]))).await;
+        assert_eq!(info["cost_unit"]["scale"], 1.0);
+        assert_eq!(info["cost_unit"]["label"], "€");
+
+        let state = AppState::from_pairs(&[("COST_SCALE", "1000"), ("COST_UNIT_LABEL", "k$")]);
+        let Json(info) = app_info(State(state)).await;
+        assert_eq!(info["cost_unit"]["scale"], 1000.0);
+        assert_eq!(info["cost_unit"]["label"], "k$");
+    }
+
+    #[tokio::test]
+    async fn test_app_info_reports_cli_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, "echo 'openstack 6.2.0'");
+
+        let state = AppState::from_pairs(&[("OPENSTACK_COMMAND", &command)]);
+        let Json(info) = app_info(State(state.clone())).await;
+        assert_eq!(info["openstack_cli_version"], "openstack 6.2.0");
+
+        // The version is read once and reused
+        std::fs::remove_file(&command).unwrap();
+        let Json(info) = app_info(State(state)).await;
+        assert_eq!(info["openstack_cli_version"], "openstack 6.2.0");
+
+        let state = AppState::from_pairs(&[("OPENSTACK_COMMAND", "/nonexistent/openstack")]);
+        let Json(info) = app_info(State(state)).await;
+        assert_eq!(info["openstack_cli_version"], "unavailable");
+    }
+
+    #[tokio::test]
+    async fn test_chartjs_config_matches_current_chart() {
+        let state = AppState::from_pairs(&[("CHART_PALETTE", "#112233, nope, #abc"), ("DASHBOARD_TITLE", "Costs")]);
+        *state.chart_data.write().await = ChartData {
+            labels: vec!["compute".to_string(), "storage".to_string(), "network".to_string()],
+            values: vec![12.5, 2.5, 1.0],
+            ..ChartData::empty()
+        };
+
+        let Json(config) = get_chartjs(State(state), HeaderMap::new()).await.unwrap();
+        assert_eq!(config["type"], "bar");
+        assert_eq!(config["data"]["labels"], serde_json::json!(["compute", "storage", "network"]));
+        let dataset = &config["data"]["datasets"][0];
+        assert_eq!(dataset["data"], serde_json::json!([12.5, 2.5, 1.0]));
+        assert_eq!(dataset["backgroundColor"], serde_json::json!(["#112233", "#abc", "#112233"]));
+        assert_eq!(config["options"]["plugins"]["title"]["text"], "Costs");
+    }
+
+    #[tokio::test]
+    async fn test_scoped_token_only_sees_its_services() {
+        let state = AppState::from_pairs(&[("API_TOKEN", "admin"), ("TOKEN_SCOPES", "team-a=compute,network;team-b=storage")]);
+        *state.chart_data.write().await = ChartData {
+            labels: vec!["compute".to_string(), "storage".to_string(), "network".to_string()],
+            values: vec![12.5, 2.5, 1.0],
+            raw_costs: vec![12.5, 2.5, 1.0],
+            total_cost: 16.0,
+            service_count: 3,
+            ..ChartData::empty()
+        };
+        let bearer = |token: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            headers
+        };
+        let query = || Ok(Query(DataQuery { currency_rate: None }));
+
+        let Json(chart) = get_chart_data(State(state.clone()), bearer("team-a"), query()).await.unwrap();
+        assert_eq!(chart.labels, vec!["compute", "network"]);
+        assert_eq!(chart.values, vec![12.5, 1.0]);
+        assert_eq!(chart.total_cost, 13.5);
+        assert_eq!(chart.service_count, 2);
+        assert_eq!(chart.average_cost, 6.75);
+
+        let Json(config) = get_chartjs(State(state.clone()), bearer("team-b")).await.unwrap();
+        assert_eq!(config["data"]["labels"], serde_json::json!(["storage"]));
+
+        let Json(chart) = get_chart_data(State(state.clone()), bearer("admin"), query()).await.unwrap();
+        assert_eq!(chart.labels.len(), 3);
+        assert_eq!(chart.total_cost, 16.0);
+
+        for headers in [HeaderMap::new(), bearer("team-c")] {
+            let error = get_chart_data(State(state.clone()), headers, query()).await.unwrap_err();
+            assert_eq!(error.into_response().status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chart_png_is_a_png_image() {
+        use http_body_util::BodyExt;
+
+        let state = AppState::from_pairs(&[]);
+        let query = |kind: ChartKind| Ok(Query(ChartImageQuery { kind, width: 320, height: 200 }));
+
+        // Empty data renders a placeholder
+        let response = get_chart_png(State(state.clone()), HeaderMap::new(), query(ChartKind::Bar)).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        *state.chart_data.write().await = ChartData {
+            labels: vec!["compute".to_string(), "storage".to_string()],
+            values: vec![12.5, 2.5],
+            ..ChartData::empty()
+        };
+        for kind in [ChartKind::Bar, ChartKind::Pie] {
+            let response = get_chart_png(State(state.clone()), HeaderMap::new(), query(kind)).await.unwrap();
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert!(body.starts_with(b"\x89PNG\r\n\x1a\n"));
+        }
+
+        let oversized = Ok(Query(ChartImageQuery { kind: ChartKind::Bar, width: 10_000, height: 200 }));
+        assert!(get_chart_png(State(state), HeaderMap::new(), oversized).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_currency_rate_override_leaves_shared_state_alone() {
+        let state = AppState::from_pairs(&[("CURRENCY_RATE", "50")]);
+        let rating = RatingData {
+            costs: HashMap::from([("compute".to_string(), 2.0), ("storage".to_string(), 1.0)]),
+            raw_costs: HashMap::from([("compute".to_string(), 100.0), ("storage".to_string(), 50.0)]),
+            ..RatingData::default()
+        };
+        *state.chart_data.write().await = state.data_service.process_data(&rating);
+
+        let query = |currency_rate| Ok(Query(DataQuery { currency_rate }));
+        let Json(overridden) = get_chart_data(State(state.clone()), HeaderMap::new(), query(Some(25.0))).await.unwrap();
+        assert_eq!(overridden.labels, vec!["compute", "storage"]);
+        assert_eq!(overridden.values, vec![4.0, 2.0]);
+        assert_eq!(overridden.total_cost, 6.0);
+
+        let Json(shared) = get_chart_data(State(state.clone()), HeaderMap::new(), query(None)).await.unwrap();
+        assert_eq!(shared.values, vec![2.0, 1.0]);
+        assert_eq!(state.chart_data.read().await.total_cost, 3.0);
+
+        for invalid in [0.0, -3.0, f64::INFINITY] {
+            assert!(get_chart_data(State(state.clone()), HeaderMap::new(), query(Some(invalid))).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_reports_deltas_since_previous_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = dir.path().join("payload.json");
+        let command = mock_command(&dir, &format!("cat {}", payload.display()));
+        let state = AppState::from_pairs(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("CURRENCY_RATE", "1"),
+            ("INCLUDE_DELTAS", "true"),
+        ]);
+        let range = || Query(DateRange {
+            begin_at: Some("2025-01-01".to_string()),
+            end_at: Some("2025-01-31".to_string()),
+            unknown: BTreeMap::new(),
+        });
+
+        std::fs::write(&payload, r#"[{"Resources": [{"service": "compute", "rating": 5}, {"service": "dns", "rating": 1}]}]"#).unwrap();
+        let (_, Json(first)) = refresh_data(State(state.clone()), HeaderMap::new(), range()).await.unwrap();
+        // The empty chart it replaces was not fetched for this range
+        assert_eq!(first.chart.deltas.map(|deltas| deltas.len()), Some(0));
+
+        std::fs::write(&payload, r#"[{"Resources": [{"service": "compute", "rating": 8}, {"service": "storage", "rating": 2}]}]"#).unwrap();
+        state.data_service.cache().clear().await;
+        let (_, Json(response)) = refresh_data(State(state.clone()), HeaderMap::new(), range()).await.unwrap();
+
+        let expected = BTreeMap::from([
+            ("compute".to_string(), 3.0),
+            ("dns".to_string(), -1.0),
+            ("storage".to_string(), 2.0),
+        ]);
+        assert_eq!(response.chart.deltas.as_ref(), Some(&expected));
+        let body = serde_json::to_value(&*state.chart_data.read().await).unwrap();
+        assert_eq!(body["deltas"]["dns"], -1.0);
+
+        // A different range is not compared with the chart it replaces
+        let other_range = Query(DateRange {
+            begin_at: Some("2025-02-01".to_string()),
+            end_at: Some("2025-02-28".to_string()),
+            unknown: BTreeMap::new(),
+        });
+        let (_, Json(response)) = refresh_data(State(state.clone()), HeaderMap::new(), other_range).await.unwrap();
+        assert_eq!(response.chart.deltas, Some(BTreeMap::new()));
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_effective_refresh_interval() {
+        let state = AppState::from_pairs(&[("REFRESH_INTERVAL_SECONDS", "60"), ("REFRESH_BACKOFF_THRESHOLD", "1")]);
+        let Json(health) = health_check(State(state.clone())).await;
+        assert_eq!(health["refresh_interval_seconds"], 60);
+
+        state.refresh_backoff.record(false);
+        state.refresh_backoff.record(false);
+        let Json(health) = health_check(State(state.clone())).await;
+        assert_eq!(health["refresh_interval_seconds"], 240);
+        assert_eq!(health["consecutive_refresh_failures"], 2);
+
+        state.refresh_backoff.record(true);
+        let Json(health) = health_check(State(state)).await;
+        assert_eq!(health["refresh_interval_seconds"], 60);
+    }
+
+    #[tokio::test]
+    async fn test_grafana_search_and_query() {
+        let state = AppState::from_pairs(&[("GRAFANA_ENABLED", "true")]);
+        let rating = RatingData {
+            costs: HashMap::from([("compute".to_string(), 5.0), ("storage".to_string(), 2.0)]),
+            ..RatingData::default()
+        };
+        let chart = state.data_service.process_data(&rating);
+        state.history.write().await.push(HistoryPoint::from_chart("2025-01-01T00:00:00+00:00".to_string(), &chart));
+        *state.chart_data.write().await = chart;
+
+        let search = |target: &str| Ok(Json(SearchRequest { target: target.to_string() }));
+        let Json(services) = grafana_search(State(state.clone()), HeaderMap::new(), search("")).await.unwrap();
+        assert_eq!(services, vec!["compute", "storage"]);
+        let Json(services) = grafana_search(State(state.clone()), HeaderMap::new(), search("stor")).await.unwrap();
+        assert_eq!(services, vec!["storage"]);
+
+        let request = serde_json::from_value(serde_json::json!({
+            "range": { "from": "2024-12-31T00:00:00Z", "to": "2025-01-02T00:00:00Z" },
+            "targets": [{ "target": "compute", "refId": "A", "type": "timeserie" }]
+        }))
+        .unwrap();
+        let Json(series) = grafana_query(State(state), HeaderMap::new(), Ok(Json(request))).await.unwrap();
+        assert_eq!(serde_json::to_value(series).unwrap(), serde_json::json!([
+            { "target": "compute", "datapoints": [[5.0, 1_735_689_600_000_i64]] }
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_grafana_is_scoped_and_anonymized() {
+        let state = AppState::from_pairs(&[
+            ("GRAFANA_ENABLED", "true"),
+            ("API_TOKEN", "admin"),
+            ("TOKEN_SCOPES", "team-a=compute"),
+            ("ANONYMIZE_SERVICES", "true"),
+        ]);
+        // History recorded before ANONYMIZE_SERVICES was turned on
+        let point = HistoryPoint {
+            timestamp: "2025-01-01T00:00:00+00:00".to_string(),
+            total_cost: 7.0,
+            cost_per_service: 3.5,
+            services: HashMap::from([("compute".to_string(), 5.0), ("storage".to_string(), 2.0)]),
+            cache_hits: 0,
+            cache_misses: 0,
+        };
+        state.history.write().await.push(point);
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer team-a".parse().unwrap());
+
+        let request = serde_json::from_value(serde_json::json!({
+            "range": { "from": "2024-12-31T00:00:00Z", "to": "2025-01-02T00:00:00Z" },
+            "targets": [{ "target": anonymize_service("compute") }, { "target": anonymize_service("storage") }, { "target": "total" }]
+        }))
+        .unwrap();
+        let Json(series) = grafana_query(State(state.clone()), headers, Ok(Json(request))).await.unwrap();
+        assert_eq!(series[0].datapoints, vec![(5.0, 1_735_689_600_000)]);
+        assert!(series[1].datapoints.is_empty());
+        assert_eq!(series[2].datapoints, vec![(5.0, 1_735_689_600_000)]);
+
+        let search = Ok(Json(SearchRequest { target: String::new() }));
+        let error = grafana_search(State(state), HeaderMap::new(), search).await.unwrap_err();
+        assert_eq!(error.into_response().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    /// Serve the index page and return its body
+    async fn index_html(state: AppState) -> String {
+        use http_body_util::BodyExt;
+
+        let response = serve_index(State(state), HeaderMap::new()).await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_asset_version_matches_served_html() {
+        let state = AppState::from_pairs(&[("DASHBOARD_LOGO_URL", "/static/logo.png")]);
+        let Json(info) = app_info(State(state.clone())).await;
+        let asset_version = info["asset_version"].as_str().unwrap().to_string();
+        assert!(asset_version.starts_with(env!("CARGO_PKG_VERSION")));
+
+        let html = index_html(state).await;
+        // External assets are left as they are, like an external logo would be
+        assert!(html.contains("npm/chart.js\"></script>"), "{}", html);
+        assert!(html.contains(&format!("/static/logo.png?v={}\"", asset_version)));
+        assert!(html.contains(&format!("<meta name=\"asset-version\" content=\"{}\">", asset_version)));
+    }
+
+    #[tokio::test]
+    async fn test_index_uses_configured_title() {
+        let html = index_html(AppState::from_pairs(&[("DASHBOARD_TITLE", "Team Costs")])).await;
+        assert!(html.contains("<title>Team Costs</title>"));
+        assert!(html.contains("<h1>Team Costs</h1>"));
+        assert!(!html.contains("{{DASHBOARD_TITLE}}"));
+        assert!(!html.contains("{{DASHBOARD_LOGO}}"));
+    }
+
+    #[tokio::test]
+    async fn test_index_escapes_injected_branding() {
+        let state = AppState::from_pairs(&[
+            ("DASHBOARD_TITLE", "<script>alert(1)</script>"),
+            ("DASHBOARD_LOGO_URL", "x\" onerror=\"alert(1)"),
+        ]);
+        let html = index_html(state).await;
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!html.contains("<script>alert(1)</script>"));
+        assert!(html.contains("src=\"x&quot; onerror=&quot;alert(1)\""));
+    }
+
+    #[tokio::test]
+    async fn test_index_etag_is_stable_and_revalidates() {
+        let state = AppState::from_pairs(&[]);
+        let first = serve_index(State(state.clone()), HeaderMap::new()).await;
+        let second = serve_index(State(state.clone()), HeaderMap::new()).await;
+        let etag = first.headers()[header::ETAG].clone();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(etag, second.headers()[header::ETAG]);
+        assert_eq!(first.headers()[header::CACHE_CONTROL], "no-cache");
+        assert!(first.headers().contains_key(header::LAST_MODIFIED));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let revalidated = serve_index(State(state.clone()), headers).await;
+        assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(revalidated.headers()[header::ETAG], etag);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, "\"stale\"".parse().unwrap());
+        assert_eq!(serve_index(State(state), headers).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,400 @@
+//! Bounded history of cost snapshots for trend charts
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::models::ChartData;
+
+/// A point-in-time snapshot of per-service costs
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistoryPoint {
+    pub timestamp: String,
+    pub total_cost: f64,
+    /// Average cost per service at that time
+    pub cost_per_service: f64,
+    pub services: HashMap<String, f64>,
+    /// Cumulative cache hits at that time
+    pub cache_hits: u64,
+    /// Cumulative cache misses at that time
+    pub cache_misses: u64,
+}
+
+impl HistoryPoint {
+    /// Create a snapshot from chart data taken at the given time
+    pub fn from_chart(timestamp: String, chart: &ChartData) -> Self {
+        Self {
+            timestamp,
+            total_cost: chart.total_cost,
+            cost_per_service: chart.average_cost,
+            services: chart.keys().iter().cloned().zip(chart.values.iter().copied()).collect(),
+            cache_hits: 0,
+            cache_misses: 0,
+        }
+    }
+
+    /// Record the cumulative cache hits and misses at the time of the snapshot
+    pub fn with_cache_lookups(self, (cache_hits, cache_misses): (u64, u64)) -> Self {
+        Self {
+            cache_hits,
+            cache_misses,
+            ..self
+        }
+    }
+}
+
+/// Per-service cost series aligned to snapshot timestamps
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Sparklines {
+    pub timestamps: Vec<String>,
+    /// One value per timestamp; `None` where the service was absent
+    pub sparklines: HashMap<String, Vec<Option<f64>>>,
+}
+
+/// Cost per service over time with the direction of its trend
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Efficiency {
+    pub timestamps: Vec<String>,
+    pub cost_per_service: Vec<f64>,
+    /// Least-squares slope of the ratio per snapshot; positive when cost grows faster
+    /// than the number of services, zero with fewer than two snapshots
+    pub slope: f64,
+}
+
+/// JSON lines file snapshots are appended to, rotated once it would grow past `max_bytes`
+#[derive(Debug)]
+struct HistoryFile {
+    path: PathBuf,
+    max_bytes: u64,
+}
+
+impl HistoryFile {
+    /// Where the previous file is kept after a rotation
+    fn rotated_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".1");
+        self.path.with_file_name(name)
+    }
+
+    /// Read the snapshots of the rotated file and then the current one, oldest first
+    ///
+    /// Missing files are skipped, as are lines that don't parse.
+    async fn load(&self) -> Vec<HistoryPoint> {
+        let mut points = Vec::new();
+        for path in [self.rotated_path(), self.path.clone()] {
+            let contents = match tokio::fs::read_to_string(&path).await {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    warn!("Failed to read history file {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            for (number, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+                match serde_json::from_str(line) {
+                    Ok(point) => points.push(point),
+                    Err(e) => warn!("Skipping line {} of history file {}: {}", number + 1, path.display(), e),
+                }
+            }
+        }
+        points
+    }
+
+    /// Append a snapshot, first moving a full file aside
+    async fn append(&self, point: &HistoryPoint) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(point)?;
+        line.push('\n');
+        if let Ok(metadata) = tokio::fs::metadata(&self.path).await
+            && metadata.len() > 0
+            && metadata.len() + line.len() as u64 > self.max_bytes
+        {
+            tokio::fs::rename(&self.path, self.rotated_path()).await?;
+            info!("Rotated history file {}", self.path.display());
+        }
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(line.as_bytes()).await
+    }
+}
+
+/// Ring buffer keeping the most recent snapshots
+#[derive(Debug)]
+pub struct HistoryBuffer {
+    points: VecDeque<HistoryPoint>,
+    max_points: usize,
+    /// File snapshots are persisted to, if any
+    file: Option<HistoryFile>,
+}
+
+impl HistoryBuffer {
+    /// Create an empty buffer holding at most `max_points` snapshots
+    pub fn new(max_points: usize) -> Self {
+        Self {
+            points: VecDeque::with_capacity(max_points),
+            max_points,
+            file: None,
+        }
+    }
+
+    /// Persist recorded snapshots to `path`, rotating it once it would exceed `max_bytes`
+    pub fn with_file(mut self, path: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        self.file = Some(HistoryFile { path: path.into(), max_bytes });
+        self
+    }
+
+    /// Restore the persisted snapshots, keeping the most recent `max_points`
+    ///
+    /// Returns how many snapshots were read from the file.
+    pub async fn load(&mut self) -> usize {
+        let Some(file) = &self.file else {
+            return 0;
+        };
+        let points = file.load().await;
+        let loaded = points.len();
+        for point in points {
+            self.push(point);
+        }
+        loaded
+    }
+
+    /// Append a snapshot and persist it when a file is configured
+    ///
+    /// Failing to write the file is logged; the snapshot is kept in memory either way.
+    pub async fn record(&mut self, point: HistoryPoint) {
+        if let Some(file) = &self.file
+            && let Err(e) = file.append(&point).await
+        {
+            warn!("Failed to persist history snapshot to {}: {}", file.path.display(), e);
+        }
+        self.push(point);
+    }
+
+    /// Append a snapshot, evicting the oldest one when full
+    pub fn push(&mut self, point: HistoryPoint) {
+        if self.max_points == 0 {
+            return;
+        }
+        while self.points.len() >= self.max_points {
+            self.points.pop_front();
+        }
+        self.points.push_back(point);
+    }
+
+    /// Get all snapshots from oldest to newest
+    pub fn points(&self) -> Vec<HistoryPoint> {
+        self.points.iter().cloned().collect()
+    }
+
+    /// Reduce the snapshots to at most `threshold` points, preserving the shape of the total
+    ///
+    /// Uses Largest-Triangle-Three-Buckets on `total_cost`, which keeps the first and
+    /// last snapshot and favours spikes within each bucket.
+    pub fn downsampled(&self, threshold: usize) -> Vec<HistoryPoint> {
+        let points: Vec<&HistoryPoint> = self.points.iter().collect();
+        if threshold >= points.len() || threshold < 3 {
+            return points.into_iter().cloned().collect();
+        }
+
+        let bucket_size = (points.len() - 2) as f64 / (threshold - 2) as f64;
+        let mut selected = vec![points[0].clone()];
+        let mut previous = 0;
+        for bucket in 0..threshold - 2 {
+            let start = (bucket as f64 * bucket_size) as usize + 1;
+            let end = ((bucket + 1) as f64 * bucket_size) as usize + 1;
+
+            // Average of the next bucket (or the last point) anchors the triangle
+            let next_start = end;
+            let next_end = (((bucket + 2) as f64 * bucket_size) as usize + 1).min(points.len());
+            let next = &points[next_start..next_end.max(next_start + 1)];
+            let avg_x = next_start as f64 + (next.len() as f64 - 1.0) / 2.0;
+            let avg_y = next.iter().map(|point| point.total_cost).sum::<f64>() / next.len() as f64;
+
+            let (prev_x, prev_y) = (previous as f64, points[previous].total_cost);
+            let chosen = (start..end)
+                .max_by(|a, b| {
+                    let area = |i: usize| {
+                        ((prev_x - avg_x) * (points[i].total_cost - prev_y) - (prev_x - i as f64) * (avg_y - prev_y)).abs()
+                    };
+                    area(*a).partial_cmp(&area(*b)).unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .unwrap_or(start);
+            selected.push(points[chosen].clone());
+            previous = chosen;
+        }
+        selected.push(points[points.len() - 1].clone());
+        selected
+    }
+
+    /// Build per-service series over the last `points` snapshots
+    pub fn sparklines(&self, points: usize) -> Sparklines {
+        let recent: Vec<&HistoryPoint> = self.points.iter().skip(self.points.len().saturating_sub(points)).collect();
+        let mut sparklines: HashMap<String, Vec<Option<f64>>> = HashMap::new();
+        for (index, point) in recent.iter().enumerate() {
+            for (service, cost) in &point.services {
+                sparklines
+                    .entry(service.clone())
+                    .or_insert_with(|| vec![None; recent.len()])[index] = Some(*cost);
+            }
+        }
+
+        Sparklines {
+            timestamps: recent.iter().map(|point| point.timestamp.clone()).collect(),
+            sparklines,
+        }
+    }
+
+    /// Build the cost-per-service trend over all snapshots
+    pub fn efficiency(&self) -> Efficiency {
+        let ratios: Vec<f64> = self.points.iter().map(|point| point.cost_per_service).collect();
+        Efficiency {
+            timestamps: self.points.iter().map(|point| point.timestamp.clone()).collect(),
+            slope: regression_slope(&ratios),
+            cost_per_service: ratios,
+        }
+    }
+
+    /// Number of snapshots currently stored
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Whether the buffer holds no snapshots
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+/// Slope of the least-squares line through `values` against their index
+fn regression_slope(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = values.iter().sum::<f64>() / n;
+    let (covariance, variance) = values.iter().enumerate().fold((0.0, 0.0), |(cov, var), (i, y)| {
+        let dx = i as f64 - mean_x;
+        (cov + dx * (y - mean_y), var + dx * dx)
+    });
+    if variance == 0.0 { 0.0 } else { covariance / variance }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(timestamp: &str, total_cost: f64) -> HistoryPoint {
+        HistoryPoint {
+            timestamp: timestamp.to_string(),
+            total_cost,
+            cost_per_service: 0.0,
+            services: HashMap::new(),
+            cache_hits: 0,
+            cache_misses: 0,
+        }
+    }
+
+    #[test]
+    fn test_oldest_points_are_evicted() {
+        let mut history = HistoryBuffer::new(2);
+        history.push(point("t1", 1.0));
+        history.push(point("t2", 2.0));
+        history.push(point("t3", 3.0));
+
+        let timestamps: Vec<String> = history.points().into_iter().map(|p| p.timestamp).collect();
+        assert_eq!(timestamps, vec!["t2", "t3"]);
+    }
+
+    #[test]
+    fn test_sparklines_pad_missing_services() {
+        let mut history = HistoryBuffer::new(10);
+        for (timestamp, services) in [
+            ("t1", vec![("compute", 1.0)]),
+            ("t2", vec![("compute", 2.0), ("storage", 5.0)]),
+            ("t3", vec![("storage", 6.0)]),
+        ] {
+            history.push(HistoryPoint {
+                timestamp: timestamp.to_string(),
+                total_cost: services.iter().map(|(_, cost)| cost).sum(),
+                cost_per_service: 0.0,
+                cache_hits: 0,
+                cache_misses: 0,
+                services: services.into_iter().map(|(name, cost)| (name.to_string(), cost)).collect(),
+            });
+        }
+
+        let sparklines = history.sparklines(10);
+        assert_eq!(sparklines.timestamps, vec!["t1", "t2", "t3"]);
+        assert_eq!(sparklines.sparklines["compute"], vec![Some(1.0), Some(2.0), None]);
+        assert_eq!(sparklines.sparklines["storage"], vec![None, Some(5.0), Some(6.0)]);
+
+        let last_two = history.sparklines(2);
+        assert_eq!(last_two.timestamps, vec!["t2", "t3"]);
+        assert_eq!(last_two.sparklines["compute"], vec![Some(2.0), None]);
+    }
+
+    #[test]
+    fn test_downsampling_keeps_length_bound_and_spike() {
+        let mut history = HistoryBuffer::new(288);
+        for i in 0..288 {
+            let cost = if i == 137 { 500.0 } else { 100.0 + (i as f64 / 10.0).sin() * 5.0 };
+            history.push(point(&format!("t{}", i), cost));
+        }
+
+        let downsampled = history.downsampled(48);
+        assert_eq!(downsampled.len(), 48);
+        assert_eq!(downsampled[0].timestamp, "t0");
+        assert_eq!(downsampled[47].timestamp, "t287");
+        assert!(downsampled.iter().any(|p| p.total_cost == 500.0), "spike must be retained");
+        assert!(downsampled.windows(2).all(|pair| {
+            pair[0].timestamp[1..].parse::<usize>().unwrap() < pair[1].timestamp[1..].parse::<usize>().unwrap()
+        }));
+
+        assert_eq!(history.downsampled(1000).len(), 288);
+    }
+
+    #[test]
+    fn test_efficiency_slope_follows_cost_per_service() {
+        let mut history = HistoryBuffer::new(10);
+        for (i, ratio) in [10.0, 12.0, 11.5, 15.0].into_iter().enumerate() {
+            history.push(HistoryPoint {
+                cost_per_service: ratio,
+                ..point(&format!("t{}", i), ratio * 4.0)
+            });
+        }
+
+        let efficiency = history.efficiency();
+        assert_eq!(efficiency.timestamps, vec!["t0", "t1", "t2", "t3"]);
+        assert_eq!(efficiency.cost_per_service, vec![10.0, 12.0, 11.5, 15.0]);
+        assert!(efficiency.slope > 0.0, "{}", efficiency.slope);
+
+        let mut single = HistoryBuffer::new(10);
+        single.push(point("t0", 5.0));
+        assert_eq!(single.efficiency().slope, 0.0);
+        assert_eq!(HistoryBuffer::new(10).efficiency().slope, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_persisted_history_is_restored_up_to_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let line_bytes = serde_json::to_string(&point("2025-01-01T00:00:00+00:00", 1.0)).unwrap().len() as u64 + 1;
+
+        // Room for two lines per file, so the five snapshots rotate it twice
+        let mut history = HistoryBuffer::new(3).with_file(&path, line_bytes * 2);
+        assert_eq!(history.load().await, 0);
+        for day in 1..=5 {
+            history.record(point(&format!("2025-01-0{}T00:00:00+00:00", day), day as f64)).await;
+        }
+        assert!(dir.path().join("history.jsonl.1").exists());
+
+        let mut restarted = HistoryBuffer::new(3).with_file(&path, line_bytes * 2);
+        assert_eq!(restarted.load().await, 3);
+        let costs: Vec<f64> = restarted.points().iter().map(|point| point.total_cost).collect();
+        assert_eq!(costs, [3.0, 4.0, 5.0]);
+        assert_eq!(restarted.points(), history.points());
+
+        // A smaller cap trims what is restored
+        let mut smaller = HistoryBuffer::new(2).with_file(&path, line_bytes * 2);
+        smaller.load().await;
+        assert_eq!(smaller.points().iter().map(|point| point.total_cost).collect::<Vec<_>>(), [4.0, 5.0]);
+    }
+}
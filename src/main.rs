@@ -9,25 +9,96 @@ mod data;
 mod config;
 mod server;
 mod cache;
+mod middleware;
+mod history;
+mod metrics;
+mod baseline;
+mod rounding;
+mod currency_format;
+mod error;
+mod backoff;
+mod schedule;
+mod render;
+mod rolling;
+mod grafana;
+mod sink;
+mod burndown;
+#[cfg(test)]
+mod test_support;
 
 use std::sync::Arc;
-use chrono::Local;
-use tokio::sync::RwLock;
+use std::sync::atomic::AtomicU64;
+use std::time::Instant;
+use chrono::{DateTime, Local, Utc};
+use tokio::sync::{OnceCell, RwLock};
 
 use crate::models::ChartData;
 use crate::data::DataService;
 use crate::server::Server;
+use crate::baseline::Baseline;
+use crate::backoff::RefreshBackoff;
 use crate::config::Config;
+use crate::history::{HistoryBuffer, HistoryPoint};
+use crate::handlers::IndexPage;
+use crate::sink::Sink;
 
 /// Application state shared across handlers
 #[derive(Clone)]
 pub struct AppState {
+    pub index_page: IndexPage,
     pub chart_data: Arc<RwLock<ChartData>>,
+    /// When the background refresh last fetched, even if it kept the chart data
+    pub last_checked: Arc<RwLock<Option<String>>>,
+    pub history: Arc<RwLock<HistoryBuffer>>,
+    pub baseline: Option<Arc<Baseline>>,
+    pub refresh_backoff: Arc<RefreshBackoff>,
+    /// OpenStack CLI version, read once for diagnostics
+    pub cli_version: Arc<OnceCell<String>>,
     pub data_service: DataService,
+    /// Where successful refreshes are published (`SINK`)
+    pub sink: Option<Arc<dyn Sink>>,
+    /// When the process started, for the uptime reported by `/api/health`
+    pub started: Instant,
+    pub started_at: DateTime<Utc>,
+    /// Refreshes run by the background task and `/api/refresh` since startup
+    pub refresh_count: Arc<AtomicU64>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+impl AppState {
+    /// Current configuration, the one `SIGHUP` reloads into the data service
+    pub fn config(&self) -> Arc<Config> {
+        self.data_service.config()
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// Build application state whose configuration only contains the given variables
+    pub fn from_pairs(vars: &[(&str, &str)]) -> Self {
+        let config = Config::from_pairs(vars);
+        let cache = Arc::new(cache::OpenStackCache::new(std::time::Duration::from_secs(300)));
+        Self {
+            index_page: IndexPage::new(&config),
+            chart_data: Arc::new(RwLock::new(ChartData::empty())),
+            last_checked: Arc::new(RwLock::new(None)),
+            history: Arc::new(RwLock::new(HistoryBuffer::new(config.history_max_points))),
+            baseline: config.baseline_file.as_deref().map(|path| Arc::new(Baseline::new(path))),
+            refresh_backoff: Arc::new(RefreshBackoff::new(
+                config.refresh_interval,
+                config.refresh_backoff_max,
+                config.refresh_backoff_threshold,
+            )),
+            cli_version: Arc::new(OnceCell::new()),
+            sink: sink::open_sink(&config),
+            started: Instant::now(),
+            started_at: Utc::now(),
+            refresh_count: Arc::new(AtomicU64::new(0)),
+            data_service: DataService::new(config, cache),
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load environment variables from .env file
     dotenvy::dotenv().ok();
     
@@ -36,26 +107,84 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Load configuration
     let config = Config::new();
+    if let Err(e) = config.validate() {
+        tracing::error!("Refusing to start: {}", e);
+        return Err(e.into());
+    }
     
-    // Initialize cache
-    let cache = Arc::new(cache::OpenStackCache::new(std::time::Duration::from_secs(300)));
+    // The runtime is sized from the configuration, so it is built only once that is loaded
+    let runtime = server::build_runtime(&config)?;
+    tracing::info!("Running on {} Tokio worker thread(s)", config.worker_threads);
+    runtime.block_on(run(config))
+}
+
+/// Warm up the caches, fetch the initial data and serve until shutdown
+async fn run(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let (started, started_at) = (Instant::now(), Utc::now());
+    
+    // Initialize cache, warming it up from disk when persisted
+    let mut cache = cache::OpenStackCache::new(std::time::Duration::from_secs(300));
+    if let Some(path) = &config.cache_file {
+        cache = cache.with_file(path);
+    }
+    if config.read_only_replica {
+        tracing::info!("Running as a read-only replica of the cache in {:?}", config.cache_file);
+        cache = cache.read_only();
+    }
+    cache.load().await;
+    let cache = Arc::new(cache);
     
     // Initialize data service
     let data_service = DataService::new(config.clone(), cache.clone());
     
+    // Record the CLI version for diagnostics
+    let cli_version = data_service.cli_version().await;
+    tracing::info!("OpenStack CLI version: {}", cli_version);
+    
+    // Seed the history with any exported dataframes, then the snapshots persisted before a restart
+    let mut history = HistoryBuffer::new(config.history_max_points);
+    if let Some(path) = &config.history_file {
+        history = history.with_file(path, config.history_file_max_bytes);
+    }
+    for point in data_service.load_backfill() {
+        history.push(point);
+    }
+    let restored = history.load().await;
+    if restored > 0 {
+        tracing::info!("Restored {} history snapshots", restored);
+    }
+    
     // Fetch initial data
     let initial_data = data_service.fetch_data(
         Some(Local::now().format("%Y-%m-01").to_string()),
         Some(Local::now().format("%Y-%m-%d").to_string())
     ).await;
 
-    let chart_data = data_service.process_data(initial_data);
+    let chart_data = data_service.process_outcome(initial_data);
+    if chart_data.error.is_none() && !chart_data.stale {
+        history.record(HistoryPoint::from_chart(Local::now().to_rfc3339(), &chart_data).with_cache_lookups(cache.lookups())).await;
+    }
+    let last_checked = Arc::new(RwLock::new(Some(chart_data.last_updated.clone())));
     let chart_data_state = Arc::new(RwLock::new(chart_data));
     
     // Create combined app state
     let app_state = AppState {
+        index_page: IndexPage::new(&config),
         chart_data: chart_data_state,
+        last_checked,
+        history: Arc::new(RwLock::new(history)),
+        baseline: config.baseline_file.as_deref().map(|path| Arc::new(Baseline::new(path))),
+        refresh_backoff: Arc::new(RefreshBackoff::new(
+            config.refresh_interval,
+            config.refresh_backoff_max,
+            config.refresh_backoff_threshold,
+        )),
+        cli_version: Arc::new(OnceCell::new_with(Some(cli_version))),
         data_service: data_service.clone(),
+        sink: sink::open_sink(&config),
+        started,
+        started_at,
+        refresh_count: Arc::new(AtomicU64::new(0)),
     };
     
     // Start the server
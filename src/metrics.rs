@@ -0,0 +1,394 @@
+//! Prometheus metrics for the OpenStack Cost Dashboard
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use serde::Serialize;
+
+use crate::cache::hit_ratio;
+use crate::models::ChartData;
+
+/// Default Prometheus histogram buckets in seconds
+pub const DEFAULT_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Text format `/metrics` is rendered in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExpositionFormat {
+    /// Legacy Prometheus text format 0.0.4
+    #[default]
+    Prometheus,
+    /// OpenMetrics 1.0 text format with unit metadata and the `# EOF` trailer
+    OpenMetrics,
+}
+
+impl ExpositionFormat {
+    /// Pick the format requested by an `Accept` header, defaulting to Prometheus
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        match accept {
+            Some(accept) if accept.contains("application/openmetrics-text") => ExpositionFormat::OpenMetrics,
+            _ => ExpositionFormat::Prometheus,
+        }
+    }
+
+    /// Content type of a response in this format
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExpositionFormat::Prometheus => "text/plain; version=0.0.4",
+            ExpositionFormat::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+/// Write the `# HELP`, `# TYPE` and, for OpenMetrics, `# UNIT` lines of a metric family
+fn write_metadata(out: &mut String, format: ExpositionFormat, family: &str, kind: &str, unit: Option<&str>, help: &str) {
+    // OpenMetrics names counter families without the `_total` suffix of their samples
+    let family_name = match (format, kind) {
+        (ExpositionFormat::OpenMetrics, "counter") => family.trim_end_matches("_total"),
+        _ => family,
+    };
+    let _ = writeln!(out, "# HELP {} {}", family_name, help);
+    let _ = writeln!(out, "# TYPE {} {}", family_name, kind);
+    if let (ExpositionFormat::OpenMetrics, Some(unit)) = (format, unit) {
+        let _ = writeln!(out, "# UNIT {} {}", family_name, unit);
+    }
+}
+
+/// Escape a label value for the text exposition formats
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Outcome of a `fetch_data` call, used as the `result` label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchResult {
+    Success,
+    Error,
+    CacheHit,
+}
+
+/// Cumulative histogram with fixed bucket bounds
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    /// Create an empty histogram with the given upper bounds
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// Record one observation
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            if seconds <= *bound {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Write the histogram series in Prometheus text format
+    fn render(&self, name: &str, out: &mut String) {
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count.load(Ordering::Relaxed));
+        }
+        let total = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, total);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+        let _ = writeln!(out, "{}_count {}", name, total);
+    }
+}
+
+/// Fetch timings of one queried range
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct RangeTiming {
+    /// Begin and end of the range, as `begin/end`
+    pub range: String,
+    /// Commands run for the range
+    pub count: u64,
+    pub avg_ms: f64,
+    pub last_ms: f64,
+}
+
+/// Running totals of the commands run for one range
+#[derive(Debug)]
+struct RangeTotals {
+    count: u64,
+    total: Duration,
+    last: Duration,
+    /// When the range was last fetched, in recorded fetches
+    sequence: u64,
+}
+
+/// Fetch timings per range, keeping the `max_ranges` most recently fetched
+#[derive(Debug)]
+struct RangeTimings {
+    max_ranges: usize,
+    ranges: HashMap<String, RangeTotals>,
+    sequence: u64,
+}
+
+impl RangeTimings {
+    /// Add one command's duration to its range, evicting the least recently fetched range when full
+    fn record(&mut self, range: &str, duration: Duration) {
+        if self.max_ranges == 0 {
+            return;
+        }
+        self.sequence += 1;
+        if !self.ranges.contains_key(range)
+            && self.ranges.len() >= self.max_ranges
+            && let Some(oldest) = self.ranges.iter().min_by_key(|(_, totals)| totals.sequence).map(|(range, _)| range.clone())
+        {
+            self.ranges.remove(&oldest);
+        }
+        let totals = self.ranges.entry(range.to_string()).or_insert(RangeTotals {
+            count: 0,
+            total: Duration::ZERO,
+            last: Duration::ZERO,
+            sequence: 0,
+        });
+        totals.count += 1;
+        totals.total += duration;
+        totals.last = duration;
+        totals.sequence = self.sequence;
+    }
+
+    /// Timings of every kept range, slowest on average first
+    fn timings(&self) -> Vec<RangeTiming> {
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let mut timings: Vec<RangeTiming> = self.ranges
+            .iter()
+            .map(|(range, totals)| RangeTiming {
+                range: range.clone(),
+                count: totals.count,
+                avg_ms: millis(totals.total) / totals.count as f64,
+                last_ms: millis(totals.last),
+            })
+            .collect();
+        timings.sort_by(|a, b| b.avg_ms.total_cmp(&a.avg_ms).then_with(|| a.range.cmp(&b.range)));
+        timings
+    }
+}
+
+/// Ranges whose fetch timings are kept by default
+pub const DEFAULT_MAX_RANGES: usize = 100;
+
+/// Metrics collected by the data service
+#[derive(Debug)]
+pub struct Metrics {
+    fetch_duration: Histogram,
+    fetch_success: AtomicU64,
+    fetch_error: AtomicU64,
+    fetch_cache_hit: AtomicU64,
+    chart_builds: AtomicU64,
+    range_timings: Mutex<RangeTimings>,
+}
+
+impl Metrics {
+    /// Create an empty metrics registry
+    pub fn new() -> Self {
+        Self {
+            fetch_duration: Histogram::new(DEFAULT_BUCKETS),
+            fetch_success: AtomicU64::new(0),
+            fetch_error: AtomicU64::new(0),
+            fetch_cache_hit: AtomicU64::new(0),
+            chart_builds: AtomicU64::new(0),
+            range_timings: Mutex::new(RangeTimings {
+                max_ranges: DEFAULT_MAX_RANGES,
+                ranges: HashMap::new(),
+                sequence: 0,
+            }),
+        }
+    }
+
+    /// Keep the fetch timings of at most `max_ranges` ranges (none when `0`)
+    pub fn with_max_ranges(self, max_ranges: usize) -> Self {
+        self.range_timings.lock().unwrap().max_ranges = max_ranges;
+        self
+    }
+
+    /// Record the duration of one OpenStack command execution
+    pub fn observe_fetch_duration(&self, duration: Duration) {
+        self.fetch_duration.observe(duration);
+    }
+
+    /// Record the duration of a successful command fetching `range` (`begin/end`)
+    pub fn observe_range_duration(&self, range: &str, duration: Duration) {
+        self.range_timings.lock().unwrap().record(range, duration);
+    }
+
+    /// Fetch timings of the recently fetched ranges, slowest on average first
+    pub fn range_timings(&self) -> Vec<RangeTiming> {
+        self.range_timings.lock().unwrap().timings()
+    }
+
+    /// Count a completed `fetch_data` call
+    pub fn record_fetch(&self, result: FetchResult) {
+        let counter = match result {
+            FetchResult::Success => &self.fetch_success,
+            FetchResult::Error => &self.fetch_error,
+            FetchResult::CacheHit => &self.fetch_cache_hit,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count ratings processed into chart data
+    pub fn record_chart_build(&self) {
+        self.chart_builds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of times ratings were processed into chart data
+    pub fn chart_builds(&self) -> u64 {
+        self.chart_builds.load(Ordering::Relaxed)
+    }
+
+    /// Render all metrics, including cost gauges for the current chart, in the given format
+    ///
+    /// `cache_lookups` are the cumulative cache hits and misses and `cache_bytes`
+    /// the estimated memory held by the cache.
+    pub fn render(&self, format: ExpositionFormat, chart: &ChartData, cache_lookups: (u64, u64), cache_bytes: usize) -> String {
+        let mut out = String::new();
+        write_metadata(&mut out, format, "openstack_fetch_duration_seconds", "histogram", Some("seconds"),
+            "Duration of OpenStack CLI command executions");
+        self.fetch_duration.render("openstack_fetch_duration_seconds", &mut out);
+
+        write_metadata(&mut out, format, "openstack_fetch_total", "counter", None, "Number of data fetches by result");
+        for (result, counter) in [
+            ("success", &self.fetch_success),
+            ("error", &self.fetch_error),
+            ("cache_hit", &self.fetch_cache_hit),
+        ] {
+            let _ = writeln!(out, "openstack_fetch_total{{result=\"{}\"}} {}", result, counter.load(Ordering::Relaxed));
+        }
+
+        let (hits, misses) = cache_lookups;
+        write_metadata(&mut out, format, "openstack_cache_lookups_total", "counter", None, "Number of cache lookups by result");
+        let _ = writeln!(out, "openstack_cache_lookups_total{{result=\"hit\"}} {}", hits);
+        let _ = writeln!(out, "openstack_cache_lookups_total{{result=\"miss\"}} {}", misses);
+        write_metadata(&mut out, format, "openstack_cache_hit_ratio", "gauge", Some("ratio"),
+            "Share of cache lookups that found a valid entry since startup");
+        let _ = writeln!(out, "openstack_cache_hit_ratio {}", hit_ratio(hits, misses));
+        write_metadata(&mut out, format, "openstack_cache_size_bytes", "gauge", Some("bytes"),
+            "Estimated memory held by cached ratings");
+        let _ = writeln!(out, "openstack_cache_size_bytes {}", cache_bytes);
+
+        write_metadata(&mut out, format, "openstack_chart_builds_total", "counter", None,
+            "Number of times fetched ratings were processed into chart data");
+        let _ = writeln!(out, "openstack_chart_builds_total {}", self.chart_builds());
+
+        write_metadata(&mut out, format, "openstack_cost_currency", "gauge", Some("currency"),
+            "Total cost of the current period in the display currency");
+        let _ = writeln!(out, "openstack_cost_currency {}", chart.total_cost);
+
+        write_metadata(&mut out, format, "openstack_service_cost_currency", "gauge", Some("currency"),
+            "Cost of each service in the current period in the display currency");
+        for (service, cost) in chart.keys().iter().zip(&chart.values) {
+            let _ = writeln!(out, "openstack_service_cost_currency{{service=\"{}\"}} {}", escape_label(service), cost);
+        }
+
+        if format == ExpositionFormat::OpenMetrics {
+            out.push_str("# EOF\n");
+        }
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_in_exposition() {
+        let metrics = Metrics::new();
+        metrics.observe_fetch_duration(Duration::from_millis(3));
+        metrics.observe_fetch_duration(Duration::from_millis(200));
+        metrics.observe_fetch_duration(Duration::from_secs(20));
+        metrics.record_fetch(FetchResult::Success);
+        metrics.record_fetch(FetchResult::CacheHit);
+        metrics.record_fetch(FetchResult::CacheHit);
+
+        let output = metrics.render(ExpositionFormat::Prometheus, &ChartData::empty(), (3, 1), 2048);
+        assert!(output.contains("openstack_fetch_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(output.contains("openstack_fetch_duration_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(output.contains("openstack_fetch_duration_seconds_bucket{le=\"0.25\"} 2\n"));
+        assert!(output.contains("openstack_fetch_duration_seconds_bucket{le=\"10\"} 2\n"));
+        assert!(output.contains("openstack_fetch_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(output.contains("openstack_fetch_duration_seconds_count 3\n"));
+        assert!(output.contains("openstack_fetch_total{result=\"success\"} 1\n"));
+        assert!(output.contains("openstack_fetch_total{result=\"error\"} 0\n"));
+        assert!(output.contains("openstack_fetch_total{result=\"cache_hit\"} 2\n"));
+        assert!(output.contains("# TYPE openstack_fetch_total counter\n"));
+        assert!(output.contains("openstack_cache_lookups_total{result=\"hit\"} 3\n"));
+        assert!(output.contains("openstack_cache_lookups_total{result=\"miss\"} 1\n"));
+        assert!(output.contains("openstack_cache_hit_ratio 0.75\n"));
+        assert!(output.contains("openstack_cache_size_bytes 2048\n"));
+        assert!(!output.contains("# UNIT"));
+        assert!(!output.contains("# EOF"));
+    }
+
+    #[test]
+    fn test_openmetrics_output_has_units_and_trailer() {
+        let chart = ChartData {
+            labels: vec!["compute".to_string(), "block \"storage\"".to_string()],
+            values: vec![12.5, 2.5],
+            total_cost: 15.0,
+            ..ChartData::empty()
+        };
+        let output = Metrics::new().render(ExpositionFormat::OpenMetrics, &chart, (0, 0), 0);
+
+        assert!(output.ends_with("# EOF\n"));
+        assert!(output.contains("# UNIT openstack_fetch_duration_seconds seconds\n"));
+        assert!(output.contains("# UNIT openstack_cost_currency currency\n"));
+        assert!(output.contains("# UNIT openstack_service_cost_currency currency\n"));
+        assert!(output.contains("# TYPE openstack_fetch counter\n"));
+        assert!(output.contains("openstack_cost_currency 15\n"));
+        assert!(output.contains("openstack_service_cost_currency{service=\"block \\\"storage\\\"\"} 2.5\n"));
+    }
+
+    #[test]
+    fn test_format_is_negotiated_from_accept_header() {
+        assert_eq!(ExpositionFormat::from_accept(None), ExpositionFormat::Prometheus);
+        assert_eq!(ExpositionFormat::from_accept(Some("text/plain")), ExpositionFormat::Prometheus);
+        assert_eq!(
+            ExpositionFormat::from_accept(Some("application/openmetrics-text; version=1.0.0,text/plain;q=0.5")),
+            ExpositionFormat::OpenMetrics
+        );
+    }
+
+    #[test]
+    fn test_range_timings_keep_the_most_recent_ranges() {
+        let metrics = Metrics::new().with_max_ranges(2);
+        metrics.observe_range_duration("january", Duration::from_millis(30));
+        metrics.observe_range_duration("february", Duration::from_millis(10));
+        metrics.observe_range_duration("january", Duration::from_millis(10));
+        metrics.observe_range_duration("march", Duration::from_millis(5));
+
+        let timings = metrics.range_timings();
+        let ranges: Vec<&str> = timings.iter().map(|timing| timing.range.as_str()).collect();
+        assert_eq!(ranges, ["january", "march"], "february was fetched least recently");
+        assert_eq!((timings[0].count, timings[0].avg_ms, timings[0].last_ms), (2, 20.0, 10.0));
+
+        let disabled = Metrics::new().with_max_ranges(0);
+        disabled.observe_range_duration("january", Duration::from_millis(30));
+        assert!(disabled.range_timings().is_empty());
+    }
+}
@@ -0,0 +1,288 @@
+//! HTTP middleware for the OpenStack Cost Dashboard
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::{info, warn};
+
+use crate::AppState;
+use crate::config::Config;
+use crate::error::ApiError;
+
+/// Headers added to every response
+pub type SecurityHeaders = Arc<Vec<(HeaderName, HeaderValue)>>;
+
+/// Build the security header set from the configuration
+///
+/// Headers whose configured value is empty or not a valid header value are skipped.
+pub fn security_headers(config: &Config) -> SecurityHeaders {
+    let candidates = [
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        (header::X_FRAME_OPTIONS, config.frame_options.as_str()),
+        (header::CONTENT_SECURITY_POLICY, config.csp_policy.as_str()),
+        (header::REFERRER_POLICY, "no-referrer"),
+    ];
+
+    let mut headers = Vec::new();
+    for (name, value) in candidates {
+        if value.trim().is_empty() {
+            continue;
+        }
+        match HeaderValue::from_str(value) {
+            Ok(value) => headers.push((name, value)),
+            Err(e) => warn!("Invalid value for {} header, skipping it: {}", name, e),
+        }
+    }
+    Arc::new(headers)
+}
+
+/// Add the configured security headers to every response
+pub async fn add_security_headers(
+    State(headers): State<SecurityHeaders>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    for (name, value) in headers.iter() {
+        response.headers_mut().insert(name.clone(), value.clone());
+    }
+    response
+}
+
+/// Query parameters masked in access log lines
+pub type RedactedParams = Arc<Vec<String>>;
+
+/// Log method, path, status and latency of every request
+pub async fn access_log(
+    State(redacted): State<RedactedParams>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let target = redact_query(request.uri().path(), request.uri().query(), &redacted);
+    let response = next.run(request).await;
+    info!(
+        target: "access_log",
+        "{} {} {} {}ms",
+        method,
+        target,
+        response.status().as_u16(),
+        started.elapsed().as_millis()
+    );
+    response
+}
+
+/// Durations a handler measured, by `Server-Timing` metric name
+///
+/// Handlers add these to their response extensions for `server_timing` to report.
+#[derive(Debug, Clone, Default)]
+pub struct ServerTimings(pub Vec<(&'static str, Duration)>);
+
+/// Add a `Server-Timing` header with the handler's timings and the `total` time spent on the request
+pub async fn server_timing(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let mut response = next.run(request).await;
+    let timings = response.extensions_mut().remove::<ServerTimings>().unwrap_or_default();
+    let mut metrics: Vec<String> = timings.0.iter().map(|(name, duration)| timing_metric(name, *duration)).collect();
+    metrics.push(timing_metric("total", started.elapsed()));
+    if let Ok(value) = HeaderValue::from_str(&metrics.join(", ")) {
+        response.headers_mut().insert(HeaderName::from_static("server-timing"), value);
+    }
+    response
+}
+
+/// One `Server-Timing` metric, with the duration in milliseconds
+fn timing_metric(name: &str, duration: Duration) -> String {
+    format!("{};dur={:.1}", name, duration.as_secs_f64() * 1000.0)
+}
+
+/// Rebuild a request target with the values of denylisted query parameters masked
+fn redact_query(path: &str, query: Option<&str>, redacted: &[String]) -> String {
+    let Some(query) = query else {
+        return path.to_string();
+    };
+    let pairs: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if redacted.contains(&name.to_ascii_lowercase()) => format!("{}=[REDACTED]", name),
+            _ => pair.to_string(),
+        })
+        .collect();
+    format!("{}?{}", path, pairs.join("&"))
+}
+
+/// Token expected in the `Authorization: Bearer` header of protected routes
+pub type ApiToken = Option<Arc<str>>;
+
+/// Reject requests to protected routes that lack the configured API token
+///
+/// Protected routes are refused with `403` when no `API_TOKEN` is configured,
+/// and with `401` when the bearer token is missing or wrong.
+pub async fn require_api_token(
+    State(token): State<ApiToken>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(expected) = token else {
+        return ApiError::forbidden(format!("{} is disabled because API_TOKEN is not configured", request.uri().path()))
+            .into_response();
+    };
+    match bearer_token(request.headers()) {
+        Some(provided) if constant_time_eq(provided.as_bytes(), expected.as_bytes()) => next.run(request).await,
+        _ => ApiError::unauthorized("Missing or invalid API token").into_response(),
+    }
+}
+
+/// Services a request may view: all of them, or those of its `TOKEN_SCOPES` entry
+///
+/// Once `TOKEN_SCOPES` is set, data requests must carry `API_TOKEN` (which sees
+/// every service) or one of the scoped tokens.
+pub fn allowed_services(config: &Config, headers: &HeaderMap) -> Result<Option<Vec<String>>, ApiError> {
+    let scopes = &config.token_scopes;
+    if scopes.is_empty() {
+        return Ok(None);
+    }
+    let Some(provided) = bearer_token(headers) else {
+        return Err(ApiError::unauthorized("Missing or invalid API token"));
+    };
+    if let Some(admin) = &config.api_token
+        && constant_time_eq(provided.as_bytes(), admin.as_bytes())
+    {
+        return Ok(None);
+    }
+    scopes
+        .iter()
+        .find(|(token, _)| constant_time_eq(provided.as_bytes(), token.as_bytes()))
+        .map(|(_, services)| Some(services.clone()))
+        .ok_or_else(|| ApiError::unauthorized("Missing or invalid API token"))
+}
+
+/// Keep scoped tokens off data routes that cannot narrow their response to a scope
+///
+/// Once `TOKEN_SCOPES` is set these routes need `API_TOKEN`: scoped tokens are
+/// refused with `403`, missing or unknown ones with `401`. The live configuration
+/// is read on every request so reloaded scopes apply at once.
+pub async fn require_full_access(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match allowed_services(&state.config(), request.headers()) {
+        Ok(None) => next.run(request).await,
+        Ok(Some(_)) => ApiError::forbidden(format!("{} is not available to scoped tokens", request.uri().path()))
+            .into_response(),
+        Err(error) => error.into_response(),
+    }
+}
+
+/// Bearer token of the `Authorization` header, if any
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Compare two byte strings without short-circuiting on the first difference
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Paths polled by probes and scrapers, which don't count as activity
+const PROBE_PATHS: &[&str] = &["/api/health", "/api/ready", "/metrics"];
+
+/// When HTTP requests were last handled, for `IDLE_SHUTDOWN_SECONDS`
+#[derive(Debug)]
+pub struct Activity {
+    started: Instant,
+    /// Milliseconds after `started` a request last began or finished
+    last_request_ms: AtomicU64,
+    in_flight: AtomicUsize,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_request_ms: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        self.last_request_ms.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// How long no request has been handled, zero while one is in flight
+    pub fn idle_for(&self) -> Duration {
+        if self.in_flight.load(Ordering::Relaxed) > 0 {
+            return Duration::ZERO;
+        }
+        let last_request = Duration::from_millis(self.last_request_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last_request)
+    }
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Marks a request as finished even if its handler is cancelled
+struct InFlight<'a>(&'a Activity);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.touch();
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Record request activity, leaving out health probes and metrics scrapes
+pub async fn track_activity(
+    State(activity): State<Arc<Activity>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if PROBE_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    activity.in_flight.fetch_add(1, Ordering::Relaxed);
+    activity.touch();
+    let _in_flight = InFlight(&activity);
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use tower::ServiceExt;
+    use crate::test_support::CapturedLogs;
+
+    #[tokio::test]
+    async fn test_access_log_line_has_status_and_path() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+
+        let app = Router::new()
+            .route("/api/health", get(|| async { StatusCode::OK }))
+            .layer(middleware::from_fn_with_state(Arc::new(vec!["token".to_string()]), access_log));
+        app.oneshot(Request::get("/api/health?token=hunter2&verbose=1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let output = logs.text();
+        let line = output.lines().find(|line| line.contains("access_log")).expect("access log line");
+        assert!(line.contains("GET /api/health?token=[REDACTED]&verbose=1 200 "), "{}", line);
+        assert!(!line.contains("hunter2"));
+    }
+}
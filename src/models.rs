@@ -1,30 +1,279 @@
 //! Data models for the OpenStack Cost Dashboard
 
-use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// A single resource from OpenStack rating data
 #[derive(Debug, Deserialize, Clone)]
 pub struct Resource {
-    pub rating: String,
+    /// Rating as a string or a JSON number; missing or unparsable ratings become NaN and are skipped
+    #[serde(default = "missing_rating", deserialize_with = "deserialize_rating")]
+    pub rating: f64,
+    /// Empty when the row lacks it; such rows are skipped and count as schema drift
+    #[serde(default)]
     pub service: String,
+    /// Keystone domain the resource belongs to, when the dataframe carries it
+    #[serde(default)]
+    pub domain_id: Option<String>,
+    /// Lifecycle state of the resource (e.g. `active`), when the dataframe carries it
+    #[serde(default, alias = "status")]
+    pub state: Option<String>,
+}
+
+/// Rating of a row without one
+fn missing_rating() -> f64 {
+    f64::NAN
+}
+
+/// Read a rating given either as a JSON number or as a numeric string
+fn deserialize_rating<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Rating {
+        Number(f64),
+        Text(String),
+    }
+
+    Ok(match Rating::deserialize(deserializer)? {
+        Rating::Number(value) => value,
+        // Older CloudKitty releases quote the rating; keep skipping rows it can't parse
+        Rating::Text(text) => text.trim().parse().unwrap_or(f64::NAN),
+    })
 }
 
 /// Wrapper for resources from OpenStack API response
 #[derive(Debug, Deserialize)]
 pub struct ResourceWrapper {
+    #[serde(rename = "Begin", default)]
+    pub begin: Option<String>,
+    #[serde(rename = "End", default)]
+    pub end: Option<String>,
     #[serde(rename = "Resources")]
     pub resources: Vec<Resource>,
 }
 
+/// Costs accumulated over a single dataframe time window
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TimeBucket {
+    pub begin: String,
+    pub end: String,
+    pub total_cost: f64,
+    pub services: HashMap<String, f64>,
+}
+
+/// Processed rating data for a queried period
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RatingData {
+    /// Total cost per service over the whole period
+    pub costs: HashMap<String, f64>,
+    /// Total rating per service before currency conversion
+    pub raw_costs: HashMap<String, f64>,
+    /// Total cost per domain (only with `SPLIT_BY=domain`)
+    pub domain_costs: HashMap<String, f64>,
+    /// Total rating per domain before currency conversion (only with `SPLIT_BY=domain`)
+    pub domain_raw_costs: HashMap<String, f64>,
+    /// Data of each `RATING_ENDPOINTS` endpoint that was merged into this one
+    pub by_endpoint: BTreeMap<String, RatingData>,
+    /// Costs bucketed by dataframe window, ordered by begin timestamp
+    pub timeseries: Vec<TimeBucket>,
+    /// Dataframes in the CLI output
+    #[serde(default)]
+    pub dataframe_count: usize,
+    /// Resource rows across all dataframes, before state filtering and sampling
+    #[serde(default)]
+    pub row_count: usize,
+}
+
+impl RatingData {
+    /// Rough size of the data in memory: key lengths plus one `f64` per map entry
+    ///
+    /// Allocator and hash table overhead are left out, so this is a lower bound.
+    pub fn approx_bytes(&self) -> usize {
+        let map_bytes = |map: &HashMap<String, f64>| -> usize {
+            map.keys().map(|key| key.len() + std::mem::size_of::<f64>()).sum()
+        };
+        let timeseries: usize = self.timeseries
+            .iter()
+            .map(|bucket| bucket.begin.len() + bucket.end.len() + std::mem::size_of::<f64>() + map_bytes(&bucket.services))
+            .sum();
+        let endpoints: usize = self.by_endpoint
+            .iter()
+            .map(|(endpoint, rating)| endpoint.len() + rating.approx_bytes())
+            .sum();
+        map_bytes(&self.costs)
+            + map_bytes(&self.raw_costs)
+            + map_bytes(&self.domain_costs)
+            + map_bytes(&self.domain_raw_costs)
+            + timeseries
+            + endpoints
+    }
+}
+
+/// Result of a data fetch along with where it came from
+#[derive(Debug, Clone, Default)]
+pub struct FetchOutcome {
+    pub rating: RatingData,
+    /// Whether the data was served from the cache rather than OpenStack
+    pub from_cache: bool,
+    /// Whether the data is an expired cache entry served after fetching failed
+    pub stale: bool,
+    /// Why no data could be fetched, if the fetch failed
+    pub error: Option<String>,
+    /// Whether some `RATING_ENDPOINTS` endpoints failed and are missing from the data
+    pub partial: bool,
+    /// Why each failed endpoint could not be fetched
+    pub endpoint_errors: BTreeMap<String, String>,
+    /// Whether the fetch gave up waiting for a free `MAX_CONCURRENT_FETCHES` slot
+    pub overloaded: bool,
+    /// Begin and end date strings the query resolved to
+    pub range: Option<(String, String)>,
+    /// Cache key the ratings are stored under, unless they are stale (joined with `+` across endpoints)
+    pub cache_key: Option<String>,
+}
+
+impl FetchOutcome {
+    /// Create an empty outcome for a failed fetch
+    pub fn failed(error: impl Into<String>) -> Self {
+        Self {
+            error: Some(error.into()),
+            ..Self::default()
+        }
+    }
+}
+
 /// Chart data structure sent to the frontend
-#[derive(Debug, Serialize, Clone)]
+///
+/// Costs are serialized with `JSON_SIGNIFICANT_DIGITS` (see `serialize_cost`) so the
+/// JSON is free of float noise while sums keep full precision internally.
+#[derive(Debug, Clone, Serialize)]
 pub struct ChartData {
     pub labels: Vec<String>,
+    /// Untruncated labels, parallel to `labels` (only when labels are truncated)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub full_labels: Vec<String>,
+    #[serde(serialize_with = "serialize_costs")]
     pub values: Vec<f64>,
+    /// Share-of-total bucket of each service ("major", "moderate" or "minor"), parallel to `labels`
+    pub categories: Vec<String>,
+    /// Pre-conversion rating per service, parallel to `values` (only when enabled)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub raw_values: Vec<f64>,
+    #[serde(serialize_with = "serialize_cost")]
     pub total_cost: f64,
     pub service_count: usize,
+    #[serde(serialize_with = "serialize_cost")]
     pub average_cost: f64,
+    /// Total written with `CURRENCY_FORMAT_PATTERN` and `COST_UNIT_LABEL`
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub formatted_total: String,
+    /// Average service cost written like `formatted_total`
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub formatted_average_cost: String,
+    /// Each service cost written like `formatted_total`, parallel to `values`
+    pub formatted_values: Vec<String>,
+    /// Median service cost, linearly interpolated between closest ranks
+    #[serde(serialize_with = "serialize_cost")]
+    pub p50: f64,
+    /// 90th percentile of service costs, linearly interpolated between closest ranks
+    #[serde(serialize_with = "serialize_cost")]
+    pub p90: f64,
+    /// Cost of the most expensive service
+    #[serde(serialize_with = "serialize_cost")]
+    pub max_service_cost: f64,
     pub last_updated: String,
+    /// Whether costs were estimated from a sample of the dataframe rows
+    pub sampled: bool,
+    /// Whether the data is an expired cached value served during an outage
+    pub stale: bool,
+    /// Whether some rating endpoints failed and their costs are missing
+    pub partial: bool,
+    /// Whether the fetch succeeded but every cost is zero
+    pub zero_cost: bool,
+    /// Why the data could not be fetched, if it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Services whose cost exceeded `SERVICE_COST_CAP` and was clamped to it
+    pub anomalies: Vec<String>,
+    /// Costs in each additional currency, served by `/api/data/currencies`
+    #[serde(skip)]
+    pub currencies: BTreeMap<String, CurrencyCosts>,
+    /// Pre-conversion rating per service, parallel to `values`, kept for repricing (not serialized)
+    #[serde(skip)]
+    pub raw_costs: Vec<f64>,
+    /// Begin and end date strings the chart was fetched for, if known (not serialized)
+    #[serde(skip)]
+    pub range: Option<(String, String)>,
+    /// Change of each service since the previous refresh (only with `INCLUDE_DELTAS`)
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_optional_deltas")]
+    pub deltas: Option<BTreeMap<String, f64>>,
+    /// Average cost per day of the queried range (only with `INCLUDE_RUN_RATE`)
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_optional_cost")]
+    pub normalized_daily_total: Option<f64>,
+    /// Daily run-rate extrapolated over the month the range begins in (only with `INCLUDE_RUN_RATE`)
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_optional_cost")]
+    pub projected_monthly_total: Option<f64>,
+    /// Dataframes the costs were computed from (only with `INCLUDE_ROW_COUNTS`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataframe_count: Option<usize>,
+    /// Resource rows in those dataframes (only with `INCLUDE_ROW_COUNTS`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row_count: Option<usize>,
+    /// Herfindahl-Hirschman index of the service costs, from near 0 to 1 (only with `INCLUDE_CONCENTRATION`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concentration: Option<f64>,
+    /// Share of the total spent on the most expensive service (only with `INCLUDE_CONCENTRATION`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_service_share: Option<f64>,
+}
+
+/// Significant digits costs are written with in JSON: enough for any bill, and few
+/// enough to drop the float noise of sums such as `0.1 + 0.2`
+const JSON_SIGNIFICANT_DIGITS: i32 = 12;
+
+/// Round a cost to `JSON_SIGNIFICANT_DIGITS`, trimming float noise when serializing
+///
+/// This is not where `COST_PRECISION` applies: costs are rounded to it when they are
+/// computed (see `DataService::round_cost`), so totals match the rows, and keep their
+/// decimals here. Full-precision costs lose only digits beyond what a float reliably carries.
+fn without_noise(cost: f64) -> f64 {
+    if cost == 0.0 || !cost.is_finite() {
+        return cost;
+    }
+    let factor = 10f64.powi(JSON_SIGNIFICANT_DIGITS - 1 - cost.abs().log10().floor() as i32);
+    if factor.is_finite() { (cost * factor).round() / factor } else { cost }
+}
+
+fn serialize_cost<S: Serializer>(cost: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(without_noise(*cost))
+}
+
+fn serialize_costs<S: Serializer>(costs: &[f64], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(costs.iter().map(|cost| without_noise(*cost)))
+}
+
+fn serialize_optional_cost<S: Serializer>(cost: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    cost.map(without_noise).serialize(serializer)
+}
+
+fn serialize_optional_deltas<S: Serializer>(deltas: &Option<BTreeMap<String, f64>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match deltas {
+        Some(deltas) => serializer.collect_map(deltas.iter().map(|(service, delta)| (service, without_noise(*delta)))),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Costs converted to one additional currency, parallel to the chart labels
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct CurrencyCosts {
+    pub total_cost: f64,
+    pub values: Vec<f64>,
+}
+
+/// Response of `/api/data/currencies`
+#[derive(Debug, Serialize, Clone)]
+pub struct CurrencyBreakdown {
+    pub labels: Vec<String>,
+    pub currencies: BTreeMap<String, CurrencyCosts>,
 }
 
 impl ChartData {
@@ -32,11 +281,94 @@ impl ChartData {
     pub fn empty() -> Self {
         Self {
             labels: Vec::new(),
+            full_labels: Vec::new(),
             values: Vec::new(),
+            categories: Vec::new(),
+            raw_values: Vec::new(),
             total_cost: 0.0,
             service_count: 0,
             average_cost: 0.0,
+            formatted_total: String::new(),
+            formatted_average_cost: String::new(),
+            formatted_values: Vec::new(),
+            p50: 0.0,
+            p90: 0.0,
+            max_service_cost: 0.0,
             last_updated: String::new(),
+            sampled: false,
+            stale: false,
+            partial: false,
+            zero_cost: false,
+            error: None,
+            anomalies: Vec::new(),
+            currencies: BTreeMap::new(),
+            raw_costs: Vec::new(),
+            range: None,
+            deltas: None,
+            normalized_daily_total: None,
+            projected_monthly_total: None,
+            dataframe_count: None,
+            row_count: None,
+            concentration: None,
+            top_service_share: None,
         }
     }
+
+    /// Get the untruncated label of every entry, for use as a lookup key
+    pub fn keys(&self) -> &[String] {
+        if self.full_labels.is_empty() {
+            &self.labels
+        } else {
+            &self.full_labels
+        }
+    }
+
+    /// Change of each service's cost since `previous`
+    ///
+    /// Services new in this chart report their full cost, services that
+    /// disappeared report the negative of their previous cost.
+    pub fn deltas_since(&self, previous: &ChartData) -> BTreeMap<String, f64> {
+        let mut deltas: BTreeMap<String, f64> = previous
+            .keys()
+            .iter()
+            .zip(&previous.values)
+            .map(|(service, cost)| (service.clone(), -cost))
+            .collect();
+        for (service, cost) in self.keys().iter().zip(&self.values) {
+            *deltas.entry(service.clone()).or_insert(0.0) += cost;
+        }
+        deltas
+    }
+}
+
+/// Raw output of the most recent OpenStack command, kept for bug reports
+#[derive(Debug, Serialize, Clone)]
+pub struct CapturedOutput {
+    pub captured_at: String,
+    /// Exit status of the command
+    pub status: String,
+    /// Standard output with obvious secrets redacted
+    pub stdout: String,
+    /// Whether `stdout` was cut to the capture limit
+    pub truncated: bool,
+}
+
+/// Command a fetch would run, with secrets redacted
+#[derive(Debug, Serialize, Clone)]
+pub struct CommandPreview {
+    /// Cloud selected with `--os-cloud`, or `None` for the `OS_*` credentials
+    pub cloud: Option<String>,
+    /// Program executed (`ssh` with `SSH_HOST`)
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+/// Response returned by a manual refresh
+#[derive(Debug, Serialize, Clone)]
+pub struct RefreshResponse {
+    #[serde(flatten)]
+    pub chart: ChartData,
+    pub from_cache: bool,
+    /// Whether this is a `READ_ONLY_REPLICA`, which refreshes from the primary's cache instead of OpenStack
+    pub replica: bool,
 }
@@ -0,0 +1,214 @@
+//! Server-side PNG rendering of the cost chart
+
+use std::sync::OnceLock;
+use plotters::coord::ranged1d::SegmentValue;
+use plotters::prelude::*;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::models::ChartData;
+
+/// Font family name the chart font is registered under
+const FONT_FAMILY: &str = "sans-serif";
+
+/// Whether the chart font could be loaded; text is left out of the image otherwise
+static FONT_LOADED: OnceLock<bool> = OnceLock::new();
+
+/// Shape of the rendered chart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChartKind {
+    #[default]
+    Bar,
+    Pie,
+}
+
+/// Load the TrueType font used for titles and labels, once per process
+fn ensure_font(path: &str) -> bool {
+    *FONT_LOADED.get_or_init(|| {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!("Failed to read CHART_FONT {}, rendering charts without text: {}", path, e);
+                return false;
+            }
+        };
+        // Registered fonts must live for the whole process
+        let bytes: &'static [u8] = Box::leak(bytes.into_boxed_slice());
+        match plotters::style::register_font(FONT_FAMILY, FontStyle::Normal, bytes) {
+            Ok(()) => {
+                info!("Loaded chart font from {}", path);
+                true
+            }
+            Err(_) => {
+                warn!("CHART_FONT {} is not a valid font, rendering charts without text", path);
+                false
+            }
+        }
+    })
+}
+
+/// Convert a `#rgb` or `#rrggbb` palette entry into a color
+fn parse_color(color: &str) -> RGBColor {
+    let hex = color.trim_start_matches('#');
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).unwrap_or(0);
+    match hex.len() {
+        3 => {
+            let expand = |i: usize| channel(&hex[i..=i].repeat(2));
+            RGBColor(expand(0), expand(1), expand(2))
+        }
+        6 => RGBColor(channel(&hex[0..2]), channel(&hex[2..4]), channel(&hex[4..6])),
+        _ => RGBColor(128, 128, 128),
+    }
+}
+
+/// Render the chart as a PNG image
+///
+/// Charts without any cost render a placeholder so image consumers never break.
+pub fn render_png(
+    chart: &ChartData,
+    kind: ChartKind,
+    (width, height): (u32, u32),
+    palette: &[String],
+    title: &str,
+    font_path: &str,
+) -> Result<Vec<u8>, String> {
+    let with_text = ensure_font(font_path);
+    let colors: Vec<RGBColor> = palette.iter().map(|color| parse_color(color)).collect();
+    let mut pixels = vec![0_u8; width as usize * height as usize * 3];
+    {
+        let root = BitMapBackend::with_buffer(&mut pixels, (width, height)).into_drawing_area();
+        root.fill(&WHITE).map_err(|e| e.to_string())?;
+        let has_data = chart.values.iter().any(|value| *value > 0.0);
+        let result = match (has_data, kind) {
+            (false, _) => draw_placeholder(&root, title, with_text),
+            (true, ChartKind::Bar) => draw_bar(&root, chart, &colors, title, with_text),
+            (true, ChartKind::Pie) => draw_pie(&root, chart, &colors, title, with_text),
+        };
+        result.map_err(|e| e.to_string())?;
+        root.present().map_err(|e| e.to_string())?;
+    }
+    encode_png(&pixels, width, height)
+}
+
+type DrawResult = Result<(), DrawingAreaErrorKind<<BitMapBackend<'static> as DrawingBackend>::ErrorType>>;
+
+fn draw_placeholder(root: &DrawingArea<BitMapBackend, plotters::coord::Shift>, title: &str, with_text: bool) -> DrawResult {
+    let (width, height) = root.dim_in_pixel();
+    root.draw(&Rectangle::new([(20, 20), (width as i32 - 20, height as i32 - 20)], RGBColor(200, 200, 200).stroke_width(2)))?;
+    if with_text {
+        let style = TextStyle::from((FONT_FAMILY, 24).into_font()).color(&RGBColor(120, 120, 120)).pos(
+            plotters::style::text_anchor::Pos::new(
+                plotters::style::text_anchor::HPos::Center,
+                plotters::style::text_anchor::VPos::Center,
+            ),
+        );
+        root.draw(&Text::new(format!("{}: no data", title), (width as i32 / 2, height as i32 / 2), style))?;
+    }
+    Ok(())
+}
+
+fn draw_bar(
+    root: &DrawingArea<BitMapBackend, plotters::coord::Shift>,
+    chart: &ChartData,
+    colors: &[RGBColor],
+    title: &str,
+    with_text: bool,
+) -> DrawResult {
+    let count = chart.values.len();
+    let max = chart.values.iter().copied().fold(0.0_f64, f64::max);
+    let mut builder = ChartBuilder::on(root);
+    builder.margin(20);
+    if with_text {
+        builder
+            .caption(title, (FONT_FAMILY, 24))
+            .x_label_area_size(40)
+            .y_label_area_size(60);
+    }
+    // Segmented ranges include their end, so `count` bars need `0..count - 1`
+    let mut plot = builder.build_cartesian_2d((0..count - 1).into_segmented(), 0.0..max * 1.1)?;
+    if with_text {
+        plot.configure_mesh()
+            .disable_x_mesh()
+            .x_labels(count)
+            .x_label_formatter(&|segment| match segment {
+                SegmentValue::CenterOf(index) => chart.labels.get(*index).cloned().unwrap_or_default(),
+                _ => String::new(),
+            })
+            .label_style((FONT_FAMILY, 12))
+            .draw()?;
+    }
+    plot.draw_series(chart.values.iter().enumerate().map(|(index, value)| {
+        let color = colors.get(index % colors.len().max(1)).copied().unwrap_or(BLUE);
+        let mut bar = Rectangle::new(
+            [(SegmentValue::Exact(index), 0.0), (SegmentValue::Exact(index + 1), *value)],
+            color.filled(),
+        );
+        bar.set_margin(0, 0, 6, 6);
+        bar
+    }))?;
+    Ok(())
+}
+
+fn draw_pie(
+    root: &DrawingArea<BitMapBackend, plotters::coord::Shift>,
+    chart: &ChartData,
+    colors: &[RGBColor],
+    title: &str,
+    with_text: bool,
+) -> DrawResult {
+    let area = if with_text { root.titled(title, (FONT_FAMILY, 24))? } else { root.clone() };
+    let (width, height) = area.dim_in_pixel();
+    let center = (width as i32 / 2, height as i32 / 2);
+    let radius = f64::from(width.min(height)) * 0.4;
+    let total: f64 = chart.values.iter().filter(|value| **value > 0.0).sum();
+
+    let mut start = -std::f64::consts::FRAC_PI_2;
+    for (index, value) in chart.values.iter().enumerate().filter(|(_, value)| **value > 0.0) {
+        let sweep = value / total * std::f64::consts::TAU;
+        // One point per degree keeps the arc smooth at any size
+        let steps = ((sweep.to_degrees()).ceil() as usize).max(1);
+        let mut points = vec![center];
+        points.extend((0..=steps).map(|step| {
+            let angle = start + sweep * step as f64 / steps as f64;
+            (center.0 + (radius * angle.cos()) as i32, center.1 + (radius * angle.sin()) as i32)
+        }));
+        let color = colors.get(index % colors.len().max(1)).copied().unwrap_or(BLUE);
+        area.draw(&Polygon::new(points, color.filled()))?;
+
+        if with_text && sweep > 0.2 {
+            let middle = start + sweep / 2.0;
+            let position = (
+                center.0 + (radius * 1.1 * middle.cos()) as i32,
+                center.1 + (radius * 1.1 * middle.sin()) as i32,
+            );
+            let label = chart.labels.get(index).cloned().unwrap_or_default();
+            area.draw(&Text::new(label, position, (FONT_FAMILY, 12).into_font()))?;
+        }
+        start += sweep;
+    }
+    Ok(())
+}
+
+/// Encode an RGB pixel buffer as PNG
+fn encode_png(pixels: &[u8], width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(pixels).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_colors_are_parsed() {
+        assert_eq!(parse_color("#3498db"), RGBColor(0x34, 0x98, 0xdb));
+        assert_eq!(parse_color("#abc"), RGBColor(0xaa, 0xbb, 0xcc));
+    }
+}
@@ -0,0 +1,136 @@
+//! Rolling-window cost totals built from dataframe time buckets
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::TimeBucket;
+
+/// Cost summed over a trailing window
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct RollingSum {
+    pub window_seconds: u64,
+    pub total_cost: f64,
+    pub services: HashMap<String, f64>,
+    /// Number of dataframe buckets inside the window
+    pub buckets: usize,
+}
+
+/// Per-service costs of recent dataframe windows, keyed by window start
+///
+/// Buckets are replaced rather than added when a window is fetched again, so
+/// overlapping queries never double count. Buckets older than the retention
+/// are evicted whenever new ones are recorded.
+#[derive(Debug)]
+pub struct RollingCosts {
+    retention: Duration,
+    buckets: BTreeMap<DateTime<Utc>, HashMap<String, f64>>,
+}
+
+impl RollingCosts {
+    /// Create an empty store keeping buckets for `retention`
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            retention,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Longest window that can be summed
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    /// Record the buckets of a fetch and evict expired ones
+    pub fn record(&mut self, timeseries: &[TimeBucket], now: DateTime<Utc>) {
+        for bucket in timeseries {
+            if let Ok(begin) = DateTime::parse_from_rfc3339(&bucket.begin) {
+                self.buckets.insert(begin.with_timezone(&Utc), bucket.services.clone());
+            }
+        }
+        let cutoff = now - self.retention;
+        self.buckets = self.buckets.split_off(&cutoff);
+    }
+
+    /// Sum the buckets that started within `window` before `now`
+    pub fn sum(&self, window: Duration, now: DateTime<Utc>) -> RollingSum {
+        let start = now - window.min(self.retention);
+        let mut services: HashMap<String, f64> = HashMap::new();
+        let mut buckets = 0;
+        for costs in self.buckets.range(start..=now).map(|(_, costs)| costs) {
+            buckets += 1;
+            for (service, cost) in costs {
+                *services.entry(service.clone()).or_insert(0.0) += cost;
+            }
+        }
+        RollingSum {
+            window_seconds: window.as_secs(),
+            total_cost: services.values().sum(),
+            services,
+            buckets,
+        }
+    }
+}
+
+/// Parse a window such as `90m`, `24h` or `7d`
+pub fn parse_window(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let unit = value.chars().last()?;
+    let amount = &value[..value.len() - unit.len_utf8()];
+    let amount: u64 = amount.parse().ok().filter(|amount| *amount > 0)?;
+    let unit_secs = match unit {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount.checked_mul(unit_secs)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(begin: &str, cost: f64) -> TimeBucket {
+        TimeBucket {
+            begin: begin.to_string(),
+            end: begin.to_string(),
+            total_cost: cost,
+            services: HashMap::from([("compute".to_string(), cost)]),
+        }
+    }
+
+    #[test]
+    fn test_rolling_sum_excludes_expired_buckets() {
+        let now: DateTime<Utc> = "2025-01-03T12:00:00Z".parse().unwrap();
+        let mut rolling = RollingCosts::new(Duration::from_secs(48 * 3600));
+        rolling.record(&[
+            bucket("2025-01-01T06:00:00+00:00", 100.0),
+            bucket("2025-01-02T11:00:00+00:00", 1.0),
+            bucket("2025-01-02T13:00:00+00:00", 2.0),
+            bucket("2025-01-03T11:00:00+00:00", 4.0),
+        ], now);
+
+        let day = rolling.sum(Duration::from_secs(24 * 3600), now);
+        assert_eq!(day.total_cost, 6.0);
+        assert_eq!(day.buckets, 2);
+
+        // Re-fetching a window replaces it, and the 2025-01-01 bucket is past retention
+        rolling.record(&[bucket("2025-01-03T11:00:00+00:00", 5.0)], now);
+        let all = rolling.sum(Duration::from_secs(72 * 3600), now);
+        assert_eq!(all.total_cost, 8.0);
+        assert_eq!(all.services["compute"], 8.0);
+    }
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("24h"), Some(Duration::from_secs(86_400)));
+        assert_eq!(parse_window("90m"), Some(Duration::from_secs(5_400)));
+        assert_eq!(parse_window("7d"), Some(Duration::from_secs(604_800)));
+        assert_eq!(parse_window("0h"), None);
+        assert_eq!(parse_window("1w"), None);
+        assert_eq!(parse_window(""), None);
+        assert_eq!(parse_window("1é"), None);
+    }
+}
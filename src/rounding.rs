@@ -0,0 +1,84 @@
+//! Cost rounding rules for the OpenStack Cost Dashboard
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How costs are rounded to the configured precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    /// Round to nearest, ties away from zero
+    #[default]
+    HalfUp,
+    /// Round to nearest, ties to the even digit (banker's rounding)
+    HalfEven,
+    /// Round towards negative infinity
+    Floor,
+    /// Round towards positive infinity
+    Ceil,
+}
+
+impl RoundingMode {
+    /// Round a value to `precision` decimal places
+    pub fn round(self, value: f64, precision: u32) -> f64 {
+        let factor = 10_f64.powi(precision as i32);
+        // Snap away binary noise so 1.005 is treated as the decimal tie it was written as
+        let scaled = (value * factor * 1e9).round() / 1e9;
+        let rounded = match self {
+            RoundingMode::HalfUp => scaled.round(),
+            RoundingMode::HalfEven => scaled.round_ties_even(),
+            RoundingMode::Floor => scaled.floor(),
+            RoundingMode::Ceil => scaled.ceil(),
+        };
+        rounded / factor
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "half_up" => Ok(RoundingMode::HalfUp),
+            "half_even" => Ok(RoundingMode::HalfEven),
+            "floor" => Ok(RoundingMode::Floor),
+            "ceil" => Ok(RoundingMode::Ceil),
+            other => Err(format!("unknown rounding mode '{}' (expected half_up, half_even, floor or ceil)", other)),
+        }
+    }
+}
+
+impl fmt::Display for RoundingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RoundingMode::HalfUp => "half_up",
+            RoundingMode::HalfEven => "half_even",
+            RoundingMode::Floor => "floor",
+            RoundingMode::Ceil => "ceil",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounding_modes_on_a_decimal_tie() {
+        assert_eq!(RoundingMode::HalfUp.round(1.005, 2), 1.01);
+        assert_eq!(RoundingMode::HalfEven.round(1.005, 2), 1.0);
+        assert_eq!(RoundingMode::Floor.round(1.005, 2), 1.0);
+        assert_eq!(RoundingMode::Ceil.round(1.005, 2), 1.01);
+
+        assert_eq!(RoundingMode::HalfEven.round(1.015, 2), 1.02);
+        assert_eq!(RoundingMode::Floor.round(1.019, 2), 1.01);
+        assert_eq!(RoundingMode::Ceil.round(1.011, 2), 1.02);
+    }
+
+    #[test]
+    fn test_rounding_mode_parsing() {
+        assert_eq!("FLOOR".parse::<RoundingMode>(), Ok(RoundingMode::Floor));
+        assert_eq!("half_even".parse::<RoundingMode>(), Ok(RoundingMode::HalfEven));
+        assert!("nearest".parse::<RoundingMode>().is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! Cron schedule for the background refresh
+
+use std::str::FromStr;
+use std::time::Duration;
+use chrono::{DateTime, Local, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+
+/// Clock times the background refresh fires at, from `REFRESH_CRON`
+///
+/// Fire times are evaluated in the display timezone (server local time when
+/// unset), so a daily schedule keeps its wall-clock time across DST changes.
+#[derive(Debug, Clone)]
+pub struct RefreshSchedule {
+    schedule: Schedule,
+    timezone: Option<Tz>,
+}
+
+impl RefreshSchedule {
+    /// Parse a cron expression with 5 fields (minute precision) or 6-7 fields (with seconds)
+    pub fn parse(expression: &str, timezone: Option<Tz>) -> Result<Self, cron::error::Error> {
+        let expression = expression.trim();
+        let schedule = if expression.split_whitespace().count() == 5 {
+            Schedule::from_str(&format!("0 {}", expression))?
+        } else {
+            Schedule::from_str(expression)?
+        };
+        Ok(Self { schedule, timezone })
+    }
+
+    /// First fire time strictly after `now`
+    pub fn next_after(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.timezone {
+            Some(tz) => self.schedule.after(&now.with_timezone(&tz)).next().map(|next| next.with_timezone(&Utc)),
+            None => self.schedule.after(&now.with_timezone(&Local)).next().map(|next| next.with_timezone(&Utc)),
+        }
+    }
+
+    /// Time to wait from `now` until the next fire time
+    pub fn delay_after(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.next_after(now).and_then(|next| (next - now).to_std().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daily_schedule_keeps_wall_clock_time_across_dst() {
+        let schedule = RefreshSchedule::parse("30 6 * * *", Some(chrono_tz::Europe::Paris)).unwrap();
+        let mut now: DateTime<Utc> = "2025-03-28T12:00:00Z".parse().unwrap();
+        let mut fires = Vec::new();
+        for _ in 0..3 {
+            now = schedule.next_after(now).unwrap();
+            fires.push(now.to_rfc3339());
+        }
+        // Paris switches from UTC+1 to UTC+2 on 2025-03-30
+        assert_eq!(fires, vec![
+            "2025-03-29T05:30:00+00:00",
+            "2025-03-30T04:30:00+00:00",
+            "2025-03-31T04:30:00+00:00",
+        ]);
+
+        let start: DateTime<Utc> = "2025-03-31T04:00:00Z".parse().unwrap();
+        assert_eq!(schedule.delay_after(start), Some(Duration::from_secs(30 * 60)));
+    }
+
+    #[test]
+    fn test_six_field_expressions_and_errors() {
+        let schedule = RefreshSchedule::parse("15 */20 * * * *", Some(chrono_tz::UTC)).unwrap();
+        let now: DateTime<Utc> = "2025-01-01T10:05:00Z".parse().unwrap();
+        assert_eq!(schedule.next_after(now).unwrap().to_rfc3339(), "2025-01-01T10:20:15+00:00");
+
+        assert!(RefreshSchedule::parse("every day", None).is_err());
+    }
+}
@@ -1,26 +1,348 @@
 //! Server management for the OpenStack Cost Dashboard
 
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 use axum::{
-    routing::get,
+    middleware::from_fn_with_state,
+    routing::{get, post, MethodRouter},
     Router,
 };
+use chrono::{Local, Utc};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
-use tracing::info;
+use tokio::sync::{watch, Semaphore};
+use tower_http::services::ServeDir;
+use tracing::{debug, info, error, warn};
 
 use crate::config::Config;
-use crate::handlers::{serve_index, get_chart_data, refresh_data, health_check, app_info};
+use crate::handlers::{serve_index, get_chart_data, get_chartjs, get_chart_png, get_data_by_category, get_data_by_domain, get_data_grouped, get_data_by_endpoint, get_currencies, refresh_data, query_data, get_timeseries, get_history, get_rolling, get_sparklines, get_efficiency, get_variance, get_burndown, health_check, readiness_check, app_info, metrics,
+    grafana_test, grafana_search, grafana_query,
+    list_clouds, set_active_cloud, last_output, debug_command, cache_stats, range_stats, clear_cache, route_not_found, method_not_allowed, INDEX_TEMPLATE};
+use crate::history::HistoryPoint;
+use crate::sink;
+use crate::middleware::{access_log, add_security_headers, require_api_token, require_full_access, security_headers, server_timing, track_activity, Activity};
+use crate::models::ChartData;
+use crate::schedule::RefreshSchedule;
 use crate::AppState;
 
+/// Markers the dashboard template must contain for the frontend to work
+pub const REQUIRED_TEMPLATE_MARKERS: &[&str] = &[
+    "{{DASHBOARD_TITLE}}",
+    "{{DASHBOARD_LOGO}}",
+    "id=\"lastUpdated\"",
+    "id=\"statusBanner\"",
+    "id=\"totalCost\"",
+    "id=\"serviceCount\"",
+    "id=\"avgCost\"",
+    "id=\"beginAt\"",
+    "id=\"endAt\"",
+    "id=\"refreshBtn\"",
+    "id=\"chartTypeBtn\"",
+    "id=\"costChart\"",
+    "id=\"topServicesChart\"",
+    "id=\"tableContainer\"",
+];
+
+/// Return the required markers that are missing from a template
+pub fn missing_template_markers(template: &str) -> Vec<&'static str> {
+    REQUIRED_TEMPLATE_MARKERS
+        .iter()
+        .copied()
+        .filter(|marker| !template.contains(marker))
+        .collect()
+}
+
+/// Build the Tokio runtime the application runs on
+///
+/// A single worker runs the server and the background refresh on the current
+/// thread; blocking work such as file I/O still goes to the blocking pool.
+pub fn build_runtime(config: &Config) -> std::io::Result<tokio::runtime::Runtime> {
+    let mut builder = if config.worker_threads == 1 {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.worker_threads(config.worker_threads);
+        builder
+    };
+    builder.enable_all().build()
+}
+
+/// Build the connection builder for the configured protocol and keep-alive settings
+///
+/// HTTP/2 is offered over cleartext with prior knowledge (h2c); browsers only
+/// negotiate HTTP/2 over TLS, so this mainly benefits API clients and proxies.
+fn connection_builder(http2_enabled: bool, keep_alive_timeout: Option<Duration>) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+    if let Some(timeout) = keep_alive_timeout {
+        builder.http1().timer(TokioTimer::new()).header_read_timeout(timeout);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(timeout)
+            .keep_alive_timeout(timeout);
+    }
+    if http2_enabled {
+        builder
+    } else {
+        builder.http1_only()
+    }
+}
+
+/// Pick the random delay added to a refresh tick, in `0..=jitter`
+fn jitter_offset(jitter: Duration, rng: &mut impl Rng) -> Duration {
+    if jitter.is_zero() {
+        Duration::ZERO
+    } else {
+        Duration::from_millis(rng.gen_range(0..=jitter.as_millis() as u64))
+    }
+}
+
+/// Resolve once shutdown is requested, or never if the sender is dropped without requesting it
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|stop| *stop).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Request shutdown once no request was handled for `window`
+fn shutdown_when_idle(activity: Arc<Activity>, window: Duration) -> watch::Receiver<bool> {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        loop {
+            let idle = activity.idle_for();
+            if idle >= window {
+                break;
+            }
+            tokio::time::sleep(window - idle).await;
+        }
+        info!("No requests for {:?}, shutting down", window);
+        shutdown_tx.send_replace(true);
+    });
+    shutdown_rx
+}
+
+/// Response written to connections accepted over the `MAX_CONNECTIONS` limit before closing them
+const OVER_CAPACITY_RESPONSE: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+
+/// Accept connections and serve the router on each of them until shutdown is requested
+///
+/// Each connection holds one of the `connections` permits until it closes, idle
+/// keep-alive time included; connections accepted while none is left get a `503`
+/// and are closed.
+async fn serve(
+    listener: TcpListener,
+    app: Router,
+    config: &Config,
+    connections: Arc<Semaphore>,
+    mut shutdown: watch::Receiver<bool>,
+) -> std::io::Result<()> {
+    let builder = connection_builder(config.http2_enabled, config.keep_alive_timeout);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown_requested(&mut shutdown) => return Ok(()),
+        };
+        let (stream, remote) = match accepted {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                // Back off so errors like running out of file descriptors don't spin the loop
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            warn!("Connection limit reached, rejecting connection from {}", remote);
+            tokio::spawn(async move {
+                let mut stream = stream;
+                stream.write_all(OVER_CAPACITY_RESPONSE).await.ok();
+                stream.shutdown().await.ok();
+            });
+            continue;
+        };
+        let service = TowerToHyperService::new(app.clone());
+        let builder = builder.clone();
+        tokio::spawn(async move {
+            if let Err(e) = builder.serve_connection_with_upgrades(TokioIo::new(stream), service).await {
+                debug!("Connection from {} closed with error: {}", remote, e);
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// Swap a background refresh into the shared chart data if it changed enough
+///
+/// The check time is recorded either way; returns whether the chart was replaced.
+async fn publish_refresh(state: &AppState, mut new_chart_data: ChartData) -> bool {
+    *state.last_checked.write().await = Some(new_chart_data.last_updated.clone());
+    let mut chart_data = state.chart_data.write().await;
+    if !state.data_service.changed_significantly(&new_chart_data, &chart_data) {
+        debug!("Costs changed by less than MIN_CHANGE_PERCENT, keeping the current chart data");
+        return false;
+    }
+    state.data_service.apply_deltas(&mut new_chart_data, &chart_data);
+    *chart_data = new_chart_data;
+    true
+}
+
+/// Fetch and process the current range, then record and publish the result
+///
+/// Returns whether the fetch succeeded with fresh data.
+async fn refresh_once(state: &AppState) -> bool {
+    state.refresh_count.fetch_add(1, Ordering::Relaxed);
+    if state.config().read_only_replica {
+        // Pick up what the primary persisted since; the fetch below then only reads the cache
+        state.data_service.cache().load().await;
+    }
+    
+    let new_data = state.data_service.fetch_data(None, None).await;
+    let failed = new_data.error.is_some() || new_data.stale;
+    state.data_service.alert_new_services(&new_data).await;
+    let range = new_data.range.clone();
+    let new_chart_data = state.data_service.process_outcome(new_data);
+    // A failed fetch has no costs to record; a zero snapshot would skew the trends
+    if !failed {
+        let point = HistoryPoint::from_chart(Local::now().to_rfc3339(), &new_chart_data)
+            .with_cache_lookups(state.data_service.cache().lookups());
+        state.history.write().await.record(point).await;
+        if let Some(sink) = &state.sink {
+            sink::publish(sink, range.as_ref(), &new_chart_data).await;
+        }
+    }
+    publish_refresh(state, new_chart_data).await;
+    !failed
+}
+
+/// Settings that only take effect when the process starts
+///
+/// The listeners, routes and middleware, the background refresh schedule, the
+/// rendered dashboard page and the stores opened at startup keep their settings.
+fn restart_only_changes(old: &Config, new: &Config) -> Vec<&'static str> {
+    [
+        ("BIND_ADDRESS", old.bind_address != new.bind_address),
+        ("PORT", old.port != new.port),
+        ("ADMIN_BIND_ADDRESS", old.admin_bind_address != new.admin_bind_address),
+        ("ADMIN_PORT", old.admin_port != new.admin_port),
+        ("TOKIO_WORKER_THREADS", old.worker_threads != new.worker_threads),
+        ("MAX_CONCURRENT_FETCHES", old.max_concurrent_fetches != new.max_concurrent_fetches),
+        ("HTTP2_ENABLED", old.http2_enabled != new.http2_enabled),
+        ("KEEP_ALIVE_TIMEOUT_SECONDS", old.keep_alive_timeout != new.keep_alive_timeout),
+        ("MAX_CONNECTIONS", old.max_connections != new.max_connections),
+        ("API_TOKEN", old.api_token != new.api_token),
+        ("METRICS_ENABLED", old.metrics_enabled != new.metrics_enabled),
+        ("GRAFANA_ENABLED", old.grafana_enabled != new.grafana_enabled),
+        ("EFFICIENCY_ENABLED", old.efficiency_enabled != new.efficiency_enabled),
+        ("STATIC_DIR", old.static_dir != new.static_dir),
+        ("ACCESS_LOG", old.access_log != new.access_log),
+        ("ACCESS_LOG_REDACT_PARAMS", old.access_log_redact_params != new.access_log_redact_params),
+        ("SERVER_TIMING", old.server_timing != new.server_timing),
+        ("IDLE_SHUTDOWN_SECONDS", old.idle_shutdown != new.idle_shutdown),
+        ("CSP_POLICY", old.csp_policy != new.csp_policy),
+        ("X_FRAME_OPTIONS", old.frame_options != new.frame_options),
+        ("REFRESH_INTERVAL_SECONDS", old.refresh_interval != new.refresh_interval),
+        ("REFRESH_JITTER_SECONDS", old.refresh_jitter != new.refresh_jitter),
+        ("REFRESH_CRON", old.refresh_cron != new.refresh_cron),
+        ("REFRESH_BACKOFF_THRESHOLD", old.refresh_backoff_threshold != new.refresh_backoff_threshold),
+        ("REFRESH_BACKOFF_MAX_SECONDS", old.refresh_backoff_max != new.refresh_backoff_max),
+        ("DASHBOARD_TITLE", old.dashboard_title != new.dashboard_title),
+        ("DASHBOARD_LOGO_URL", old.dashboard_logo_url != new.dashboard_logo_url),
+        ("CHART_PALETTE", old.chart_palette != new.chart_palette),
+        ("ASSET_VERSION", old.asset_version != new.asset_version),
+        ("CACHE_FILE", old.cache_file != new.cache_file),
+        ("READ_ONLY_REPLICA", old.read_only_replica != new.read_only_replica),
+        ("HISTORY_FILE", old.history_file != new.history_file),
+        ("HISTORY_MAX_POINTS", old.history_max_points != new.history_max_points),
+        ("BASELINE_FILE", old.baseline_file != new.baseline_file),
+        ("SINK", old.sink != new.sink || old.sink_url != new.sink_url),
+        ("ROLLING_RETENTION_HOURS", old.rolling_retention != new.rolling_retention),
+        ("CACHE_PROCESSED_CHART", old.cache_processed_chart != new.cache_processed_chart),
+        ("DAILY_AGGREGATES", old.daily_aggregates != new.daily_aggregates),
+        ("RANGE_STATS_MAX", old.range_stats_max != new.range_stats_max),
+    ]
+    .into_iter()
+    .filter_map(|(name, changed)| changed.then_some(name))
+    .collect()
+}
+
+/// Read the configuration again, values from `.env` winning over the environment inherited at startup
+///
+/// `.env` is read into a map rather than into the process environment, which is
+/// not safe to change while other threads run.
+fn load_config() -> Config {
+    let dotenv: HashMap<String, String> = dotenvy::dotenv_iter()
+        .map(|entries| entries.filter_map(Result::ok).collect())
+        .unwrap_or_default();
+    Config::from_source(|name| dotenv.get(name).cloned().or_else(|| std::env::var(name).ok()))
+}
+
+/// Apply a reloaded configuration and refresh with it, returning whether it was applied
+///
+/// Processing settings (currency rate, filters, grouping, token scopes, ...) apply
+/// right away; a configuration that fails validation is ignored.
+async fn reload_config(state: &AppState, config: Config) -> bool {
+    if let Err(e) = config.validate() {
+        error!("Keeping the current configuration: {}", e);
+        return false;
+    }
+    let ignored = restart_only_changes(&state.config(), &config);
+    if !ignored.is_empty() {
+        warn!("Ignoring changes to {} until the next restart", ignored.join(", "));
+    }
+    state.data_service.reload(config);
+    if refresh_once(state).await {
+        info!("Refreshed with the reloaded configuration");
+    } else {
+        warn!("Refresh with the reloaded configuration failed");
+    }
+    true
+}
+
+/// Reload the configuration with `load` on every `SIGHUP` (see `reload_config`)
+#[cfg(unix)]
+fn watch_sighup(state: AppState, load: impl Fn() -> Config + Send + 'static) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            info!("SIGHUP received, reloading the configuration");
+            reload_config(&state, load()).await;
+        }
+    });
+    Ok(())
+}
+
 /// Server struct managing the web server and background tasks
 pub struct Server {
     config: Config,
     app_state: AppState,
+    /// Request activity watched for `IDLE_SHUTDOWN_SECONDS`
+    activity: Arc<Activity>,
+    /// Open connections allowed by `MAX_CONNECTIONS`, shared by the public and admin listeners
+    connections: Arc<Semaphore>,
 }
 
 impl Server {
     /// Create a new server instance
     pub fn new(config: Config, app_state: AppState) -> Self {
+        let missing = missing_template_markers(INDEX_TEMPLATE);
+        if !missing.is_empty() {
+            error!("Dashboard template is missing required markers: {}", missing.join(", "));
+            error!("The dashboard will not render correctly until templates/index.html is fixed");
+        }
+
         Self {
+            activity: Arc::new(Activity::new()),
+            connections: Arc::new(Semaphore::new(config.max_connections)),
             config,
             app_state,
         }
@@ -30,46 +352,855 @@ impl Server {
     pub async fn start(self) -> Result<(), Box<dyn std::error::Error>> {
         // Start background refresh task
         self.start_background_refresh().await;
+        #[cfg(unix)]
+        watch_sighup(self.app_state.clone(), load_config)?;
         
-        // Build router
-        let app = self.build_router();
+        // Build routers
+        let (app, admin_app) = self.build_routers();
         
         // Start server
         let listener = TcpListener::bind(self.config.server_address()).await?;
         info!("Server running on {}", self.config.public_url());
         
-        axum::serve(listener, app).await?;
+        // Without an idle window the sender is dropped and shutdown is never requested
+        let shutdown = match self.config.idle_shutdown {
+            Some(window) => shutdown_when_idle(self.activity.clone(), window),
+            None => watch::channel(false).1,
+        };
+        match (admin_app, self.config.admin_address()) {
+            (Some(admin_app), Some(admin_address)) => {
+                let admin_listener = TcpListener::bind(&admin_address).await?;
+                info!("Admin endpoints running on {}", admin_address);
+                let (public, admin) = tokio::join!(
+                    serve(listener, app, &self.config, self.connections.clone(), shutdown.clone()),
+                    serve(admin_listener, admin_app, &self.config, self.connections.clone(), shutdown),
+                );
+                public?;
+                admin?;
+            }
+            _ => serve(listener, app, &self.config, self.connections.clone(), shutdown).await?,
+        }
         
         Ok(())
     }
 
+    /// Build the public router and, when an admin port is configured, the admin router
+    ///
+    /// Without an admin port every route is served by the public router.
+    fn build_routers(&self) -> (Router, Option<Router>) {
+        if self.config.admin_port.is_some() {
+            let public = self.with_layers(self.public_routes());
+            let admin = self.with_layers(self.admin_routes());
+            (public, Some(admin))
+        } else {
+            (self.with_layers(self.public_routes().merge(self.admin_routes())), None)
+        }
+    }
+
     /// Build the Axum router with all routes
+    #[cfg(test)]
     fn build_router(&self) -> Router {
-        Router::new()
+        self.build_routers().0
+    }
+
+    /// User-facing dashboard and data routes
+    fn public_routes(&self) -> Router<AppState> {
+        let routes = Router::new()
             .route("/", get(serve_index))
             .route("/api/data", get(get_chart_data))
+            .route("/api/chartjs", get(get_chartjs))
+            .route("/api/chart.png", get(get_chart_png))
+            .route("/api/data/by-category", self.full_access(get(get_data_by_category)))
+            .route("/api/data/grouped", get(get_data_grouped))
+            .route("/api/data/currencies", get(get_currencies))
+            .route("/api/data/by-domain", self.full_access(get(get_data_by_domain)))
+            .route("/api/data/by-endpoint", self.full_access(get(get_data_by_endpoint)))
             .route("/api/refresh", get(refresh_data))
+            .route("/api/query", get(query_data))
+            .route("/api/timeseries", self.full_access(get(get_timeseries)))
+            .route("/api/history", self.full_access(get(get_history)))
+            .route("/api/rolling", self.full_access(get(get_rolling)))
+            .route("/api/sparklines", self.full_access(get(get_sparklines)))
+            .route("/api/variance", self.full_access(get(get_variance)))
+            .route("/api/burndown", self.full_access(get(get_burndown)))
+            .route("/api/clouds", get(list_clouds))
+            .route("/api/clouds/active", self.protected(post(set_active_cloud)))
+            .route("/api/debug/last-output", self.protected(get(last_output)))
+            .route("/api/debug/command", self.protected(get(debug_command)))
             .route("/api/health", get(health_check))
-            .route("/api/info", get(app_info))
-            .with_state(self.app_state.clone())
+            .route("/api/ready", get(readiness_check))
+            .route("/api/info", get(app_info));
+        let routes = if self.config.efficiency_enabled {
+            routes.route("/api/efficiency", self.full_access(get(get_efficiency)))
+        } else {
+            routes
+        };
+        // The datasource test hits both forms depending on how the URL was entered
+        let routes = if self.config.grafana_enabled {
+            routes
+                .route("/grafana", get(grafana_test))
+                .route("/grafana/", get(grafana_test))
+                .route("/grafana/search", post(grafana_search))
+                .route("/grafana/query", post(grafana_query))
+        } else {
+            routes
+        };
+        // Nested under its own prefix so assets can never shadow `/api/*`
+        match &self.config.static_dir {
+            Some(dir) => routes.nest_service("/static", ServeDir::new(dir)),
+            None => routes,
+        }
+    }
+
+    /// Require the API token on a route
+    fn protected(&self, route: MethodRouter<AppState>) -> MethodRouter<AppState> {
+        route.route_layer(from_fn_with_state(
+            self.config.api_token.as_deref().map(Into::into),
+            require_api_token,
+        ))
+    }
+
+    /// Keep `TOKEN_SCOPES` tokens off a route whose data is not narrowed to their scope
+    fn full_access(&self, route: MethodRouter<AppState>) -> MethodRouter<AppState> {
+        route.route_layer(from_fn_with_state(self.app_state.clone(), require_full_access))
+    }
+
+    /// Operational routes that may be isolated on the admin port
+    fn admin_routes(&self) -> Router<AppState> {
+        let mut router = Router::new()
+            .route("/api/cache/stats", get(cache_stats))
+            .route("/api/stats/ranges", get(range_stats))
+            .route("/api/admin/cache/clear", self.protected(post(clear_cache)));
+        if self.config.metrics_enabled {
+            router = router.route("/metrics", self.full_access(get(metrics)));
+        }
+        router
+    }
+
+    /// Apply the shared middleware stack and state to a set of routes
+    fn with_layers(&self, routes: Router<AppState>) -> Router {
+        // Added last so it covers every route registered above
+        let routes = routes
+            .fallback(route_not_found)
+            .method_not_allowed_fallback(method_not_allowed)
+            .layer(from_fn_with_state(security_headers(&self.config), add_security_headers));
+        // The background refresh never goes through here, so it doesn't count as activity
+        let routes = if self.config.idle_shutdown.is_some() {
+            routes.layer(from_fn_with_state(self.activity.clone(), track_activity))
+        } else {
+            routes
+        };
+        let routes = if self.config.server_timing {
+            routes.layer(axum::middleware::from_fn(server_timing))
+        } else {
+            routes
+        };
+        let routes = if self.config.access_log {
+            routes.layer(from_fn_with_state(
+                Arc::new(self.config.access_log_redact_params.clone()),
+                access_log,
+            ))
+        } else {
+            routes
+        };
+        routes.with_state(self.app_state.clone())
     }
 
     /// Start the background task for automatic data refresh
+    ///
+    /// The wait between refreshes grows while OpenStack keeps failing (see
+    /// `RefreshBackoff`) and returns to `refresh_interval` after a success.
     async fn start_background_refresh(&self) {
         let bg_state = self.app_state.clone();
-        let refresh_interval = self.config.refresh_interval;
+        let refresh_jitter = self.config.refresh_jitter;
+        // Invalid expressions were rejected by `Config::validate` at startup
+        let schedule = self.config.refresh_cron.as_deref()
+            .and_then(|expression| RefreshSchedule::parse(expression, self.config.display_timezone).ok());
         
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(refresh_interval);
+            // Seeded per process so replicas sharing an interval drift apart
+            let mut rng = StdRng::from_entropy();
             loop {
-                interval.tick().await;
                 info!("Background refresh triggered");
+                let failed = !refresh_once(&bg_state).await;
+                let mut interval = bg_state.refresh_backoff.record(!failed);
+                // On a schedule, failures are still retried at the backoff interval if that comes sooner
+                if let Some(next) = schedule.as_ref().and_then(|schedule| schedule.delay_after(Utc::now())) {
+                    interval = if failed { interval.min(next) } else { next };
+                }
+                if failed {
+                    warn!(
+                        "Background refresh failed ({} in a row), next attempt in {:?}",
+                        bg_state.refresh_backoff.consecutive_failures(),
+                        interval
+                    );
+                } else {
+                    info!("Background refresh completed successfully");
+                }
                 
-                let new_data = bg_state.data_service.fetch_data(None, None).await;
-                let new_chart_data = bg_state.data_service.process_data(new_data);
-                *bg_state.chart_data.write().await = new_chart_data;
-                info!("Background refresh completed successfully");
+                let offset = jitter_offset(refresh_jitter, &mut rng);
+                if !offset.is_zero() {
+                    debug!("Delaying background refresh by {:?}", offset);
+                }
+                tokio::time::sleep(interval + offset).await;
             }
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+    use regex::Regex;
+    use crate::test_support::mock_command;
+
+    /// Read a response body as JSON
+    async fn json_body(response: axum::response::Response) -> serde_json::Value {
+        use http_body_util::BodyExt;
+
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    /// Assert that a body is an error envelope with the given code
+    fn assert_error_envelope(body: &serde_json::Value, code: &str) {
+        let error = &body["error"];
+        assert_eq!(error["code"], code);
+        assert!(!error["message"].as_str().unwrap().is_empty());
+        let request_id = error["request_id"].as_str().unwrap();
+        assert_eq!(request_id.len(), 16);
+        assert!(request_id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    /// CLI output rating compute at 5
+    const COMPUTE_AT_5: &str = r#"echo '[{"Resources": [{"service": "compute", "rating": "5"}]}]'"#;
+
+    /// Build the router for a state configured with the given variables
+    fn router_with(vars: &[(&str, &str)]) -> Router {
+        let state = AppState::from_pairs(vars);
+        Server::new(Config::clone(&state.config()), state).build_router()
+    }
+
+    #[test]
+    fn test_bundled_template_has_all_markers() {
+        assert!(missing_template_markers(INDEX_TEMPLATE).is_empty());
+    }
+
+    #[test]
+    fn test_missing_template_marker_is_reported() {
+        let template = INDEX_TEMPLATE.replace("id=\"costChart\"", "id=\"renamedChart\"");
+        assert_eq!(missing_template_markers(&template), vec!["id=\"costChart\""]);
+    }
+
+    #[test]
+    fn test_refresh_jitter_varies_tick_intervals_within_bound() {
+        let interval = Duration::from_secs(300);
+        let jitter = Duration::from_secs(30);
+        let mut rng = StdRng::seed_from_u64(7);
+        let ticks: Vec<Duration> = (0..50)
+            .map(|n| interval * n + jitter_offset(jitter, &mut rng))
+            .collect();
+        let gaps: Vec<Duration> = ticks.windows(2).map(|pair| pair[1] - pair[0]).collect();
+
+        assert!(gaps.iter().all(|gap| *gap >= interval - jitter && *gap <= interval + jitter));
+        assert!(gaps.iter().any(|gap| *gap != gaps[0]), "jittered intervals should vary");
+        assert_eq!(jitter_offset(Duration::ZERO, &mut rng), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_index_response_has_security_headers() {
+        let response = router_with(&[("CSP_POLICY", "default-src 'self'")])
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let headers = response.headers();
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert_eq!(headers["x-frame-options"], "DENY");
+        assert_eq!(headers["content-security-policy"], "default-src 'self'");
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_move_to_admin_port() {
+        let state = AppState::from_pairs(&[("ADMIN_PORT", "9090")]);
+        let (public, admin) = Server::new(Config::clone(&state.config()), state).build_routers();
+        let admin = admin.expect("admin router should be built when ADMIN_PORT is set");
+
+        let response = public.clone().oneshot(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        let response = public.oneshot(Request::get("/api/health").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let response = admin.clone().oneshot(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let response = admin.oneshot(Request::get("/api/cache/stats").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_all_routes_on_main_port_without_admin_port() {
+        let response = router_with(&[])
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_http2_request_succeeds_when_enabled() {
+        use http_body_util::Empty;
+        use axum::body::Bytes;
+
+        let state = AppState::from_pairs(&[("HTTP2_ENABLED", "true"), ("KEEP_ALIVE_TIMEOUT_SECONDS", "30")]);
+        let config = Config::clone(&state.config());
+        let app = Server::new(config.clone(), state).build_router();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { serve(listener, app, &config, Arc::new(Semaphore::new(config.max_connections)), watch::channel(false).1).await });
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+
+        let request = Request::get(format!("http://{}/api/health", address))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.version(), axum::http::Version::HTTP_2);
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_idle_connections_count_against_the_limit() {
+        use tokio::io::AsyncReadExt;
+
+        let state = AppState::from_pairs(&[("MAX_CONNECTIONS", "1")]);
+        let config = Config::clone(&state.config());
+        let server = Server::new(config.clone(), state);
+        let app = server.build_router();
+        let connections = server.connections.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { serve(listener, app, &config, connections, watch::channel(false).1).await });
+
+        // An idle keep-alive connection holds the only permit
+        let idle = tokio::net::TcpStream::connect(address).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut rejected = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 "), "{}", response);
+
+        drop(idle);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.connections.available_permits() == 0 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("the closed connection kept its permit");
+    }
+
+    #[test]
+    fn test_app_serves_on_a_single_worker_runtime() {
+        use http_body_util::Empty;
+        use axum::body::Bytes;
+
+        let state = AppState::from_pairs(&[("TOKIO_WORKER_THREADS", "1"), ("OPENSTACK_COMMAND", "/nonexistent/openstack")]);
+        let config = Config::clone(&state.config());
+        let runtime = build_runtime(&config).unwrap();
+        assert_eq!(runtime.handle().runtime_flavor(), tokio::runtime::RuntimeFlavor::CurrentThread);
+
+        runtime.block_on(async move {
+            let server = Server::new(config.clone(), state.clone());
+            server.start_background_refresh().await;
+            let app = server.build_router();
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move { serve(listener, app, &config, Arc::new(Semaphore::new(config.max_connections)), watch::channel(false).1).await });
+
+            let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await.unwrap();
+            tokio::spawn(connection);
+            let request = Request::get(format!("http://{}/api/health", address))
+                .header("host", address.to_string())
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+            let response = sender.send_request(request).await.unwrap();
+            assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+            // The background refresh gets to run alongside the server
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while state.last_checked.read().await.is_none() {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            })
+            .await
+            .expect("background refresh did not run");
+        });
+    }
+
+    #[tokio::test]
+    async fn test_server_shuts_down_only_after_inactivity() {
+        let state = AppState::from_pairs(&[("IDLE_SHUTDOWN_SECONDS", "1")]);
+        let config = Config::clone(&state.config());
+        let server = Server::new(config.clone(), state);
+        let app = server.build_router();
+        // A shorter window than the configured one keeps the test fast
+        let shutdown = shutdown_when_idle(server.activity.clone(), Duration::from_millis(300));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let serving = tokio::spawn(async move { serve(listener, app, &config, Arc::new(Semaphore::new(config.max_connections)), shutdown).await });
+        let app = server.build_router();
+        let get = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+
+        for _ in 0..6 {
+            app.clone().oneshot(get("/api/clouds")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(!serving.is_finished(), "requests keep the server up");
+
+        // Health probes alone don't hold off the shutdown
+        for _ in 0..3 {
+            app.clone().oneshot(get("/api/health")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let result = tokio::time::timeout(Duration::from_secs(2), serving).await;
+        assert!(result.expect("server did not shut down").unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_small_changes_keep_the_published_chart() {
+        let state = AppState::from_pairs(&[("CURRENCY_RATE", "1"), ("MIN_CHANGE_PERCENT", "1")]);
+        let chart = |compute: f64, updated: &str| {
+            let mut rating = crate::models::RatingData::default();
+            rating.costs.insert("compute".to_string(), compute);
+            let mut chart = state.data_service.process_data(&rating);
+            chart.last_updated = updated.to_string();
+            chart
+        };
+        *state.chart_data.write().await = chart(100.0, "first");
+
+        assert!(!publish_refresh(&state, chart(100.5, "second")).await);
+        assert_eq!(state.chart_data.read().await.last_updated, "first");
+        assert_eq!(state.last_checked.read().await.as_deref(), Some("second"));
+
+        assert!(publish_refresh(&state, chart(102.0, "third")).await);
+        assert_eq!(state.chart_data.read().await.last_updated, "third");
+        assert_eq!(state.last_checked.read().await.as_deref(), Some("third"));
+    }
+
+    #[tokio::test]
+    async fn test_cache_clear_requires_api_token() {
+        let clear = |token: Option<&str>| {
+            let mut request = Request::post("/api/admin/cache/clear");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = router_with(&[]).oneshot(clear(None)).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
+
+        let router = router_with(&[("API_TOKEN", "secret")]);
+        let response = router.clone().oneshot(clear(None)).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+        let response = router.oneshot(clear(Some("secret"))).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_tokens_are_limited_on_every_data_route() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, COMPUTE_AT_5);
+        let router = router_with(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("API_TOKEN", "admin"),
+            ("TOKEN_SCOPES", "team-a=compute;team-b=storage"),
+        ]);
+        let get = |uri: &str, token: Option<&str>| {
+            let mut request = Request::get(uri);
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = router.clone().oneshot(get("/api/history", Some("team-a"))).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
+        assert_error_envelope(&json_body(response).await, "forbidden");
+        let response = router.clone().oneshot(get("/api/history", None)).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+        let response = router.clone().oneshot(get("/api/history", Some("admin"))).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let response = router.clone().oneshot(get("/api/refresh", Some("team-b"))).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["labels"], serde_json::json!([]));
+        assert_eq!(body["total_cost"], 0.0);
+        let response = router.oneshot(get("/api/data/currencies", Some("team-a"))).await.unwrap();
+        assert_eq!(json_body(response).await["labels"], serde_json::json!(["compute"]));
+    }
+
+    #[tokio::test]
+    async fn test_cloud_switch_requires_api_token() {
+        let switch = |token: Option<&str>| {
+            let mut request = Request::post("/api/clouds/active").header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from(r#"{"name": "unknown"}"#)).unwrap()
+        };
+
+        let router = router_with(&[("OS_CLOUDS", "east,west")]);
+        let response = router.oneshot(switch(Some("secret"))).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
+
+        let router = router_with(&[("OS_CLOUDS", "east,west"), ("API_TOKEN", "secret")]);
+        let response = router.clone().oneshot(switch(None)).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+        let response = router.clone().oneshot(switch(Some("wrong"))).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+        let response = router.oneshot(switch(Some("secret"))).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_date_range_returns_error_envelope() {
+        let response = router_with(&[])
+            .oneshot(Request::get("/api/refresh?begin_at=2099-01-01&end_at=2099-01-31").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        assert_error_envelope(&json_body(response).await, "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_routes_and_methods_return_error_envelope() {
+        let router = router_with(&[]);
+        let response = router.clone().oneshot(Request::get("/api/nope").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        assert_error_envelope(&json_body(response).await, "not_found");
+
+        let response = router.oneshot(Request::delete("/api/data").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()["allow"], "GET,HEAD");
+        assert_error_envelope(&json_body(response).await, "method_not_allowed");
+    }
+
+    #[tokio::test]
+    async fn test_date_range_is_capped_by_max_range_days() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo '[{"Resources": [{"service": "compute", "rating": 1}]}]'"#);
+        let router = router_with(&[("OPENSTACK_COMMAND", &command)]);
+
+        let response = router.clone()
+            .oneshot(Request::get("/api/refresh?begin_at=2023-01-01&end_at=2024-01-03").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        let body = json_body(response).await;
+        assert_error_envelope(&body, "invalid_request");
+        assert!(body["error"]["message"].as_str().unwrap().contains("spans 367 days"));
+
+        let response = router
+            .oneshot(Request::get("/api/refresh?begin_at=2023-01-01&end_at=2024-01-02").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_static_dir_is_served_under_static() {
+        use http_body_util::BodyExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("theme.css"), "body { color: teal; }").unwrap();
+        let router = router_with(&[("STATIC_DIR", dir.path().to_str().unwrap())]);
+
+        let response = router.clone()
+            .oneshot(Request::get("/static/theme.css").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/css");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"body { color: teal; }");
+
+        let traversal = router.clone()
+            .oneshot(Request::get("/static/../Cargo.toml").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(traversal.status(), axum::http::StatusCode::NOT_FOUND);
+
+        let api = router.oneshot(Request::get("/api/health").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(api.status(), axum::http::StatusCode::OK);
+
+        let disabled = router_with(&[])
+            .oneshot(Request::get("/static/theme.css").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(disabled.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_query_warms_the_cache_without_replacing_the_chart() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, r#"echo '[{"Resources": [{"service": "compute", "rating": "3"}]}]'"#);
+        let state = AppState::from_pairs(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        let router = Server::new(Config::clone(&state.config()), state.clone()).build_router();
+
+        let response = router
+            .oneshot(Request::get("/api/query?begin_at=2025-01-01&end_at=2025-01-31").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(json_body(response).await["total_cost"], 3.0);
+        assert_eq!(state.chart_data.read().await.total_cost, 0.0, "the shared chart is left alone");
+
+        assert_eq!(state.data_service.cache().stats().await.valid_entries, 1);
+        let refresh = state.data_service.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string())).await;
+        assert!(refresh.from_cache, "a refresh of the queried range is served from the cache");
+    }
+
+    #[tokio::test]
+    async fn test_query_response_has_server_timing() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, "echo '[]'");
+        let vars = [("OPENSTACK_COMMAND", command.as_str())];
+        let request = || Request::get("/api/query?begin_at=2025-01-01&end_at=2025-01-31").body(Body::empty()).unwrap();
+
+        let response = router_with(&vars).oneshot(request()).await.unwrap();
+        assert!(!response.headers().contains_key("server-timing"));
+
+        let response = router_with(&[vars.as_slice(), &[("SERVER_TIMING", "true")]].concat())
+            .oneshot(request())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let header = response.headers()["server-timing"].to_str().unwrap();
+        let metric = Regex::new(r"^[a-z]+;dur=\d+\.\d$").unwrap();
+        let names: Vec<&str> = header
+            .split(", ")
+            .map(|entry| {
+                assert!(metric.is_match(entry), "{}", header);
+                entry.split(';').next().unwrap()
+            })
+            .collect();
+        assert_eq!(names, ["fetch", "process", "total"]);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_failure_returns_error_envelope() {
+        let response = router_with(&[("OPENSTACK_COMMAND", "/nonexistent/openstack")])
+            .oneshot(Request::get("/api/timeseries?begin_at=2025-01-01&end_at=2025-01-31").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_GATEWAY);
+        let body = json_body(response).await;
+        assert_error_envelope(&body, "upstream_error");
+        assert!(body["error"]["message"].as_str().unwrap().contains("failed to execute OpenStack command"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_query_params_are_rejected_in_strict_mode() {
+        let request = || Request::get("/api/refresh?begin_at=2025-01-01&end_at=2025-01-31&beginAt=2025-01-02").body(Body::empty()).unwrap();
+        let vars = [("OPENSTACK_COMMAND", "/nonexistent/openstack"), ("STRICT_QUERY_PARAMS", "true")];
+
+        let response = router_with(&vars).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        let body = json_body(response).await;
+        assert_error_envelope(&body, "invalid_request");
+        assert!(body["error"]["message"].as_str().unwrap().contains("beginAt"));
+
+        // Lenient mode ignores the typo and goes on to query OpenStack
+        let response = router_with(&vars[..1]).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_negotiates_openmetrics() {
+        use http_body_util::BodyExt;
+
+        let router = router_with(&[]);
+        let request = Request::get("/metrics")
+            .header("accept", "application/openmetrics-text; version=1.0.0")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("application/openmetrics-text"));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("# UNIT openstack_cost_currency currency\n"));
+        assert!(body.ends_with("# EOF\n"));
+
+        let response = router.oneshot(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.headers()["content-type"], "text/plain; version=0.0.4");
+    }
+
+    #[tokio::test]
+    async fn test_debug_command_shows_redacted_args_without_running() {
+        let request = |token: &str| {
+            Request::get("/api/debug/command?begin_at=2025-01-01&end_at=2025-01-31")
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let vars = [
+            ("API_TOKEN", "secret"),
+            ("OPENSTACK_COMMAND", "/nonexistent/openstack"),
+            ("OS_USERNAME", "alice"),
+            ("OS_PASSWORD", "hunter2"),
+            ("OS_PROJECT_ID", "project-1"),
+        ];
+
+        let response = router_with(&vars).oneshot(request("secret")).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+
+        let router = router_with(&[vars.as_slice(), &[("DEBUG_COMMAND_ENABLED", "true")]].concat());
+        let response = router.clone().oneshot(request("wrong")).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+
+        let response = router.oneshot(request("secret")).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body[0]["program"], "/nonexistent/openstack");
+        assert!(body[0]["cloud"].is_null());
+        let args: Vec<&str> = body[0]["args"].as_array().unwrap().iter().map(|arg| arg.as_str().unwrap()).collect();
+        assert_eq!(args, [
+            "--os-username", "alice",
+            "--os-password", "[REDACTED]",
+            "--os-project-id", "[REDACTED]",
+            "--os-user-domain-name", "Default",
+            "rating", "dataframes", "get",
+            "-b", "2025-01-01T00:00:00+00:00",
+            "-e", "2025-01-31T23:59:59+00:00",
+            "-c", "Begin", "-c", "End", "-c", "Resources",
+            "-f", "json",
+        ]);
+        assert!(!body.to_string().contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn test_last_output_requires_capture_to_be_enabled() {
+        let request = || {
+            Request::get("/api/debug/last-output")
+                .header("authorization", "Bearer secret")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router_with(&[("API_TOKEN", "secret")]).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        assert_error_envelope(&json_body(response).await, "not_found");
+
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, "echo '[]'");
+
+        let state = AppState::from_pairs(&[
+            ("API_TOKEN", "secret"),
+            ("DEBUG_CAPTURE_OUTPUT", "true"),
+            ("OPENSTACK_COMMAND", &command),
+        ]);
+        state.data_service.fetch_data(Some("2025-01-01".to_string()), Some("2025-01-31".to_string())).await;
+        let router = Server::new(Config::clone(&state.config()), state).build_router();
+        let response = router.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["stdout"], "[]\n");
+        assert_eq!(body["truncated"], false);
+    }
+
+    #[tokio::test]
+    async fn test_ready_only_after_first_successful_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, COMPUTE_AT_5);
+        let state = AppState::from_pairs(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        let router = Server::new(Config::clone(&state.config()), state.clone()).build_router();
+        let ready = || Request::get("/api/ready").body(Body::empty()).unwrap();
+        let health = || Request::get("/api/health").body(Body::empty()).unwrap();
+
+        let response = router.clone().oneshot(ready()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_error_envelope(&json_body(response).await, "unavailable");
+        let response = router.clone().oneshot(health()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        // A manual refresh is enough, without waiting for the background one
+        let response = router.clone().oneshot(Request::get("/api/refresh").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let response = router.oneshot(ready()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_uptime_and_refresh_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, COMPUTE_AT_5);
+        let state = AppState::from_pairs(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        let router = Server::new(Config::clone(&state.config()), state.clone()).build_router();
+        let health = || async {
+            let response = router.clone().oneshot(Request::get("/api/health").body(Body::empty()).unwrap()).await.unwrap();
+            json_body(response).await
+        };
+
+        let first = health().await;
+        assert_eq!(first["refresh_count"], 0);
+        assert!(first["started_at"].is_string());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(refresh_once(&state).await);
+        let response = router.clone().oneshot(Request::get("/api/refresh").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let second = health().await;
+        assert_eq!(second["refresh_count"], 2);
+        assert!(second["uptime_seconds"].as_f64().unwrap() > first["uptime_seconds"].as_f64().unwrap());
+        assert_eq!(second["started_at"], first["started_at"]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_refresh_leaves_history_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, COMPUTE_AT_5);
+        let state = AppState::from_pairs(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        assert!(refresh_once(&state).await);
+        assert_eq!(state.history.read().await.points().len(), 1);
+
+        std::fs::write(&command, "#!/bin/sh\nexit 1\n").unwrap();
+        state.data_service.cache().clear().await;
+        assert!(!refresh_once(&state).await);
+        let points = state.history.read().await.points();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].total_cost, 5.0);
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_processing_settings_and_keeps_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = mock_command(&dir, COMPUTE_AT_5);
+        let state = AppState::from_pairs(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "1")]);
+        assert!(refresh_once(&state).await);
+        assert_eq!(state.chart_data.read().await.total_cost, 5.0);
+
+        let invalid = Config::from_pairs(&[
+            ("OPENSTACK_COMMAND", &command),
+            ("CURRENCY_RATE", "2"),
+            ("SSH_HOST", "bastion"),
+            ("OPENSTACK_COMMAND_FALLBACK", "openstack"),
+        ]);
+        assert!(!reload_config(&state, invalid).await);
+        assert_eq!(state.config().currency_rate, 1.0);
+
+        let reloaded = Config::from_pairs(&[("OPENSTACK_COMMAND", &command), ("CURRENCY_RATE", "2"), ("PORT", "9999")]);
+        assert!(reload_config(&state, reloaded).await);
+        assert_eq!(state.config().currency_rate, 2.0);
+        assert_eq!(state.chart_data.read().await.total_cost, 2.5);
+        assert!(state.data_service.cache().stats().await.total_entries > 0);
+    }
+}
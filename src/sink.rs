@@ -0,0 +1,172 @@
+//! Publishing of refresh results to an external sink
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::Local;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::models::ChartData;
+
+/// Where refresh results are published
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SinkKind {
+    /// Refresh results are not published
+    #[default]
+    None,
+    /// Append JSON lines to the file named by `SINK_URL`
+    File,
+    Kafka,
+    Nats,
+}
+
+impl SinkKind {
+    /// Whether this build has a publisher for the sink
+    pub fn is_available(self) -> bool {
+        matches!(self, SinkKind::None | SinkKind::File)
+    }
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(SinkKind::None),
+            "file" => Ok(SinkKind::File),
+            "kafka" => Ok(SinkKind::Kafka),
+            "nats" => Ok(SinkKind::Nats),
+            other => Err(format!("unknown sink '{}' (expected none, file, kafka or nats)", other)),
+        }
+    }
+}
+
+impl fmt::Display for SinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SinkKind::None => "none",
+            SinkKind::File => "file",
+            SinkKind::Kafka => "kafka",
+            SinkKind::Nats => "nats",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A destination for serialized refresh results
+///
+/// Implementations are called from a blocking task, one record at a time.
+pub trait Sink: Send + Sync {
+    /// Publish one record, already serialized as JSON
+    fn publish(&self, record: &str) -> std::io::Result<()>;
+}
+
+/// Sink appending one JSON document per line to a file
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    /// Create a sink appending to `path`, which is created on first publish
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl Sink for FileSink {
+    fn publish(&self, record: &str) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        // A single write keeps concurrent appends from interleaving within a line
+        file.write_all(format!("{}\n", record).as_bytes())
+    }
+}
+
+/// Open the sink selected by `SINK`, if any
+///
+/// Unavailable sinks were rejected by `Config::validate` at startup. A
+/// `READ_ONLY_REPLICA` leaves publishing to the primary.
+pub fn open_sink(config: &Config) -> Option<Arc<dyn Sink>> {
+    if config.read_only_replica {
+        return None;
+    }
+    match (config.sink, &config.sink_url) {
+        (SinkKind::File, Some(url)) => {
+            let path = url.strip_prefix("file://").unwrap_or(url);
+            Some(Arc::new(FileSink::new(path)))
+        }
+        _ => None,
+    }
+}
+
+/// A published refresh result
+#[derive(Serialize)]
+struct SinkRecord<'a> {
+    timestamp: String,
+    begin_at: Option<&'a str>,
+    end_at: Option<&'a str>,
+    chart: &'a ChartData,
+}
+
+/// Publish a successful refresh of the period `range`
+///
+/// Failures are logged and never fail the refresh itself.
+pub async fn publish(sink: &Arc<dyn Sink>, range: Option<&(String, String)>, chart: &ChartData) {
+    let record = SinkRecord {
+        timestamp: Local::now().to_rfc3339(),
+        begin_at: range.map(|(begin_at, _)| begin_at.as_str()),
+        end_at: range.map(|(_, end_at)| end_at.as_str()),
+        chart,
+    };
+    let record = match serde_json::to_string(&record) {
+        Ok(record) => record,
+        Err(e) => {
+            warn!("Failed to serialize the refresh for the sink: {}", e);
+            return;
+        }
+    };
+    let sink = sink.clone();
+    match tokio::task::spawn_blocking(move || sink.publish(&record)).await {
+        Ok(Ok(())) => debug!("Published the refresh to the sink"),
+        Ok(Err(e)) => warn!("Failed to publish the refresh to the sink: {}", e),
+        Err(e) => warn!("Sink publisher panicked: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_sink_appends_a_line_per_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refreshes.jsonl");
+        let config = Config::from_pairs(&[("SINK", "file"), ("SINK_URL", path.to_str().unwrap())]);
+        let sink = open_sink(&config).unwrap();
+        let range = ("2025-01-01T00:00:00+00:00".to_string(), "2025-01-31T23:59:59+00:00".to_string());
+        let chart = ChartData {
+            total_cost: 12.5,
+            ..ChartData::empty()
+        };
+
+        publish(&sink, Some(&range), &chart).await;
+        publish(&sink, None, &chart).await;
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["begin_at"], "2025-01-01T00:00:00+00:00");
+        assert_eq!(lines[0]["chart"]["total_cost"], 12.5);
+        assert!(lines[0]["timestamp"].is_string());
+        assert!(lines[1]["end_at"].is_null());
+
+        // A sink that cannot be written to only logs
+        let broken: Arc<dyn Sink> = Arc::new(FileSink::new(dir.path()));
+        publish(&broken, None, &chart).await;
+    }
+}